@@ -0,0 +1,16 @@
+-- Add migration script here
+-- A publication is a tenant of the application:
+-- it owns its subscribers, sender identity, base url and email copy.
+CREATE TABLE publications (
+  id uuid NOT NULL,
+  PRIMARY KEY(id),
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  -- Requests carrying this Host header are routed to the publication.
+  host TEXT NULL UNIQUE,
+  base_url TEXT NULL,
+  sender_email TEXT NULL,
+  confirmation_subject TEXT NOT NULL DEFAULT 'Welcome',
+  confirmation_message TEXT NOT NULL DEFAULT 'Welcome to our newsletter!',
+  created_at timestamptz NOT NULL
+);
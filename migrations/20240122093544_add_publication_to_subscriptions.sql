@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Historical subscribers are moved into a `default` publication,
+-- and email addresses only need to be unique within a publication.
+BEGIN;
+    INSERT INTO publications (id, slug, name, created_at)
+        VALUES (gen_random_uuid(), 'default', 'Newsletter', now());
+    ALTER TABLE subscriptions
+        ADD COLUMN publication_id uuid NULL REFERENCES publications (id);
+    UPDATE subscriptions
+        SET publication_id = (SELECT id FROM publications WHERE slug = 'default');
+    ALTER TABLE subscriptions ALTER COLUMN publication_id SET NOT NULL;
+    ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+    ALTER TABLE subscriptions ADD UNIQUE (publication_id, email);
+COMMIT;
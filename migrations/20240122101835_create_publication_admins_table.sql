@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Admins authenticate with a bearer token,
+-- only the SHA-256 hash of the token is stored.
+CREATE TABLE publication_admins (
+  id uuid NOT NULL,
+  PRIMARY KEY(id),
+  publication_id uuid NOT NULL REFERENCES publications (id),
+  username TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  created_at timestamptz NOT NULL,
+  UNIQUE (publication_id, username)
+);
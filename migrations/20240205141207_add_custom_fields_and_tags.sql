@@ -0,0 +1,36 @@
+-- Add migration script here
+-- Publications describe the extra data they keep on subscribers,
+-- the values live in a JSONB column validated by the application.
+CREATE TABLE custom_field_definitions (
+  id uuid NOT NULL,
+  PRIMARY KEY(id),
+  publication_id uuid NOT NULL REFERENCES publications (id),
+  key TEXT NOT NULL,
+  label TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  options TEXT[] NOT NULL DEFAULT '{}',
+  required BOOLEAN NOT NULL DEFAULT false,
+  created_at timestamptz NOT NULL,
+  UNIQUE (publication_id, key)
+);
+
+ALTER TABLE subscriptions
+  ADD COLUMN custom_fields JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX subscriptions_custom_fields_idx
+  ON subscriptions USING GIN (custom_fields jsonb_path_ops);
+
+CREATE TABLE tags (
+  id uuid NOT NULL,
+  PRIMARY KEY(id),
+  publication_id uuid NOT NULL REFERENCES publications (id),
+  name TEXT NOT NULL,
+  created_at timestamptz NOT NULL,
+  UNIQUE (publication_id, name)
+);
+
+CREATE TABLE subscription_tags (
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  tag_id uuid NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+  PRIMARY KEY(subscriber_id, tag_id)
+);
+CREATE INDEX subscription_tags_tag_id_idx ON subscription_tags (tag_id);
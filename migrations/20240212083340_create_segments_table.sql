@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Saved segment expressions, re-evaluated every time they are used.
+CREATE TABLE segments (
+  id uuid NOT NULL,
+  PRIMARY KEY(id),
+  publication_id uuid NOT NULL REFERENCES publications (id),
+  name TEXT NOT NULL,
+  expression TEXT NOT NULL,
+  created_at timestamptz NOT NULL,
+  UNIQUE (publication_id, name)
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Every subscriber gets a token for the unsubscribe link in their emails,
+-- generated by the database so that every insert path gets one.
+BEGIN;
+    ALTER TABLE subscriptions
+        ADD COLUMN unsubscribe_token TEXT NULL;
+    UPDATE subscriptions
+        SET unsubscribe_token = replace(gen_random_uuid()::text, '-', '');
+    ALTER TABLE subscriptions
+        ALTER COLUMN unsubscribe_token SET NOT NULL,
+        ALTER COLUMN unsubscribe_token SET DEFAULT replace(gen_random_uuid()::text, '-', ''),
+        ADD UNIQUE (unsubscribe_token);
+COMMIT;
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- Issues are kept with the content they were sent with,
+-- and with their Markdown source when they were written in Markdown.
+CREATE TABLE newsletter_issues (
+  id uuid NOT NULL,
+  PRIMARY KEY(id),
+  publication_id uuid NOT NULL REFERENCES publications (id),
+  title TEXT NOT NULL,
+  markdown_content TEXT NULL,
+  html_content TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  segment TEXT NULL,
+  published_at timestamptz NOT NULL
+);
+CREATE INDEX newsletter_issues_publication_id_idx ON newsletter_issues (publication_id, published_at);
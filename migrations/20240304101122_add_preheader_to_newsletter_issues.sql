@@ -0,0 +1,3 @@
+-- Add migration script here
+-- The preview text shown next to the subject in most inboxes.
+ALTER TABLE newsletter_issues ADD COLUMN preheader TEXT NULL;
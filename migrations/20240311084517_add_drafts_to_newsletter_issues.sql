@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Issues can be saved as drafts and published later.
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+    ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NULL;
+    ALTER TABLE newsletter_issues ADD COLUMN updated_at timestamptz NULL;
+    UPDATE newsletter_issues
+        SET status = 'published', created_at = published_at, updated_at = published_at;
+    ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+    ALTER TABLE newsletter_issues ALTER COLUMN created_at SET NOT NULL;
+    ALTER TABLE newsletter_issues ALTER COLUMN updated_at SET NOT NULL;
+    ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+COMMIT;
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Issues can be scheduled, sending goes through a queue worked off in the background.
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_at timestamptz NULL;
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_at)
+    WHERE status = 'scheduled';
+
+CREATE TABLE issue_delivery_queue (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_email TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Scheduled issues can go out at the same local time for every subscriber.
+ALTER TABLE subscriptions ADD COLUMN time_zone TEXT NULL;
+
+-- The wall-clock time an issue is delivered at in each subscriber's time zone,
+-- subscribers without one get it in the fallback time zone.
+ALTER TABLE newsletter_issues ADD COLUMN local_send_time timestamp NULL;
+ALTER TABLE newsletter_issues ADD COLUMN fallback_time_zone TEXT NULL;
+
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+CREATE INDEX issue_delivery_queue_execute_after_idx ON issue_delivery_queue (execute_after);
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Every email that went out, so editors know how far a delivery got
+-- when they pause or cancel it.
+CREATE TABLE issue_deliveries (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_email TEXT NOT NULL,
+    delivered_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Failed deliveries are retried a few times before they are given up on.
+ALTER TABLE issue_delivery_queue ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0;
+
+-- Deliveries that were given up on are kept next to the sent ones,
+-- `delivered_at` is when the worker was done with them either way.
+ALTER TABLE issue_deliveries ADD COLUMN outcome TEXT NOT NULL DEFAULT 'sent';
+ALTER TABLE issue_deliveries ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0;
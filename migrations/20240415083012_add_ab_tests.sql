@@ -0,0 +1,38 @@
+-- Add migration script here
+-- Issues can test variants on a slice of their audience before the winner
+-- goes out to everyone else.
+CREATE TABLE ab_tests (
+    newsletter_issue_id uuid PRIMARY KEY REFERENCES newsletter_issues (id),
+    -- Percentage of the audience the variants are tested on.
+    test_share SMALLINT NOT NULL,
+    metric TEXT NOT NULL,
+    window_minutes INTEGER NOT NULL,
+    -- Set when the variants go out to the test slice.
+    decides_at timestamptz NULL,
+    winning_variant SMALLINT NULL,
+    decided_at timestamptz NULL
+);
+CREATE INDEX ab_tests_due_idx ON ab_tests (decides_at) WHERE winning_variant IS NULL;
+
+-- Variants are stored as they are sent, with the fallbacks to the issue filled in.
+CREATE TABLE issue_variants (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    position SMALLINT NOT NULL,
+    title TEXT NOT NULL,
+    markdown_content TEXT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, position)
+);
+
+ALTER TABLE issue_delivery_queue ADD COLUMN variant SMALLINT NULL;
+ALTER TABLE issue_deliveries ADD COLUMN variant SMALLINT NULL;
+
+-- Opens and clicks of delivered issues, what A/B tests are decided on.
+CREATE TABLE delivery_events (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_email TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX delivery_events_issue_idx ON delivery_events (newsletter_issue_id, kind);
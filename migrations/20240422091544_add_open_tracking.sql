@@ -0,0 +1,7 @@
+-- Add migration script here
+-- Issues can track opens with a pixel unique to each delivery.
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE issue_deliveries ADD COLUMN tracking_token TEXT NULL UNIQUE;
+
+CREATE INDEX delivery_events_subscriber_idx
+    ON delivery_events (newsletter_issue_id, subscriber_email, kind, occurred_at);
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Issues can route their links through signed redirects, and tag them for analytics.
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ADD COLUMN utm_source TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN utm_medium TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN utm_campaign TEXT NULL;
+
+-- The link a click led to.
+ALTER TABLE delivery_events ADD COLUMN url TEXT NULL;
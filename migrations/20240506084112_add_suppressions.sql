@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Addresses that hard-bounced or complained about spam, across every publication.
+-- Emails are stored lowercased.
+CREATE TABLE suppressions(
+    email TEXT NOT NULL,
+    PRIMARY KEY (email),
+    reason TEXT NOT NULL,
+    suppressed_at timestamptz NOT NULL
+);
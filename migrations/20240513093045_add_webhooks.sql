@@ -0,0 +1,35 @@
+-- Add migration script here
+-- Endpoints of other systems, e.g. a CRM, told about subscriber lifecycle events.
+CREATE TABLE webhook_endpoints(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    publication_id uuid NOT NULL REFERENCES publications (id),
+    url TEXT NOT NULL,
+    -- Signs every payload sent to the endpoint.
+    secret TEXT NOT NULL,
+    event_types TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+-- Both the queue of events still to deliver and the log of the ones that were.
+CREATE TABLE webhook_deliveries(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    endpoint_id uuid NOT NULL REFERENCES webhook_endpoints (id) ON DELETE CASCADE,
+    event_id uuid NOT NULL,
+    event_type TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at timestamptz NOT NULL,
+    -- pending, delivered or failed.
+    status TEXT NOT NULL,
+    execute_after timestamptz NOT NULL,
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    last_attempt_at timestamptz NULL,
+    last_response_status SMALLINT NULL,
+    last_error TEXT NULL,
+    delivered_at timestamptz NULL
+);
+
+CREATE INDEX webhook_deliveries_pending_idx
+    ON webhook_deliveries (execute_after) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_endpoint_idx ON webhook_deliveries (endpoint_id, created_at);
@@ -0,0 +1,37 @@
+-- Add migration script here
+-- Named templates other services send one-off emails with, e.g. password resets.
+CREATE TABLE email_templates(
+    publication_id uuid NOT NULL REFERENCES publications (id),
+    name TEXT NOT NULL,
+    PRIMARY KEY (publication_id, name),
+    subject TEXT NOT NULL,
+    html TEXT NOT NULL,
+    text TEXT NOT NULL,
+    -- The merge tags callers fill in.
+    variables TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+
+-- Both the queue of transactional emails and their status, as reported to callers.
+-- Emails are rendered when they are requested, later template changes don't affect them.
+CREATE TABLE transactional_emails(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    publication_id uuid NOT NULL REFERENCES publications (id),
+    template_name TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html TEXT NOT NULL,
+    text TEXT NOT NULL,
+    -- queued, sent, failed or suppressed.
+    status TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    execute_after timestamptz NOT NULL,
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    last_error TEXT NULL,
+    sent_at timestamptz NULL
+);
+
+CREATE INDEX transactional_emails_queued_idx
+    ON transactional_emails (execute_after) WHERE status = 'queued';
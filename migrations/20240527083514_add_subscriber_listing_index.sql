@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Pages of the subscriber API are read in subscription order.
+CREATE INDEX subscriptions_listing_idx ON subscriptions (publication_id, subscribed_at, id);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Bounces and complaints suppress an address in every publication, whatever its case.
+CREATE INDEX subscriptions_lower_email_idx ON subscriptions (lower(email));
@@ -0,0 +1,468 @@
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde_json::Value;
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+
+use crate::domain::{
+    Condition, CustomFieldDefinition, CustomFieldKind, Operator, Segment, SubscriberEmail,
+    SubscriberTimeZone,
+};
+
+// Merge tags every email can use on top of the custom fields of the publication.
+pub const BUILTIN_MERGE_TAGS: [&str; 3] = ["name", "email", "unsubscribe_url"];
+
+// Keeps the interval well within the range of a timestamp.
+const MAX_OPENED_LAST_DAYS: u32 = 3650;
+
+pub fn available_merge_tags(definitions: &[CustomFieldDefinition]) -> Vec<&str> {
+    BUILTIN_MERGE_TAGS
+        .into_iter()
+        .chain(definitions.iter().map(|definition| definition.key.as_str()))
+        .collect()
+}
+
+// A member of the audience, with what is needed to personalise their email.
+#[derive(Clone)]
+pub struct Recipient {
+    pub email: SubscriberEmail,
+    pub name: String,
+    pub custom_fields: Value,
+    pub unsubscribe_token: String,
+}
+
+impl Recipient {
+    // `base_url` is the base url of the publication the email is sent for.
+    pub fn merge_value(&self, tag: &str, base_url: &str) -> Option<String> {
+        match tag {
+            "name" => Some(self.name.clone()),
+            "email" => Some(self.email.as_ref().to_string()),
+            "unsubscribe_url" => Some(format!(
+                "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+                base_url, self.unsubscribe_token
+            )),
+            key => self.custom_fields.get(key).map(display_value),
+        }
+    }
+}
+
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        // Numbers are stored as floats, `5` reads better than `5.0`.
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 && f.abs() < 1e15 => format!("{}", f as i64),
+            _ => n.to_string(),
+        },
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct RecipientRow {
+    email: String,
+    name: String,
+    custom_fields: Value,
+    unsubscribe_token: String,
+}
+
+impl TryFrom<RecipientRow> for Recipient {
+    type Error = anyhow::Error;
+
+    fn try_from(row: RecipientRow) -> Result<Self, Self::Error> {
+        let email = SubscriberEmail::parse(row.email).map_err(anyhow::Error::msg)?;
+        Ok(Recipient {
+            email,
+            name: row.name,
+            custom_fields: row.custom_fields,
+            unsubscribe_token: row.unsubscribe_token,
+        })
+    }
+}
+
+const RECIPIENT_COLUMNS: &str = "SELECT subscriptions.email, subscriptions.name, \
+    subscriptions.custom_fields, subscriptions.unsubscribe_token";
+
+// Issues sent in time zone waves go out at the same wall-clock time
+// for every subscriber. Subscribers without a time zone get the fallback.
+#[derive(Debug, Clone)]
+pub struct LocalSendTime {
+    pub local_time: NaiveDateTime,
+    pub fallback_time_zone: SubscriberTimeZone,
+}
+
+// The audience of a publication is its confirmed subscribers,
+// optionally narrowed down by a segment.
+pub struct Audience<'a> {
+    pub publication_id: Uuid,
+    pub segment: Option<&'a Segment>,
+    pub definitions: &'a [CustomFieldDefinition],
+}
+
+impl<'a> Audience<'a> {
+    // Every value coming from the segment expression is bound as a query parameter,
+    // the SQL text itself only ever contains fixed fragments.
+    fn query(&self, select: &str) -> Result<QueryBuilder<'static, Postgres>, String> {
+        self.push_query(QueryBuilder::new(select))
+    }
+
+    fn push_query(
+        &self,
+        mut builder: QueryBuilder<'static, Postgres>,
+    ) -> Result<QueryBuilder<'static, Postgres>, String> {
+        builder
+            .push(" FROM subscriptions WHERE subscriptions.status = 'confirmed'")
+            .push(" AND subscriptions.publication_id = ")
+            .push_bind(self.publication_id)
+            .push(
+                " AND NOT EXISTS (SELECT 1 FROM suppressions \
+                WHERE suppressions.email = lower(subscriptions.email))",
+            );
+
+        if let Some(segment) = self.segment {
+            builder.push(" AND (");
+            push_segment(&mut builder, segment, self.definitions)?;
+            builder.push(")");
+        }
+
+        Ok(builder)
+    }
+
+    // Checks that the segment only uses fields and values the publication knows about.
+    pub fn validate(&self) -> Result<(), String> {
+        self.query("SELECT 1").map(|_| ())
+    }
+
+    #[tracing::instrument(name = "Counting the audience", skip_all)]
+    pub async fn count(&self, connection_pool: &PgPool) -> Result<i64, anyhow::Error> {
+        let mut query = self.query("SELECT COUNT(*)").map_err(anyhow::Error::msg)?;
+        let count: i64 = query
+            .build_query_scalar()
+            .fetch_one(connection_pool)
+            .await
+            .context("Failed to count the audience.")?;
+
+        Ok(count)
+    }
+
+    // Queues one delivery of the issue per member of the audience,
+    // as it is at the time the issue goes out, who did not get it yet.
+    #[tracing::instrument(
+        name = "Enqueueing the delivery of an issue",
+        skip(self, transaction, local_send_time)
+    )]
+    pub async fn enqueue_delivery(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        issue_id: Uuid,
+        local_send_time: Option<&LocalSendTime>,
+        variant: Option<i16>,
+    ) -> Result<u64, anyhow::Error> {
+        let mut builder = QueryBuilder::new(
+            "INSERT INTO issue_delivery_queue \
+            (newsletter_issue_id, subscriber_email, variant, execute_after) SELECT ",
+        );
+        builder
+            .push_bind(issue_id)
+            .push(", subscriptions.email, ")
+            .push_bind(variant)
+            .push(", ");
+        match local_send_time {
+            // Each email waits until the local clock of its subscriber reaches the send time.
+            Some(local_send_time) => builder
+                .push("(")
+                .push_bind(local_send_time.local_time)
+                .push(" AT TIME ZONE COALESCE(subscriptions.time_zone, ")
+                .push_bind(local_send_time.fallback_time_zone.as_ref().to_string())
+                .push("))"),
+            None => builder.push("now()"),
+        };
+        let mut query = self.push_query(builder).map_err(anyhow::Error::msg)?;
+        query
+            .push(
+                " AND NOT EXISTS (SELECT 1 FROM issue_deliveries \
+                WHERE issue_deliveries.subscriber_email = subscriptions.email \
+                AND issue_deliveries.newsletter_issue_id = ",
+            )
+            .push_bind(issue_id)
+            .push(") ON CONFLICT DO NOTHING");
+        let result = query
+            .build()
+            .execute(&mut **transaction)
+            .await
+            .context("Failed to enqueue the delivery tasks.")?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Queues the delivery of the issue to a random `share` percent of the audience.
+    #[tracing::instrument(
+        name = "Enqueueing the test slice of an issue",
+        skip(self, transaction)
+    )]
+    pub async fn enqueue_test_slice(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        issue_id: Uuid,
+        share: i16,
+    ) -> Result<u64, anyhow::Error> {
+        let mut count = self.query("SELECT COUNT(*)").map_err(anyhow::Error::msg)?;
+        let count: i64 = count
+            .build_query_scalar()
+            .fetch_one(&mut **transaction)
+            .await
+            .context("Failed to count the audience.")?;
+        // Rounded up, small audiences still get a test.
+        let size = (count * i64::from(share) + 99) / 100;
+
+        let mut builder = QueryBuilder::new(
+            "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) SELECT ",
+        );
+        builder.push_bind(issue_id).push(", subscriptions.email");
+        let mut query = self.push_query(builder).map_err(anyhow::Error::msg)?;
+        query.push(" ORDER BY random() LIMIT ").push_bind(size);
+        let result = query
+            .build()
+            .execute(&mut **transaction)
+            .await
+            .context("Failed to enqueue the test slice.")?;
+
+        Ok(result.rows_affected())
+    }
+
+    // The longest-standing member of the audience, used to preview issues.
+    #[tracing::instrument(name = "Get a sample recipient", skip_all)]
+    pub async fn sample(
+        &self,
+        connection_pool: &PgPool,
+    ) -> Result<Option<Recipient>, anyhow::Error> {
+        let mut query = self.query(RECIPIENT_COLUMNS).map_err(anyhow::Error::msg)?;
+        query.push(" ORDER BY subscriptions.subscribed_at LIMIT 1");
+        let row: Option<RecipientRow> = query
+            .build_query_as()
+            .fetch_optional(connection_pool)
+            .await
+            .context("Failed to fetch a sample of the audience.")?;
+
+        row.map(Recipient::try_from).transpose()
+    }
+}
+
+fn push_segment(
+    builder: &mut QueryBuilder<'static, Postgres>,
+    segment: &Segment,
+    definitions: &[CustomFieldDefinition],
+) -> Result<(), String> {
+    match segment {
+        Segment::And(left, right) | Segment::Or(left, right) => {
+            let keyword = match segment {
+                Segment::And(_, _) => " AND ",
+                _ => " OR ",
+            };
+            builder.push("(");
+            push_segment(builder, left, definitions)?;
+            builder.push(keyword);
+            push_segment(builder, right, definitions)?;
+            builder.push(")");
+        }
+        Segment::Not(inner) => {
+            builder.push("NOT (");
+            push_segment(builder, inner, definitions)?;
+            builder.push(")");
+        }
+        Segment::Condition(condition) => push_condition(builder, condition, definitions)?,
+    }
+    Ok(())
+}
+
+fn push_condition(
+    builder: &mut QueryBuilder<'static, Postgres>,
+    condition: &Condition,
+    definitions: &[CustomFieldDefinition],
+) -> Result<(), String> {
+    match condition {
+        Condition::Tag(tag) => {
+            builder
+                .push(
+                    "EXISTS (SELECT 1 FROM subscription_tags \
+                    JOIN tags ON tags.id = subscription_tags.tag_id \
+                    WHERE subscription_tags.subscriber_id = subscriptions.id \
+                    AND tags.name = ",
+                )
+                .push_bind(tag.as_ref().to_string())
+                .push(")");
+        }
+        Condition::Compare {
+            field,
+            operator,
+            value,
+        } => match field.as_str() {
+            "email" | "name" => {
+                if !matches!(operator, Operator::Equal | Operator::NotEqual) {
+                    return Err(format!("{} can only be compared with = or !=.", field));
+                }
+                builder
+                    .push(format!("subscriptions.{} {} ", field, operator.as_sql()))
+                    .push_bind(value.clone());
+            }
+            "subscribed_at" => {
+                builder
+                    .push(format!(
+                        "subscriptions.subscribed_at {} ",
+                        operator.as_sql()
+                    ))
+                    .push_bind(parse_date(value)?);
+            }
+            key => {
+                let definition = find_definition(definitions, key)?;
+                let typed_value = definition.parse_value(value).map_err(|e| e.message)?;
+                match (operator, definition.kind) {
+                    (Operator::Equal, _) => push_contains(builder, key, typed_value),
+                    (Operator::NotEqual, _) => {
+                        builder.push("NOT ");
+                        push_contains(builder, key, typed_value);
+                    }
+                    (_, CustomFieldKind::Number) => {
+                        builder
+                            .push("(subscriptions.custom_fields ->> ")
+                            .push_bind(key.to_string())
+                            .push(format!(")::float8 {} ", operator.as_sql()))
+                            .push_bind(typed_value.as_f64());
+                    }
+                    (_, CustomFieldKind::Date) => {
+                        builder
+                            .push("(subscriptions.custom_fields ->> ")
+                            .push_bind(key.to_string())
+                            .push(format!(")::date {} ", operator.as_sql()))
+                            .push_bind(typed_value.as_str().map(String::from))
+                            .push("::date");
+                    }
+                    _ => {
+                        return Err(format!(
+                            "{} is a {} field, only number and date fields can be ordered.",
+                            key,
+                            definition.kind.as_str()
+                        ))
+                    }
+                }
+            }
+        },
+        Condition::In { field, values } => match field.as_str() {
+            "email" | "name" => {
+                builder
+                    .push(format!("subscriptions.{} = ANY(", field))
+                    .push_bind(values.clone())
+                    .push(")");
+            }
+            "subscribed_at" => {
+                return Err("subscribed_at can't be used with IN.".to_string());
+            }
+            key => {
+                let definition = find_definition(definitions, key)?;
+                builder.push("(");
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        builder.push(" OR ");
+                    }
+                    let typed_value = definition.parse_value(value).map_err(|e| e.message)?;
+                    push_contains(builder, key, typed_value);
+                }
+                builder.push(")");
+            }
+        },
+        // Only issues tracking opens count, prefetches by mail proxies don't.
+        Condition::OpenedLast { days } => {
+            if *days > MAX_OPENED_LAST_DAYS {
+                return Err(format!(
+                    "opened_last goes back at most {} days.",
+                    MAX_OPENED_LAST_DAYS
+                ));
+            }
+            builder
+                .push(
+                    "EXISTS (SELECT 1 FROM delivery_events \
+                    JOIN newsletter_issues \
+                    ON newsletter_issues.id = delivery_events.newsletter_issue_id \
+                    WHERE newsletter_issues.publication_id = subscriptions.publication_id \
+                    AND delivery_events.subscriber_email = subscriptions.email \
+                    AND delivery_events.kind = 'open' \
+                    AND delivery_events.occurred_at > now() - make_interval(days => ",
+                )
+                .push_bind(*days as i32)
+                .push("))");
+        }
+    }
+    Ok(())
+}
+
+// Equality is expressed as JSONB containment so it can use the GIN index.
+fn push_contains(builder: &mut QueryBuilder<'static, Postgres>, key: &str, value: Value) {
+    let mut object = serde_json::Map::new();
+    object.insert(key.to_string(), value);
+    builder
+        .push("subscriptions.custom_fields @> ")
+        .push_bind(Value::Object(object));
+}
+
+fn find_definition<'a>(
+    definitions: &'a [CustomFieldDefinition],
+    key: &str,
+) -> Result<&'a CustomFieldDefinition, String> {
+    definitions
+        .iter()
+        .find(|definition| definition.key == key)
+        .ok_or_else(|| format!("{} is not a field segments can filter on.", key))
+}
+
+fn parse_date(value: &str) -> Result<chrono::DateTime<Utc>, String> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("{} is not a date, use the YYYY-MM-DD format.", value))?;
+    Ok(Utc.from_utc_datetime(
+        &date
+            .and_hms_opt(0, 0, 0)
+            .expect("Midnight is a valid time."),
+    ))
+}
+
+// Only confirmed subscribers get issues, or previews of them,
+// unless their address was suppressed since.
+#[tracing::instrument(name = "Get a subscriber as a recipient", skip(connection_pool))]
+pub async fn get_recipient_by_email(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    email: &str,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        RecipientRow,
+        r#"
+    SELECT email, name, custom_fields, unsubscribe_token
+    FROM subscriptions
+    WHERE publication_id = $1 AND email = $2 AND status = 'confirmed'
+        AND NOT EXISTS (SELECT 1 FROM suppressions WHERE suppressions.email = lower($2))"#,
+        publication_id,
+        email
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the subscriber.")?;
+
+    row.map(Recipient::try_from).transpose()
+}
+
+#[tracing::instrument(name = "Get a saved segment", skip(connection_pool))]
+pub async fn get_saved_segment(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    name: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT expression FROM segments WHERE publication_id = $1 AND name = $2",
+        publication_id,
+        name
+    )
+    .fetch_optional(connection_pool)
+    .await?;
+
+    Ok(row.map(|r| r.expression))
+}
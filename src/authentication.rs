@@ -0,0 +1,142 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::{
+    dev::Payload,
+    http::header::{self, HeaderMap, HeaderValue},
+    web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    routes::error_chain_fmt,
+    tenant::{Publication, TenantError},
+};
+
+// An admin of the publication the request was routed to.
+// Handlers taking a `PublicationAdmin` argument are only reachable
+// with a valid `Authorization: Bearer <token>` header.
+#[derive(Debug)]
+pub struct PublicationAdmin {
+    pub id: Uuid,
+    pub username: String,
+    pub publication: Publication,
+}
+
+impl FromRequest for PublicationAdmin {
+    type Error = AuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let publication = Publication::from_request(req, payload);
+        let connection_pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let token = bearer_token(req.headers());
+
+        Box::pin(async move {
+            let publication = publication.await?;
+            let token = token.map_err(AuthError::InvalidCredentials)?;
+            let connection_pool =
+                connection_pool.context("The database pool is not registered on the app.")?;
+
+            validate_token(&connection_pool, publication, token).await
+        })
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Result<Secret<String>, anyhow::Error> {
+    let header_value = headers
+        .get(header::AUTHORIZATION)
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+
+    let token = header_value
+        .strip_prefix("Bearer ")
+        .context("The authorization scheme was not 'Bearer'.")?;
+
+    Ok(Secret::new(token.to_string()))
+}
+
+#[tracing::instrument(name = "Validate admin token", skip(connection_pool, token))]
+async fn validate_token(
+    connection_pool: &PgPool,
+    publication: Publication,
+    token: Secret<String>,
+) -> Result<PublicationAdmin, AuthError> {
+    let record = sqlx::query!(
+        r#"
+    SELECT id, username FROM publication_admins
+    WHERE token_hash = $1 AND publication_id = $2"#,
+        hash_token(&token),
+        publication.id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to perform a query to validate admin token.")?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown admin token.")))?;
+
+    Ok(PublicationAdmin {
+        id: record.id,
+        username: record.username,
+        publication,
+    })
+}
+
+// Tokens are long random strings, a plain SHA-256 digest is enough
+// to keep them out of the database in a usable form.
+pub fn hash_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+pub fn generate_admin_token() -> Secret<String> {
+    let mut rng = thread_rng();
+    Secret::new(
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(40)
+            .collect(),
+    )
+}
+
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    TenantError(#[from] TenantError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::InvalidCredentials(_) => StatusCode::UNAUTHORIZED,
+            AuthError::TenantError(e) => e.status_code(),
+            AuthError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::new(self.status_code());
+        if let AuthError::InvalidCredentials(_) = self {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Bearer realm="publish""#),
+            );
+        }
+        response
+    }
+}
@@ -0,0 +1,102 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+// A link of a delivery, routed through `/r/{token}`. The destination travels
+// in the token, the signature keeps anyone else from pointing it elsewhere.
+#[derive(Debug, PartialEq)]
+pub struct ClickToken {
+    pub tracking_token: String,
+    pub url: String,
+}
+
+impl ClickToken {
+    pub fn sign(&self, secret: &HmacSecret) -> String {
+        let payload = format!("{}\n{}", self.tracking_token, self.url);
+        let signature = mac(secret, payload.as_bytes()).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    // None for tokens that were not signed with the secret.
+    pub fn verify(token: &str, secret: &HmacSecret) -> Option<Self> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        mac(secret, &payload).verify_slice(&signature).ok()?;
+
+        let payload = String::from_utf8(payload).ok()?;
+        let (tracking_token, url) = payload.split_once('\n')?;
+        Some(Self {
+            tracking_token: tracking_token.to_string(),
+            url: url.to_string(),
+        })
+    }
+}
+
+fn mac(secret: &HmacSecret, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC takes keys of any size.");
+    mac.update(payload);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClickToken, HmacSecret};
+    use claims::{assert_none, assert_some_eq};
+    use secrecy::Secret;
+
+    fn secret(key: &str) -> HmacSecret {
+        HmacSecret(Secret::new(key.to_string()))
+    }
+
+    fn click() -> ClickToken {
+        ClickToken {
+            tracking_token: "abc123".to_string(),
+            url: "https://example.com/a?x=1&y=2".to_string(),
+        }
+    }
+
+    #[test]
+    fn signed_tokens_are_verified() {
+        let token = click().sign(&secret("key"));
+
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)));
+        assert_some_eq!(ClickToken::verify(&token, &secret("key")), click());
+    }
+
+    #[test]
+    fn tokens_signed_with_another_key_are_rejected() {
+        let token = click().sign(&secret("another key"));
+
+        assert_none!(ClickToken::verify(&token, &secret("key")));
+    }
+
+    #[test]
+    fn tampered_destinations_are_rejected() {
+        let token = click().sign(&secret("key"));
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = ClickToken {
+            tracking_token: "abc123".to_string(),
+            url: "https://evil.example.com".to_string(),
+        }
+        .sign(&secret("guess"));
+        let (payload, _) = forged.split_once('.').unwrap();
+
+        assert_none!(ClickToken::verify(
+            &format!("{}.{}", payload, signature),
+            &secret("key")
+        ));
+        assert_none!(ClickToken::verify("garbage", &secret("key")));
+    }
+}
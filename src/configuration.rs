@@ -7,7 +7,9 @@ use sqlx::{
 };
 use std::time::Duration;
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{SubscriberEmail, ValidationError};
+use crate::email_clients::EmailClient;
+use crate::outbound::DestinationPolicy;
 
 pub enum Environment {
     Local,
@@ -38,14 +40,16 @@ impl TryFrom<String> for Environment {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub link_checker: LinkCheckerSettings,
+    pub outbound: OutboundSettings,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ApplicationSettings {
     // Attribute macro telling serde to use the provided function
     // to deserialize this field.
@@ -53,9 +57,14 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    // Slug of the publication serving requests
+    // that match neither a path prefix nor a publication host.
+    pub default_publication: String,
+    // Signs the redirect links of tracked clicks.
+    pub hmac_secret: Secret<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DatabaseSettings {
     pub username: String,
     pub password: Secret<String>,
@@ -90,22 +99,55 @@ impl DatabaseSettings {
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
     pub auth_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    // Signs the bounce and complaint events the email provider sends us.
+    pub webhook_secret: Secret<String>,
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn sender(&self) -> Result<SubscriberEmail, ValidationError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
     pub fn timeout(&self) -> Duration {
         Duration::from_millis(self.timeout_milliseconds)
     }
+
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalide sender email.");
+        let timeout = self.timeout();
+        EmailClient::new(self.base_url, sender_email, self.auth_token, timeout)
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LinkCheckerSettings {
+    pub timeout_milliseconds: u64,
+    pub max_concurrency_per_host: usize,
+    // Links of an issue checked at most, the others are reported unchecked.
+    pub max_links: usize,
+}
+
+impl LinkCheckerSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct OutboundSettings {
+    pub allow_private_destinations: bool,
+}
+
+impl OutboundSettings {
+    pub fn policy(&self) -> DestinationPolicy {
+        DestinationPolicy::new(self.allow_private_destinations)
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use serde_json::{Map, Value};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::domain::{ValidationCode, ValidationError, ValidationErrors};
+
+// The types a publication can choose from
+// when it defines extra data to store on its subscribers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CustomFieldKind {
+    Text,
+    Number,
+    Date,
+    Enum,
+    Boolean,
+}
+
+impl CustomFieldKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CustomFieldKind::Text => "text",
+            CustomFieldKind::Number => "number",
+            CustomFieldKind::Date => "date",
+            CustomFieldKind::Enum => "enum",
+            CustomFieldKind::Boolean => "boolean",
+        }
+    }
+}
+
+impl TryFrom<String> for CustomFieldKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "text" => Ok(CustomFieldKind::Text),
+            "number" => Ok(CustomFieldKind::Number),
+            "date" => Ok(CustomFieldKind::Date),
+            "enum" => Ok(CustomFieldKind::Enum),
+            "boolean" => Ok(CustomFieldKind::Boolean),
+            other => Err(format!(
+                "{} is not a supported field type. \
+                Use one of 'text', 'number', 'date', 'enum' or 'boolean'.",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomFieldDefinition {
+    pub key: String,
+    pub label: String,
+    pub kind: CustomFieldKind,
+    // The allowed values of an enum field.
+    pub options: Vec<String>,
+    pub required: bool,
+}
+
+impl CustomFieldDefinition {
+    pub fn parse(
+        key: String,
+        label: String,
+        kind: CustomFieldKind,
+        options: Vec<String>,
+        required: bool,
+    ) -> Result<Self, String> {
+        // Keys end up in form field names, CSV headers and merge tags,
+        // so they are kept to a conservative character set.
+        let is_valid_key = !key.is_empty()
+            && key.len() <= 64
+            && key
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !is_valid_key || matches!(key.as_str(), "email" | "name" | "tags") {
+            return Err(format!("{} is not a valid custom field key.", key));
+        }
+
+        if label.trim().is_empty() || label.graphemes(true).count() > 256 {
+            return Err(format!("{} is not a valid custom field label.", label));
+        }
+
+        match kind {
+            CustomFieldKind::Enum if options.is_empty() => {
+                return Err(format!("Enum field {} needs at least one option.", key))
+            }
+            CustomFieldKind::Enum => {}
+            _ if !options.is_empty() => {
+                return Err(format!("Only enum fields can have options, {} can't.", key))
+            }
+            _ => {}
+        }
+
+        Ok(Self {
+            key,
+            label,
+            kind,
+            options,
+            required,
+        })
+    }
+
+    // Values arrive as strings from forms and CSV files,
+    // they are stored as typed JSON values.
+    pub fn parse_value(&self, raw: &str) -> Result<Value, ValidationError> {
+        let raw = raw.trim();
+        let invalid = || {
+            ValidationError::new(
+                ValidationCode::InvalidFormat,
+                format!("{} is not a valid value for {}.", raw, self.key),
+            )
+        };
+
+        match self.kind {
+            CustomFieldKind::Text => {
+                if raw.graphemes(true).count() > 256 {
+                    Err(ValidationError::new(
+                        ValidationCode::TooLong,
+                        format!("{} can't be longer than 256 characters.", self.key),
+                    ))
+                } else {
+                    Ok(Value::String(raw.to_string()))
+                }
+            }
+            CustomFieldKind::Number => raw
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(invalid),
+            CustomFieldKind::Date => NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                .map(|date| Value::String(date.format("%Y-%m-%d").to_string()))
+                .map_err(|_| invalid()),
+            CustomFieldKind::Enum => self
+                .options
+                .iter()
+                .find(|option| option.as_str() == raw)
+                .map(|option| Value::String(option.clone()))
+                .ok_or_else(invalid),
+            CustomFieldKind::Boolean => match raw.to_lowercase().as_str() {
+                // HTML checkboxes submit "on" when ticked.
+                "true" | "yes" | "on" | "1" => Ok(Value::Bool(true)),
+                "false" | "no" | "off" | "0" => Ok(Value::Bool(false)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+// Custom field values that passed validation
+// against the definitions of a publication.
+#[derive(Debug, Default)]
+pub struct CustomFields(Map<String, Value>);
+
+impl CustomFields {
+    // Every field is checked, the errors are keyed by the field they are about.
+    pub fn parse(
+        definitions: &[CustomFieldDefinition],
+        raw: HashMap<String, String>,
+    ) -> Result<Self, ValidationErrors> {
+        let mut fields = Map::new();
+        let mut errors = ValidationErrors::new();
+
+        for (key, value) in raw {
+            let Some(definition) = definitions.iter().find(|definition| definition.key == key)
+            else {
+                errors.add(
+                    &key,
+                    ValidationError::new(
+                        ValidationCode::UnknownField,
+                        format!("{} is not a known custom field.", key),
+                    ),
+                );
+                continue;
+            };
+
+            // Empty inputs are how forms say "no value".
+            if value.trim().is_empty() {
+                continue;
+            }
+            if let Some(value) = errors.check(&key, definition.parse_value(&value)) {
+                fields.insert(key, value);
+            }
+        }
+
+        for definition in definitions {
+            let is_missing = definition.required
+                && !fields.contains_key(&definition.key)
+                && errors.field(&definition.key).is_none();
+            if is_missing {
+                errors.add(
+                    &definition.key,
+                    ValidationError::new(
+                        ValidationCode::Empty,
+                        format!("{} is a required field.", definition.key),
+                    ),
+                );
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Self(fields))
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// JSON clients send numbers and booleans as they are,
+// they go through the same parsing as form values.
+pub fn raw_custom_fields(fields: HashMap<String, Value>) -> HashMap<String, String> {
+    fields
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::String(s) => (key, s),
+            Value::Null => (key, String::new()),
+            other => (key, other.to_string()),
+        })
+        .collect()
+}
+
+impl AsRef<Map<String, Value>> for CustomFields {
+    fn as_ref(&self) -> &Map<String, Value> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok};
+
+    fn definition(kind: CustomFieldKind) -> CustomFieldDefinition {
+        let options = match kind {
+            CustomFieldKind::Enum => vec!["free".to_string(), "pro".to_string()],
+            _ => vec![],
+        };
+        CustomFieldDefinition::parse("field".into(), "Field".into(), kind, options, false).unwrap()
+    }
+
+    #[test]
+    fn keys_with_uppercase_or_punctuation_are_rejected() {
+        for key in ["Company", "plan-tier", "", "email", "a b"] {
+            assert_err!(CustomFieldDefinition::parse(
+                key.into(),
+                "Label".into(),
+                CustomFieldKind::Text,
+                vec![],
+                false
+            ));
+        }
+    }
+
+    #[test]
+    fn enum_fields_need_options() {
+        assert_err!(CustomFieldDefinition::parse(
+            "plan".into(),
+            "Plan".into(),
+            CustomFieldKind::Enum,
+            vec![],
+            false
+        ));
+    }
+
+    #[test]
+    fn values_are_parsed_according_to_the_field_type() {
+        let cases = [
+            (CustomFieldKind::Text, "ACME Inc.", Value::from("ACME Inc.")),
+            (CustomFieldKind::Number, "42", Value::from(42.0)),
+            (
+                CustomFieldKind::Date,
+                "2025-01-31",
+                Value::from("2025-01-31"),
+            ),
+            (CustomFieldKind::Enum, "pro", Value::from("pro")),
+            (CustomFieldKind::Boolean, "on", Value::from(true)),
+        ];
+
+        for (kind, raw, expected) in cases {
+            assert_eq!(definition(kind).parse_value(raw).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn values_of_the_wrong_type_are_rejected() {
+        let cases = [
+            (CustomFieldKind::Number, "forty-two"),
+            (CustomFieldKind::Number, "NaN"),
+            (CustomFieldKind::Date, "31/01/2025"),
+            (CustomFieldKind::Enum, "enterprise"),
+            (CustomFieldKind::Boolean, "maybe"),
+        ];
+
+        for (kind, raw) in cases {
+            assert_err!(definition(kind).parse_value(raw));
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let raw = HashMap::from([("unknown".to_string(), "value".to_string())]);
+        assert_err!(CustomFields::parse(
+            &[definition(CustomFieldKind::Text)],
+            raw
+        ));
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported_with_its_code() {
+        let mut required = definition(CustomFieldKind::Text);
+        required.key = "company".into();
+        required.required = true;
+        let raw = HashMap::from([
+            ("field".to_string(), "forty-two".to_string()),
+            ("unknown".to_string(), "value".to_string()),
+        ]);
+
+        let errors =
+            CustomFields::parse(&[definition(CustomFieldKind::Number), required], raw).unwrap_err();
+
+        let code = |field| errors.field(field).map(|error| error.code);
+        assert_eq!(code("field"), Some(ValidationCode::InvalidFormat));
+        assert_eq!(code("unknown"), Some(ValidationCode::UnknownField));
+        assert_eq!(code("company"), Some(ValidationCode::Empty));
+    }
+
+    #[test]
+    fn missing_required_fields_are_rejected() {
+        let mut required = definition(CustomFieldKind::Text);
+        required.required = true;
+
+        assert_err!(CustomFields::parse(&[required.clone()], HashMap::new()));
+        assert_ok!(CustomFields::parse(
+            &[required],
+            HashMap::from([("field".to_string(), "value".to_string())])
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+// Email content with merge tags that are filled in for every recipient, e.g.
+//
+//   Hello {{ name | default: "friend" }}!
+//
+// Templates are parsed against the tags that will be available when sending,
+// so a typo is reported before any email goes out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeTemplate(Vec<Part>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Tag {
+        name: String,
+        default: Option<String>,
+    },
+}
+
+impl MergeTemplate {
+    pub fn parse(s: &str, available_tags: &[&str]) -> Result<MergeTemplate, String> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let after_open = &rest[start + 2..];
+            let end = after_open
+                .find("}}")
+                .ok_or_else(|| format!("A merge tag is not closed: {}", preview(&rest[start..])))?;
+            parts.push(parse_tag(&after_open[..end], available_tags)?);
+            rest = &after_open[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+
+        Ok(Self(parts))
+    }
+
+    // `lookup` returns the value of a tag for the current recipient,
+    // `None` or an empty value falls back on the tag default.
+    pub fn render<F>(&self, lookup: F, escape_html: bool) -> String
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut output = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(literal) => output.push_str(literal),
+                Part::Tag { name, default } => {
+                    let value = lookup(name)
+                        .filter(|value| !value.is_empty())
+                        .or_else(|| default.clone())
+                        .unwrap_or_default();
+                    if escape_html {
+                        output.push_str(&html_escape(&value));
+                    } else {
+                        output.push_str(&value);
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    // Tags without a default, left empty when no value is given.
+    pub fn required_tags(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter_map(|part| match part {
+            Part::Tag {
+                name,
+                default: None,
+            } => Some(name.as_str()),
+            _ => None,
+        })
+    }
+}
+
+fn parse_tag(inner: &str, available_tags: &[&str]) -> Result<Part, String> {
+    let (name, filter) = match inner.split_once('|') {
+        Some((name, filter)) => (name.trim(), Some(filter.trim())),
+        None => (inner.trim(), None),
+    };
+
+    if !available_tags.contains(&name) {
+        return Err(format!(
+            "{{{{ {} }}}} is not a known merge tag. Available tags are: {}.",
+            name,
+            available_tags.join(", ")
+        ));
+    }
+
+    let default = match filter {
+        None => None,
+        Some(filter) => {
+            let argument = filter
+                .strip_prefix("default")
+                .map(str::trim_start)
+                .and_then(|filter| filter.strip_prefix(':'))
+                .map(str::trim)
+                .ok_or_else(|| {
+                    format!(
+                        "{} is not a known filter, only 'default' is supported.",
+                        filter
+                    )
+                })?;
+            Some(parse_quoted(argument).ok_or_else(|| {
+                format!(
+                    "The default of {{{{ {} }}}} has to be a quoted string.",
+                    name
+                )
+            })?)
+        }
+    };
+
+    Ok(Part::Tag {
+        name: name.to_string(),
+        default,
+    })
+}
+
+fn parse_quoted(s: &str) -> Option<String> {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| s.strip_prefix(*quote)?.strip_suffix(*quote))
+        .map(String::from)
+}
+
+fn preview(s: &str) -> String {
+    s.chars().take(30).collect()
+}
+
+pub fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok};
+
+    const TAGS: &[&str] = &["name", "email", "company"];
+
+    fn lookup(tag: &str) -> Option<String> {
+        match tag {
+            "name" => Some("Ursula".to_string()),
+            "company" => Some("Tom & Jerry".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tags_are_replaced_with_the_recipient_values() {
+        let template = MergeTemplate::parse("Hello {{ name }}, from {{company}}!", TAGS).unwrap();
+        assert_eq!(
+            template.render(lookup, false),
+            "Hello Ursula, from Tom & Jerry!"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let template = MergeTemplate::parse("<p>{{ company }}</p>", TAGS).unwrap();
+        assert_eq!(template.render(lookup, true), "<p>Tom &amp; Jerry</p>");
+    }
+
+    #[test]
+    fn missing_values_fall_back_on_the_default() {
+        let template =
+            MergeTemplate::parse(r#"Dear {{ email | default: "friend" }}"#, TAGS).unwrap();
+        assert_eq!(template.render(lookup, false), "Dear friend");
+
+        let template = MergeTemplate::parse("Dear {{ email|default:'you' }}", TAGS).unwrap();
+        assert_eq!(template.render(lookup, false), "Dear you");
+    }
+
+    #[test]
+    fn missing_values_without_a_default_render_empty() {
+        let template = MergeTemplate::parse("[{{ email }}]", TAGS).unwrap();
+        assert_eq!(template.render(lookup, false), "[]");
+    }
+
+    #[test]
+    fn text_without_tags_is_left_untouched() {
+        let text = "Nothing to see { here }";
+        let template = MergeTemplate::parse(text, TAGS).unwrap();
+        assert_eq!(template.render(lookup, true), text);
+    }
+
+    #[test]
+    fn unknown_tags_are_rejected() {
+        assert_err!(MergeTemplate::parse("Hello {{ nmae }}", TAGS));
+    }
+
+    #[test]
+    fn unclosed_tags_are_rejected() {
+        assert_err!(MergeTemplate::parse("Hello {{ name", TAGS));
+    }
+
+    #[test]
+    fn tags_with_a_default_are_not_required() {
+        let template =
+            MergeTemplate::parse(r#"{{ name }} at {{ company | default: "home" }}"#, TAGS).unwrap();
+        assert_eq!(template.required_tags().collect::<Vec<_>>(), ["name"]);
+    }
+
+    #[test]
+    fn unknown_filters_and_unquoted_defaults_are_rejected() {
+        assert_err!(MergeTemplate::parse("{{ name | upcase }}", TAGS));
+        assert_err!(MergeTemplate::parse("{{ name | default: friend }}", TAGS));
+        assert_ok!(MergeTemplate::parse(r#"{{ name | default: "" }}"#, TAGS));
+    }
+}
@@ -1,9 +1,19 @@
+mod custom_field;
+mod merge_template;
 mod new_subscriber;
-mod subscriber_email;
-mod subscriber_name;
+mod segment;
+mod subscriber_time_zone;
+mod tag_name;
+mod validation_errors;
 
 // expose chosen features on a sub-crate level
+pub use custom_field::{raw_custom_fields, CustomFieldDefinition, CustomFieldKind, CustomFields};
+pub use merge_template::{html_escape, MergeTemplate};
 pub use new_subscriber::FormDataSubscriber;
 pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
-pub use subscriber_name::SubscriberName;
+pub use segment::{Condition, Operator, Segment};
+pub use subscriber_time_zone::SubscriberTimeZone;
+pub use tag_name::TagName;
+pub use validation_errors::ValidationErrors;
+// Shared with the client crate, so callers validate exactly like the service does.
+pub use zero2prod_client::{SubscriberEmail, SubscriberName, ValidationCode, ValidationError};
@@ -1,23 +1,84 @@
-use crate::domain::subscriber_email::SubscriberEmail;
-use crate::domain::subscriber_name::SubscriberName;
+use std::collections::HashMap;
 
-#[derive(serde::Deserialize)]
+use crate::domain::subscriber_time_zone::SubscriberTimeZone;
+use crate::domain::{
+    SubscriberEmail, SubscriberName, ValidationCode, ValidationError, ValidationErrors,
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct FormDataSubscriber {
     pub email: String,
     pub name: String,
+    // Usually filled in by the form from the time zone the browser reports.
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    // Every other form field is a custom field of the publication,
+    // they are validated against its definitions once it is known.
+    #[serde(flatten)]
+    pub custom_fields: HashMap<String, String>,
 }
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub time_zone: Option<SubscriberTimeZone>,
 }
 
+// Every field is parsed, the error holds all of those that were not valid.
 impl TryFrom<FormDataSubscriber> for NewSubscriber {
-    type Error = String;
+    type Error = ValidationErrors;
 
     fn try_from(form: FormDataSubscriber) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(form.name)?;
-        let email = SubscriberEmail::parse(form.email)?;
-        Ok(Self { name, email })
+        let mut errors = ValidationErrors::new();
+        let name = errors.check("name", SubscriberName::parse(form.name));
+        let email = errors.check("email", SubscriberEmail::parse(form.email));
+        let time_zone = errors.check(
+            "time_zone",
+            form.time_zone
+                .filter(|time_zone| !time_zone.trim().is_empty())
+                .map(SubscriberTimeZone::parse)
+                .transpose()
+                .map_err(|e| ValidationError::new(ValidationCode::InvalidFormat, e)),
+        );
+        match (name, email, time_zone) {
+            (Some(name), Some(email), Some(time_zone)) => Ok(Self {
+                name,
+                email,
+                time_zone,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(name: &str, email: &str, time_zone: Option<&str>) -> FormDataSubscriber {
+        FormDataSubscriber {
+            name: name.into(),
+            email: email.into(),
+            time_zone: time_zone.map(Into::into),
+            custom_fields: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn a_valid_form_is_accepted() {
+        let form = form("le guin", "ursula_le_guin@gmail.com", Some("Europe/Berlin"));
+        assert!(NewSubscriber::try_from(form).is_ok());
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let form = form("le guin <3", "not-an-email", Some("Mars/Olympus_Mons"));
+
+        let errors = NewSubscriber::try_from(form).err().unwrap();
+
+        let code = |field| errors.field(field).map(|error| error.code);
+        assert_eq!(code("name"), Some(ValidationCode::ForbiddenCharacter));
+        assert_eq!(code("email"), Some(ValidationCode::InvalidFormat));
+        assert_eq!(code("time_zone"), Some(ValidationCode::InvalidFormat));
     }
 }
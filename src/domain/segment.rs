@@ -0,0 +1,433 @@
+use crate::domain::TagName;
+
+// A segment expression picks part of the audience of a publication, e.g.
+//
+//   tag:rust AND country IN (DE, FR) AND subscribed_at > 2025-01-01
+//
+// Conditions can be combined with AND, OR, NOT and parentheses.
+// Parsing only checks the shape of the expression, field names and
+// values are checked against the publication when the query is built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+    Not(Box<Segment>),
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Tag(TagName),
+    Compare {
+        field: String,
+        operator: Operator,
+        value: String,
+    },
+    In {
+        field: String,
+        values: Vec<String>,
+    },
+    // Subscribers who opened an issue within the last `days` days.
+    OpenedLast {
+        days: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Operator {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "<>",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+        }
+    }
+}
+
+// Expressions are typed in by editors, these limits keep
+// a runaway expression from turning into a runaway query.
+const MAX_EXPRESSION_LENGTH: usize = 2000;
+const MAX_NESTING_DEPTH: usize = 32;
+
+impl Segment {
+    pub fn parse(s: &str) -> Result<Segment, String> {
+        if s.len() > MAX_EXPRESSION_LENGTH {
+            return Err(format!(
+                "Segment expressions can't be longer than {} characters.",
+                MAX_EXPRESSION_LENGTH
+            ));
+        }
+
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            depth: 0,
+        };
+        let segment = parser.parse_or()?;
+
+        match parser.peek() {
+            None => Ok(segment),
+            Some(token) => Err(format!("Unexpected {} in segment expression.", token)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    Comma,
+    Operator(Operator),
+    // Bare words: keywords, field names, numbers, dates...
+    Word(String),
+    // Double quoted values, which may contain spaces.
+    Quoted(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::LeftParen => write!(f, "'('"),
+            Token::RightParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::Operator(operator) => write!(f, "'{}'", operator.as_sql()),
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Quoted(value) => write!(f, "\"{}\"", value),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LeftParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RightParen);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Operator(Operator::Equal));
+            }
+            '!' | '<' | '>' => {
+                chars.next();
+                let followed_by_equal = chars.next_if_eq(&'=').is_some();
+                let operator = match (c, followed_by_equal) {
+                    ('!', true) => Operator::NotEqual,
+                    ('<', true) => Operator::LessOrEqual,
+                    ('>', true) => Operator::GreaterOrEqual,
+                    ('<', false) => Operator::Less,
+                    ('>', false) => Operator::Greater,
+                    _ => return Err("'!' has to be followed by '='.".to_string()),
+                };
+                tokens.push(Token::Operator(operator));
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => return Err("Unterminated quoted value.".to_string()),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err("Unterminated quoted value.".to_string()),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "(),=!<>\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("Expected {} but found {}.", expected, token)),
+            None => Err(format!("Expected {} but the expression ended.", expected)),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_and()?;
+        while self.next_is_keyword("OR") {
+            self.next();
+            segment = Segment::Or(Box::new(segment), Box::new(self.parse_and()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_unary()?;
+        while self.next_is_keyword("AND") {
+            self.next();
+            segment = Segment::And(Box::new(segment), Box::new(self.parse_unary()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_unary(&mut self) -> Result<Segment, String> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING_DEPTH {
+            return Err("The segment expression is nested too deeply.".to_string());
+        }
+
+        let segment = if self.next_is_keyword("NOT") {
+            self.next();
+            Segment::Not(Box::new(self.parse_unary()?))
+        } else if self.peek() == Some(&Token::LeftParen) {
+            self.next();
+            let segment = self.parse_or()?;
+            self.expect(Token::RightParen)?;
+            segment
+        } else {
+            Segment::Condition(self.parse_condition()?)
+        };
+
+        self.depth -= 1;
+        Ok(segment)
+    }
+
+    fn parse_condition(&mut self) -> Result<Condition, String> {
+        let word = match self.next() {
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(format!("Expected a condition but found {}.", token)),
+            None => return Err("Expected a condition but the expression ended.".to_string()),
+        };
+
+        if let Some(tag) = word.strip_prefix("tag:") {
+            return Ok(Condition::Tag(TagName::parse(tag.to_string())?));
+        }
+
+        if word.eq_ignore_ascii_case("opened_last") {
+            self.expect(Token::LeftParen)?;
+            let days = match self.next() {
+                Some(Token::Word(period)) => parse_days(period)?,
+                _ => return Err("opened_last needs a period such as 90d.".to_string()),
+            };
+            self.expect(Token::RightParen)?;
+            return Ok(Condition::OpenedLast { days });
+        }
+
+        let field = word.to_lowercase();
+        if self.next_is_keyword("IN") {
+            self.next();
+            self.expect(Token::LeftParen)?;
+            let mut values = vec![self.parse_value()?];
+            while self.peek() == Some(&Token::Comma) {
+                self.next();
+                values.push(self.parse_value()?);
+            }
+            self.expect(Token::RightParen)?;
+            return Ok(Condition::In { field, values });
+        }
+
+        match self.next() {
+            Some(Token::Operator(operator)) => Ok(Condition::Compare {
+                field,
+                operator: *operator,
+                value: self.parse_value()?,
+            }),
+            _ => Err(format!("Expected a comparison or IN after {}.", field)),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word.clone()),
+            Some(Token::Quoted(value)) => Ok(value.clone()),
+            Some(token) => Err(format!("Expected a value but found {}.", token)),
+            None => Err("Expected a value but the expression ended.".to_string()),
+        }
+    }
+}
+
+// Periods are written as a number of days, e.g. `90d`.
+fn parse_days(period: &str) -> Result<u32, String> {
+    period
+        .strip_suffix('d')
+        .and_then(|days| days.parse::<u32>().ok())
+        .filter(|days| *days > 0)
+        .ok_or_else(|| {
+            format!(
+                "{} is not a valid period, use a number of days like 90d.",
+                period
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok};
+
+    fn tag(name: &str) -> Segment {
+        Segment::Condition(Condition::Tag(TagName::parse(name.to_string()).unwrap()))
+    }
+
+    #[test]
+    fn the_documented_example_is_parsed() {
+        let segment = Segment::parse(
+            "tag:rust AND country IN (DE, FR) AND subscribed_at > 2025-01-01 AND NOT opened_last(90d)",
+        )
+        .unwrap();
+
+        let expected = Segment::And(
+            Box::new(Segment::And(
+                Box::new(Segment::And(
+                    Box::new(tag("rust")),
+                    Box::new(Segment::Condition(Condition::In {
+                        field: "country".into(),
+                        values: vec!["DE".into(), "FR".into()],
+                    })),
+                )),
+                Box::new(Segment::Condition(Condition::Compare {
+                    field: "subscribed_at".into(),
+                    operator: Operator::Greater,
+                    value: "2025-01-01".into(),
+                })),
+            )),
+            Box::new(Segment::Not(Box::new(Segment::Condition(
+                Condition::OpenedLast { days: 90 },
+            )))),
+        );
+        assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let segment = Segment::parse("tag:a OR tag:b AND tag:c").unwrap();
+
+        let expected = Segment::Or(
+            Box::new(tag("a")),
+            Box::new(Segment::And(Box::new(tag("b")), Box::new(tag("c")))),
+        );
+        assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let segment = Segment::parse("(tag:a OR tag:b) and tag:c").unwrap();
+
+        let expected = Segment::And(
+            Box::new(Segment::Or(Box::new(tag("a")), Box::new(tag("b")))),
+            Box::new(tag("c")),
+        );
+        assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn quoted_values_can_contain_spaces_and_keywords() {
+        let segment = Segment::parse(r#"company = "Rust AND Friends""#).unwrap();
+
+        let expected = Segment::Condition(Condition::Compare {
+            field: "company".into(),
+            operator: Operator::Equal,
+            value: "Rust AND Friends".into(),
+        });
+        assert_eq!(segment, expected);
+    }
+
+    #[test]
+    fn all_comparison_operators_are_supported() {
+        for operator in ["=", "!=", ">", ">=", "<", "<="] {
+            assert_ok!(Segment::parse(&format!("seats {} 5", operator)));
+        }
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        let cases = [
+            "",
+            "tag:rust AND",
+            "(tag:rust",
+            "tag:rust)",
+            "country IN (DE,",
+            "country IN DE",
+            "seats 5",
+            "seats ! 5",
+            "tag:",
+            r#"company = "unterminated"#,
+            "opened_last(ninety)",
+            "opened_last(0d)",
+        ];
+
+        for case in cases {
+            assert_err!(Segment::parse(case), "{} should be rejected", case);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let expression = format!("{}tag:rust{}", "(".repeat(100), ")".repeat(100));
+        assert_err!(Segment::parse(&expression));
+    }
+
+    #[test]
+    fn overly_long_expressions_are_rejected() {
+        let expression = vec!["tag:rust"; 500].join(" OR ");
+        assert_err!(Segment::parse(&expression));
+    }
+}
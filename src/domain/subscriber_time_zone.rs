@@ -0,0 +1,46 @@
+use chrono_tz::Tz;
+
+// An IANA time zone, e.g. `Europe/Berlin`, used to deliver
+// scheduled issues at the same local time for every subscriber.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubscriberTimeZone(Tz);
+
+impl SubscriberTimeZone {
+    pub fn parse(s: String) -> Result<SubscriberTimeZone, String> {
+        s.trim()
+            .parse::<Tz>()
+            .map(Self)
+            .map_err(|_| format!("{} is not a known time zone.", s))
+    }
+}
+
+impl AsRef<str> for SubscriberTimeZone {
+    fn as_ref(&self) -> &str {
+        self.0.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_err;
+
+    #[test]
+    fn iana_time_zones_are_accepted() {
+        for name in ["Europe/Berlin", "America/Argentina/Buenos_Aires", "UTC"] {
+            assert_eq!(
+                SubscriberTimeZone::parse(name.to_string())
+                    .unwrap()
+                    .as_ref(),
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_time_zones_and_offsets_are_rejected() {
+        for name in ["", "Europe/Atlantis", "+02:00", "CEST"] {
+            assert_err!(SubscriberTimeZone::parse(name.to_string()));
+        }
+    }
+}
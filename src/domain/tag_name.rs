@@ -0,0 +1,54 @@
+// Tags are matched by name in segments and imports,
+// so they are normalised to lowercase on the way in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagName(String);
+
+impl TagName {
+    pub fn parse(s: String) -> Result<TagName, String> {
+        let name = s.trim().to_lowercase();
+
+        let is_valid = !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+
+        if is_valid {
+            Ok(Self(name))
+        } else {
+            Err(format!("{} is not a valid tag name.", s))
+        }
+    }
+}
+
+impl AsRef<str> for TagName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_err;
+
+    #[test]
+    fn tag_names_are_lowercased() {
+        assert_eq!(
+            TagName::parse(" Rust ".to_string()).unwrap().as_ref(),
+            "rust"
+        );
+    }
+
+    #[test]
+    fn empty_tag_names_are_rejected() {
+        assert_err!(TagName::parse(" ".to_string()));
+    }
+
+    #[test]
+    fn tag_names_with_spaces_or_punctuation_are_rejected() {
+        for name in ["rust lang", "rust,go", "c++", "a:b"] {
+            assert_err!(TagName::parse(name.to_string()));
+        }
+    }
+}
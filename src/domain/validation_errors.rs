@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+
+use zero2prod_client::ValidationError;
+
+// What is wrong with a request, by the name of the field it is about.
+// Every field is checked, so a form is corrected in a single round trip.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(BTreeMap<String, ValidationError>);
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn single(field: &str, error: ValidationError) -> Self {
+        let mut errors = Self::new();
+        errors.add(field, error);
+        errors
+    }
+
+    pub fn add(&mut self, field: &str, error: ValidationError) {
+        self.0.insert(field.into(), error);
+    }
+
+    // Keeps the value if it is valid, records why under `field` otherwise.
+    pub fn check<T>(&mut self, field: &str, outcome: Result<T, ValidationError>) -> Option<T> {
+        outcome.map_err(|error| self.add(field, error)).ok()
+    }
+
+    // Keeps the value if it is valid, takes over its errors otherwise.
+    pub fn merge<T>(&mut self, outcome: Result<T, ValidationErrors>) -> Option<T> {
+        outcome.map_err(|errors| self.0.extend(errors.0)).ok()
+    }
+
+    pub fn field(&self, field: &str) -> Option<&ValidationError> {
+        self.0.get(field)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<&str> = self
+            .0
+            .values()
+            .map(|error| error.message.as_str())
+            .collect();
+        write!(f, "{}", messages.join(" "))
+    }
+}
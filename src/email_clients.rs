@@ -29,9 +29,23 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.send_email_from(None, recipient, subject, html_content, text_content)
+            .await
+    }
+
+    // Publications can bring their own sender identity,
+    // the configured sender is used when they don't.
+    pub async fn send_email_from(
+        &self,
+        sender: Option<&SubscriberEmail>,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
     ) -> Result<(), reqwest::Error> {
         let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
+            from: sender.unwrap_or(&self.sender).as_ref(),
             to: recipient.as_ref(),
             subject,
             html_body: html_content,
@@ -164,6 +178,34 @@ mod tests {
         assert_ok!(outcome);
     }
 
+    #[tokio::test]
+    async fn send_email_from_uses_the_given_sender() {
+        let mock_server = mock_server().await;
+        let email_client = email_client(mock_server.uri());
+        let publication_sender = sender();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_from(
+                Some(&publication_sender),
+                subscriber(),
+                &subject(),
+                &content(),
+                &content(),
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["From"], publication_sender.as_ref());
+    }
+
     #[tokio::test]
     async fn send_email_times_out_if_the_server_takes_too_long() {
         let mock_server = mock_server().await;
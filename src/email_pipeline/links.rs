@@ -0,0 +1,51 @@
+use std::collections::BTreeSet;
+
+use linkify::{LinkFinder, LinkKind};
+
+use super::MergeTags;
+
+// The http(s) links of an email part. Links built from merge tags
+// depend on the recipient and can't be checked ahead of sending.
+pub fn extract_links(content: &str) -> BTreeSet<String> {
+    let mut merge_tags = MergeTags::default();
+    let content = merge_tags.protect(content);
+
+    let mut finder = LinkFinder::new();
+    finder.kinds(&[LinkKind::Url]);
+    finder
+        .links(&content)
+        .map(|link| link.as_str())
+        .filter(|link| !merge_tags.is_placeholder_in(link))
+        // HTML attributes escape `&`, the plain-text part doesn't.
+        .map(|link| link.replace("&amp;", "&"))
+        .filter(|link| link.starts_with("http://") || link.starts_with("https://"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_found_in_html_and_text() {
+        let html =
+            r#"<p><a href="https://example.com/a?x=1&amp;y=2">A</a> or https://example.com/b.</p>"#;
+        let text = "A [1] or https://example.com/b.\n\n[1] https://example.com/a?x=1&y=2";
+
+        let expected = BTreeSet::from([
+            "https://example.com/a?x=1&y=2".to_string(),
+            "https://example.com/b".to_string(),
+        ]);
+        assert_eq!(extract_links(html), expected);
+        assert_eq!(extract_links(text), expected);
+    }
+
+    #[test]
+    fn links_with_merge_tags_and_other_schemes_are_skipped() {
+        let html = r#"<a href="{{ unsubscribe_url }}">Unsubscribe</a>
+            <a href="https://example.com/{{ name }}">Profile</a>
+            <a href="mailto:editor@example.com">Write us</a> ftp://example.com"#;
+
+        assert!(extract_links(html).is_empty());
+    }
+}
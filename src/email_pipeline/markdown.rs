@@ -0,0 +1,274 @@
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use unicode_segmentation::UnicodeSegmentation;
+
+fn parser(markdown: &str) -> Parser<'_> {
+    Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    )
+}
+
+pub fn markdown_to_html(markdown: &str) -> String {
+    let mut output = String::new();
+    html::push_html(&mut output, parser(markdown));
+    output
+}
+
+// Plain text meant to be read as-is in a mail client:
+// headings are underlined and links are moved to numbered footnotes.
+pub fn markdown_to_text(markdown: &str) -> String {
+    let mut renderer = TextRenderer::default();
+    for event in parser(markdown) {
+        renderer.handle(event);
+    }
+    renderer.finish()
+}
+
+// What every line of a nested block starts with, e.g. `> ` in a quote.
+// List items use their marker on the first line and align the following ones.
+struct Prefix {
+    first: Option<String>,
+    rest: String,
+}
+
+#[derive(Default)]
+struct TextRenderer {
+    output: String,
+    at_line_start: bool,
+    prefixes: Vec<Prefix>,
+    // The next number of every open list, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    // Open links and headings, with where their text starts in the output.
+    links: Vec<(String, usize)>,
+    heading: Option<(HeadingLevel, usize)>,
+    footnotes: Vec<String>,
+    first_cell: bool,
+}
+
+impl TextRenderer {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Code(text) => self.push_text(&text),
+            Event::SoftBreak | Event::HardBreak => self.newline(),
+            Event::Rule => {
+                self.start_block();
+                self.push_text("----------");
+                self.newline();
+            }
+            // Raw HTML has no place in the plain-text part.
+            Event::Html(_) | Event::InlineHtml(_) => {}
+            Event::FootnoteReference(_) | Event::TaskListMarker(_) => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::Table(_) => self.start_block(),
+            Tag::Heading { level, .. } => {
+                self.start_block();
+                self.heading = Some((level, self.output.len()));
+            }
+            Tag::BlockQuote => {
+                self.start_block();
+                self.prefixes.push(Prefix {
+                    first: None,
+                    rest: "> ".to_string(),
+                });
+            }
+            Tag::CodeBlock(_) => {
+                self.start_block();
+                self.prefixes.push(Prefix {
+                    first: None,
+                    rest: "    ".to_string(),
+                });
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                } else {
+                    self.end_line();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.end_line();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.prefixes.push(Prefix {
+                    rest: " ".repeat(marker.len()),
+                    first: Some(marker),
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                self.end_line();
+                self.first_cell = true;
+            }
+            Tag::TableCell => {
+                if !self.first_cell {
+                    self.push_text(" | ");
+                }
+                self.first_cell = false;
+            }
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.links.push((dest_url.to_string(), self.output.len()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::Table => self.end_line(),
+            TagEnd::Heading(level) => {
+                if let Some((_, start)) = self.heading.take() {
+                    let width = self.output[start..].graphemes(true).count();
+                    let underline = match level {
+                        HeadingLevel::H1 => "=",
+                        HeadingLevel::H2 => "-",
+                        _ => "",
+                    };
+                    if !underline.is_empty() {
+                        self.newline();
+                        self.push_text(&underline.repeat(width));
+                    }
+                }
+                self.end_line();
+            }
+            TagEnd::BlockQuote | TagEnd::CodeBlock => {
+                self.prefixes.pop();
+                self.end_line();
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                self.end_line();
+            }
+            TagEnd::Item => {
+                self.prefixes.pop();
+                self.end_line();
+            }
+            TagEnd::Link | TagEnd::Image => {
+                if let Some((url, start)) = self.links.pop() {
+                    let text = self.output[start..].trim();
+                    // Bare links already show where they go.
+                    if text != url && Some(text) != url.strip_prefix("mailto:") {
+                        let number = match self.footnotes.iter().position(|f| *f == url) {
+                            Some(index) => index + 1,
+                            None => {
+                                self.footnotes.push(url);
+                                self.footnotes.len()
+                            }
+                        };
+                        self.push_text(&format!(" [{}]", number));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.newline();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if self.at_line_start || self.output.is_empty() {
+                for prefix in self.prefixes.iter_mut() {
+                    let prefix = prefix.first.take().unwrap_or_else(|| prefix.rest.clone());
+                    self.output.push_str(&prefix);
+                }
+                self.at_line_start = false;
+            }
+            self.output.push_str(line);
+        }
+    }
+
+    fn newline(&mut self) {
+        self.output.push('\n');
+        self.at_line_start = true;
+    }
+
+    fn end_line(&mut self) {
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.newline();
+        }
+    }
+
+    // Blocks are separated by an empty line.
+    fn start_block(&mut self) {
+        self.end_line();
+        if !self.output.is_empty() && !self.output.ends_with("\n\n") {
+            self.newline();
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut output = self.output.trim_end().to_string();
+        if !self.footnotes.is_empty() {
+            output.push('\n');
+            for (index, url) in self.footnotes.iter().enumerate() {
+                output.push_str(&format!("\n[{}] {}", index + 1, url));
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings_are_underlined_in_text() {
+        let text = markdown_to_text("# Big news\n\nSome text.\n\n## Smaller news");
+        assert_eq!(
+            text,
+            "Big news\n========\n\nSome text.\n\nSmaller news\n------------"
+        );
+    }
+
+    #[test]
+    fn links_become_footnotes_in_text() {
+        let text = markdown_to_text(
+            "Read [the post](https://example.com/post) and [more](https://example.com/post).\n\n\
+            Or go to <https://example.com>.",
+        );
+        assert_eq!(
+            text,
+            "Read the post [1] and more [1].\n\n\
+            Or go to https://example.com.\n\n\
+            [1] https://example.com/post"
+        );
+    }
+
+    #[test]
+    fn lists_and_quotes_keep_their_structure_in_text() {
+        let text = markdown_to_text("1. one\n2. two\n   - nested\n\n> quoted\n> text");
+        assert_eq!(text, "1. one\n2. two\n   - nested\n\n> quoted\n> text");
+    }
+
+    #[test]
+    fn raw_html_is_left_out_of_text() {
+        let text = markdown_to_text("Hello <b>world</b>\n\n<div>block</div>");
+        assert_eq!(text, "Hello world");
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let html =
+            markdown_to_html("## Title\n\nSome *emphasis* and [a link](https://example.com).");
+        assert_eq!(
+            html,
+            "<h2>Title</h2>\n<p>Some <em>emphasis</em> and <a href=\"https://example.com\">a link</a>.</p>\n"
+        );
+    }
+}
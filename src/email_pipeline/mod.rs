@@ -0,0 +1,188 @@
+mod links;
+mod markdown;
+mod post_process;
+mod sanitize;
+mod tracking;
+
+use anyhow::Context;
+use askama::Template;
+use unicode_segmentation::UnicodeSegmentation;
+
+pub use links::extract_links;
+pub use markdown::{markdown_to_html, markdown_to_text};
+pub use post_process::{post_process_html, PostProcessError, GMAIL_CLIPPING_LIMIT};
+pub use sanitize::{sanitize_html, Sanitized, Stripped};
+pub use tracking::{add_open_pixel, rewrite_links, UtmParameters};
+
+use crate::templating::{NewsletterHtmlTemplate, NewsletterTextTemplate};
+
+// The HTML and plain-text parts of an email, merge tags still in place.
+#[derive(Debug)]
+pub struct EmailContent {
+    pub html: String,
+    pub text: String,
+}
+
+// Renders an issue written in Markdown into both parts of the email,
+// wrapped in the layout of the publication.
+// Markdown can embed raw HTML, what the sanitizer removed from it is returned too.
+pub fn render_markdown_issue(
+    publication_name: &str,
+    title: &str,
+    markdown: &str,
+) -> Result<(EmailContent, Vec<Stripped>), anyhow::Error> {
+    let mut merge_tags = MergeTags::default();
+    let title = merge_tags.protect(title);
+    let markdown = merge_tags.protect(markdown);
+    let sanitized = sanitize::sanitize_protected(&markdown_to_html(&markdown), &merge_tags)?;
+
+    let html = NewsletterHtmlTemplate {
+        publication_name,
+        title: &title,
+        content: &sanitized.html,
+    }
+    .render()
+    .context("Failed to render the HTML layout of the issue.")?;
+
+    let text = NewsletterTextTemplate {
+        publication_name,
+        title: &title,
+        title_underline: &"=".repeat(title.graphemes(true).count()),
+        content: &markdown_to_text(&markdown),
+    }
+    .render()
+    .context("Failed to render the text layout of the issue.")?;
+
+    let content = EmailContent {
+        html: merge_tags.restore(&html),
+        text: merge_tags.restore(&text),
+    };
+    Ok((content, sanitized.stripped))
+}
+
+// Markdown and HTML escaping would mangle merge tags, `{{ name | default: "you" }}`
+// in a link or a heading for instance. They are swapped for plain placeholders
+// while rendering and put back at the end.
+#[derive(Default)]
+struct MergeTags(Vec<String>);
+
+impl MergeTags {
+    fn placeholder(index: usize) -> String {
+        format!("zzmergetag{}zz", index)
+    }
+
+    fn protect(&mut self, s: &str) -> String {
+        let mut output = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            // Unclosed tags are left for the merge template parser to report.
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            output.push_str(&rest[..start]);
+            output.push_str(&Self::placeholder(self.0.len()));
+            self.0.push(rest[start..start + end + 2].to_string());
+            rest = &rest[start + end + 2..];
+        }
+        output.push_str(rest);
+        output
+    }
+
+    // Where each merge tag of `s` starts and ends, with its name:
+    // `{{ name | default: "you" }}` is `name`.
+    fn find_in(&self, s: &str) -> Vec<(usize, usize, &str)> {
+        let mut found: Vec<(usize, usize, &str)> = self
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(|(index, tag)| {
+                let placeholder = Self::placeholder(index);
+                let name = tag
+                    .trim_start_matches("{{")
+                    .trim_end_matches("}}")
+                    .split('|')
+                    .next()
+                    .unwrap_or_default()
+                    .trim();
+                let length = placeholder.len();
+                s.match_indices(&placeholder)
+                    .map(move |(start, _)| (start, start + length, name))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    fn is_placeholder_in(&self, s: &str) -> bool {
+        (0..self.0.len()).any(|index| s.contains(&Self::placeholder(index)))
+    }
+
+    fn restore(&self, s: &str) -> String {
+        let mut output = s.to_string();
+        for (index, tag) in self.0.iter().enumerate() {
+            output = output.replace(&Self::placeholder(index), tag);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_tags_survive_markdown_rendering() {
+        let (content, _) = render_markdown_issue(
+            "The Weekly",
+            "News for {{ name | default: \"you\" }}",
+            "Hi **{{ name }}**, [unsubscribe]({{ unsubscribe_url }})",
+        )
+        .unwrap();
+
+        assert!(content
+            .html
+            .contains("News for {{ name | default: \"you\" }}</h1>"));
+        assert!(content
+            .html
+            .contains("<strong>{{ name }}</strong>, <a href=\"{{ unsubscribe_url }}\">"));
+        assert!(content
+            .text
+            .starts_with("News for {{ name | default: \"you\" }}\n"));
+        assert!(content.text.contains("[1] {{ unsubscribe_url }}"));
+    }
+
+    #[test]
+    fn the_layout_carries_the_publication_name() {
+        let (content, _) = render_markdown_issue("Tom & Jerry", "Title", "Body").unwrap();
+
+        assert!(content.html.contains("Tom &amp; Jerry"));
+        assert!(content.text.contains("subscribed to Tom & Jerry."));
+    }
+
+    #[test]
+    fn merge_tags_can_not_start_links_in_markdown() {
+        let (content, stripped) = render_markdown_issue(
+            "The Weekly",
+            "Title",
+            "[Site]({{ website }}) [Leave]({{ unsubscribe_url }})",
+        )
+        .unwrap();
+
+        assert!(content.html.contains("<a>Site</a>"));
+        assert!(content
+            .html
+            .contains("<a href=\"{{ unsubscribe_url }}\">Leave</a>"));
+        assert_eq!(stripped[0].reason, "a merge tag can't start a URL");
+    }
+
+    #[test]
+    fn raw_html_in_markdown_is_sanitized() {
+        let (content, stripped) =
+            render_markdown_issue("The Weekly", "Title", "Hi <img src=x onerror=alert(1)>")
+                .unwrap();
+
+        assert!(!content.html.contains("onerror"));
+        assert_eq!(stripped[0].attribute.as_deref(), Some("onerror"));
+    }
+}
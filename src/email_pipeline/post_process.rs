@@ -0,0 +1,212 @@
+use anyhow::Context;
+use css_inline::CSSInliner;
+use lol_html::{doc_comments, element, html_content::ContentType, rewrite_str, RewriteStrSettings};
+
+use super::MergeTags;
+use crate::domain::html_escape;
+
+// Built from `templates/email/input.css` with `npm run build:email-css`.
+const EMAIL_CSS: &str = include_str!("../../static/email/css/style.css");
+
+// Gmail hides everything past the first 102KB of HTML behind a
+// "View entire message" link, unsubscribe link included.
+pub const GMAIL_CLIPPING_LIMIT: usize = 102 * 1024;
+
+// Elements email clients either strip or refuse to render.
+const UNSUPPORTED_ELEMENTS: &str =
+    "script, noscript, iframe, object, embed, form, input, button, select, textarea, video, audio, canvas";
+
+#[derive(thiserror::Error)]
+pub enum PostProcessError {
+    #[error(
+        "The HTML content is {size} bytes once processed, \
+        Gmail clips emails over {GMAIL_CLIPPING_LIMIT} bytes."
+    )]
+    TooLarge { size: usize },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PostProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::routes::error_chain_fmt(self, f)
+    }
+}
+
+// The last stage before an HTML part is handed to the email client:
+// styles are inlined, what mail clients can't handle is removed
+// and the preheader, the preview text shown next to the subject, is added.
+pub fn post_process_html(html: &str, preheader: Option<&str>) -> Result<String, PostProcessError> {
+    let mut merge_tags = MergeTags::default();
+    let html = merge_tags.protect(html);
+    let preheader = preheader
+        .filter(|preheader| !preheader.trim().is_empty())
+        .map(|preheader| preheader_html(&merge_tags.protect(preheader)));
+
+    let inliner = CSSInliner::options()
+        .load_remote_stylesheets(false)
+        .extra_css(Some(EMAIL_CSS.into()))
+        .build();
+    let inlined = inliner
+        .inline(&html)
+        .context("Failed to inline the CSS of the email.")?;
+
+    let processed = rewrite_str(
+        &inlined,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!(UNSUPPORTED_ELEMENTS, |el| {
+                    el.remove();
+                    Ok(())
+                }),
+                // Classes have nothing left to match once the styles are inlined.
+                element!("[class]", |el| {
+                    el.remove_attribute("class");
+                    Ok(())
+                }),
+                element!("[style]", |el| {
+                    let style = el.get_attribute("style").unwrap_or_default();
+                    match clean_style(&style) {
+                        style if style.is_empty() => el.remove_attribute("style"),
+                        style => el.set_attribute("style", &style)?,
+                    }
+                    Ok(())
+                }),
+                element!("body", |el| {
+                    if let Some(preheader) = &preheader {
+                        el.prepend(preheader, ContentType::Html);
+                    }
+                    Ok(())
+                }),
+            ],
+            // Outlook relies on conditional comments, the rest is dead weight.
+            document_content_handlers: vec![doc_comments!(|comment| {
+                if !comment.text().trim_start().starts_with("[if") {
+                    comment.remove();
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .context("Failed to post-process the HTML of the email.")?;
+
+    let processed = merge_tags.restore(&processed);
+    if processed.len() > GMAIL_CLIPPING_LIMIT {
+        return Err(PostProcessError::TooLarge {
+            size: processed.len(),
+        });
+    }
+
+    Ok(processed)
+}
+
+// Hidden in the body, and padded with invisible characters so
+// clients don't fill the rest of the preview with the email content.
+fn preheader_html(preheader: &str) -> String {
+    format!(
+        "<div style=\"display: none; max-height: 0; overflow: hidden; mso-hide: all\">{}{}</div>",
+        html_escape(preheader),
+        "&#847;&zwnj;&nbsp;".repeat(80)
+    )
+}
+
+// CSS variables are not supported by email clients,
+// neither as custom properties nor through `var()`.
+fn clean_style(style: &str) -> String {
+    split_declarations(style)
+        .into_iter()
+        .map(str::trim)
+        .filter(|declaration| {
+            !declaration.is_empty()
+                && !declaration.starts_with("--")
+                && !declaration.contains("var(")
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+// Declarations are separated by `;`, except inside parentheses,
+// e.g. in `url(data:image/png;base64,...)`.
+fn split_declarations(style: &str) -> Vec<&str> {
+    let mut declarations = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (index, c) in style.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ';' if depth == 0 => {
+                declarations.push(&style[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    declarations.push(&style[start..]);
+    declarations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::assert_err;
+
+    #[test]
+    fn styles_are_inlined_and_style_tags_removed() {
+        let html = post_process_html(
+            "<html><head><style>p { margin: 0 }</style></head>\
+            <body><p class=\"text-zinc-500\">Hi</p></body></html>",
+            None,
+        )
+        .unwrap();
+
+        assert!(!html.contains("<style"));
+        assert!(!html.contains("class="));
+        assert!(html.contains("margin: 0"));
+        assert!(html.contains("color: #71717a"));
+    }
+
+    #[test]
+    fn css_variables_are_stripped() {
+        assert_eq!(
+            clean_style("--tw-opacity: 1;color: rgb(0 0 0 / var(--tw-opacity)); margin: 0"),
+            "margin: 0"
+        );
+        assert_eq!(
+            clean_style("background: url(data:image/png;base64,AAAA);--x: 1"),
+            "background: url(data:image/png;base64,AAAA)"
+        );
+    }
+
+    #[test]
+    fn unsupported_elements_and_comments_are_removed() {
+        let html = post_process_html(
+            "<p>Hi</p><script>alert(1)</script><form><input></form>\
+            <!-- a note --><!--[if mso]><table><![endif]-->",
+            None,
+        )
+        .unwrap();
+
+        assert!(!html.contains("script"));
+        assert!(!html.contains("<form"));
+        assert!(!html.contains("a note"));
+        assert!(html.contains("[if mso]"));
+    }
+
+    #[test]
+    fn the_preheader_opens_the_body() {
+        let html = post_process_html("<p>Body</p>", Some("Hi {{ name | default: \"you\" }} & co"))
+            .unwrap();
+
+        let body = html.split("<body>").nth(1).unwrap();
+        assert!(body.starts_with("<div style=\"display: none;"));
+        assert!(body.contains("Hi {{ name | default: \"you\" }} &amp; co"));
+    }
+
+    #[test]
+    fn html_over_the_gmail_clipping_limit_is_rejected() {
+        let html = format!("<p>{}</p>", "a".repeat(GMAIL_CLIPPING_LIMIT));
+        assert_err!(post_process_html(&html, None));
+    }
+}
@@ -0,0 +1,454 @@
+use std::cell::RefCell;
+
+use anyhow::Context;
+use lol_html::{element, rewrite_str, text, RewriteStrSettings};
+
+use super::MergeTags;
+
+// Dropped along with everything inside them.
+const DANGEROUS_ELEMENTS: &[&str] = &[
+    "script", "noscript", "template", "iframe", "frame", "frameset", "object", "embed", "applet",
+    "base", "link", "form", "input", "button", "select", "textarea", "svg", "math",
+];
+
+// Everything email clients render consistently. Other elements are unwrapped,
+// their content is kept.
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "html",
+    "head",
+    "body",
+    "title",
+    "meta",
+    "style",
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "caption",
+    "center",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "font",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "s",
+    "small",
+    "span",
+    "strike",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "align",
+    "alt",
+    "bgcolor",
+    "border",
+    "cellpadding",
+    "cellspacing",
+    "charset",
+    "class",
+    "color",
+    "colspan",
+    "content",
+    "dir",
+    "face",
+    "height",
+    "href",
+    "id",
+    "lang",
+    "name",
+    "rel",
+    "role",
+    "rowspan",
+    "size",
+    "span",
+    "src",
+    "start",
+    "style",
+    "summary",
+    "target",
+    "title",
+    "type",
+    "valign",
+    "width",
+];
+
+const URL_ATTRIBUTES: &[&str] = &["href", "src"];
+
+// Merge tags whose values the service builds itself, they may make up a whole URL.
+// Every other value can come from a subscriber, a custom field for instance.
+const GENERATED_URL_TAGS: &[&str] = &["unsubscribe_url"];
+
+// Something the sanitizer took out of the content, reported back to the editor.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Stripped {
+    pub element: String,
+    pub attribute: Option<String>,
+    pub reason: &'static str,
+    pub count: usize,
+}
+
+#[derive(Debug)]
+pub struct Sanitized {
+    pub html: String,
+    pub stripped: Vec<Stripped>,
+}
+
+// Content coming from editors is untrusted, only an allowlist of
+// elements, attributes and URL schemes makes it into emails.
+pub fn sanitize_html(html: &str) -> Result<Sanitized, anyhow::Error> {
+    let mut merge_tags = MergeTags::default();
+    let html = merge_tags.protect(html);
+    let sanitized = sanitize_protected(&html, &merge_tags)?;
+    Ok(Sanitized {
+        html: merge_tags.restore(&sanitized.html),
+        stripped: sanitized.stripped,
+    })
+}
+
+// For HTML whose merge tags were already swapped for placeholders,
+// they are left as placeholders.
+pub(super) fn sanitize_protected(
+    html: &str,
+    merge_tags: &MergeTags,
+) -> Result<Sanitized, anyhow::Error> {
+    let stripped = RefCell::new(Vec::<Stripped>::new());
+    let record = |element: &str, attribute: Option<&str>, reason: &'static str| {
+        let mut stripped = stripped.borrow_mut();
+        match stripped.iter_mut().find(|s| {
+            s.element == element && s.attribute.as_deref() == attribute && s.reason == reason
+        }) {
+            Some(existing) => existing.count += 1,
+            None => stripped.push(Stripped {
+                element: element.to_string(),
+                attribute: attribute.map(String::from),
+                reason,
+                count: 1,
+            }),
+        }
+    };
+
+    let sanitized = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("*", |el| {
+                    let name = el.tag_name();
+                    if DANGEROUS_ELEMENTS.contains(&name.as_str()) {
+                        record(&name, None, "not allowed in emails");
+                        el.remove();
+                        return Ok(());
+                    }
+                    if !ALLOWED_ELEMENTS.contains(&name.as_str()) {
+                        record(&name, None, "not supported in emails, its content was kept");
+                        el.remove_and_keep_content();
+                        return Ok(());
+                    }
+
+                    let attributes: Vec<(String, String)> = el
+                        .attributes()
+                        .iter()
+                        .map(|attribute| (attribute.name(), attribute.value()))
+                        .collect();
+                    for (attribute, value) in attributes {
+                        let reason = if !ALLOWED_ATTRIBUTES.contains(&attribute.as_str()) {
+                            Some("not allowed in emails")
+                        } else if URL_ATTRIBUTES.contains(&attribute.as_str())
+                            && !is_safe_url(&name, &value)
+                        {
+                            Some("unsafe URL")
+                        } else if URL_ATTRIBUTES.contains(&attribute.as_str())
+                            && !is_fixed_scheme(&value, merge_tags)
+                        {
+                            Some("a merge tag can't start a URL")
+                        } else if attribute == "style" && !is_safe_css(&value) {
+                            Some("unsafe CSS")
+                        } else if attribute == "style" && merge_tags.is_placeholder_in(&value) {
+                            Some("merge tags are not allowed in CSS")
+                        } else {
+                            None
+                        };
+                        if let Some(reason) = reason {
+                            record(&name, Some(&attribute), reason);
+                            el.remove_attribute(&attribute);
+                        }
+                    }
+                    Ok(())
+                }),
+                text!("style", |chunk| {
+                    if !is_safe_css(chunk.as_str()) {
+                        record("style", None, "unsafe CSS");
+                        chunk.remove();
+                    } else if merge_tags.is_placeholder_in(chunk.as_str()) {
+                        record("style", None, "merge tags are not allowed in CSS");
+                        chunk.remove();
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .context("Failed to sanitize the HTML content.")?;
+
+    Ok(Sanitized {
+        html: sanitized,
+        stripped: stripped.into_inner(),
+    })
+}
+
+// Merge tags are filled in at send time, after the URL checks. A tag that
+// starts a URL could turn it into `javascript:`, so what comes before the
+// first tag has to settle the scheme, unless the service builds the URL.
+fn is_fixed_scheme(url: &str, merge_tags: &MergeTags) -> bool {
+    match merge_tags.find_in(url).first() {
+        None => true,
+        Some((0, _, name)) => GENERATED_URL_TAGS.contains(name),
+        Some((start, _, _)) => decode_entities(&url[..*start]).contains([':', '/', '?', '#']),
+    }
+}
+
+fn is_safe_url(element: &str, url: &str) -> bool {
+    // Browsers ignore whitespace and control characters in schemes, `java\tscript:` is a thing.
+    let url: String = decode_entities(url)
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    let scheme = match url.find(':') {
+        // A colon after a path, query or fragment is not a scheme separator.
+        Some(index) if !url[..index].contains(['/', '?', '#']) => &url[..index],
+        _ => return true,
+    };
+    match scheme {
+        "http" | "https" => true,
+        "mailto" | "tel" => element == "a",
+        // Inline attachments and embedded images.
+        "cid" => element == "img",
+        "data" => element == "img" && url.starts_with("data:image/"),
+        _ => false,
+    }
+}
+
+fn is_safe_css(css: &str) -> bool {
+    let css = decode_entities(css).to_lowercase();
+    // CSS escapes can spell out anything, there is no reason to use them in an email.
+    !css.contains('\\')
+        && [
+            "expression(",
+            "javascript:",
+            "vbscript:",
+            "behavior:",
+            "-moz-binding",
+            "@import",
+        ]
+        .iter()
+        .all(|pattern| !css.contains(pattern))
+}
+
+// Attribute values reach the sanitizer undecoded, `&#58;` would otherwise hide a colon.
+// Browsers also accept numeric references without the closing `;`.
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(number) = rest.strip_prefix('#') {
+            let (digits, radix) = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => (hex, 16),
+                None => (number, 10),
+            };
+            let length = digits
+                .find(|c: char| !c.is_digit(radix))
+                .unwrap_or(digits.len());
+            if let Some(character) = u32::from_str_radix(&digits[..length], radix)
+                .ok()
+                .and_then(char::from_u32)
+            {
+                decoded.push(character);
+                let after = &digits[length..];
+                rest = after.strip_prefix(';').unwrap_or(after);
+                continue;
+            }
+        }
+
+        let named = [
+            ("colon;", ':'),
+            ("tab;", '\t'),
+            ("newline;", '\n'),
+            ("lpar;", '('),
+            ("rpar;", ')'),
+            ("amp;", '&'),
+        ]
+        .into_iter()
+        .find(|(name, _)| rest.starts_with(name));
+        match named {
+            Some((name, character)) => {
+                decoded.push(character);
+                rest = &rest[name.len()..];
+            }
+            None => decoded.push('&'),
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(html: &str) -> Sanitized {
+        sanitize_html(html).unwrap()
+    }
+
+    #[test]
+    fn email_safe_formatting_is_kept() {
+        let html = "<table role=\"presentation\" width=\"100%\"><tr><td style=\"color: red\">\
+            <p><a href=\"https://example.com\" target=\"_blank\">A <strong>link</strong></a></p>\
+            <img src=\"cid:logo\" alt=\"Logo\"></td></tr></table>";
+        let sanitized = sanitize(html);
+
+        assert_eq!(sanitized.html, html);
+        assert!(sanitized.stripped.is_empty());
+    }
+
+    #[test]
+    fn scripts_and_their_content_are_removed() {
+        let sanitized = sanitize("<p>Hi</p><script>alert(1)</script><script>alert(2)</script>");
+
+        assert_eq!(sanitized.html, "<p>Hi</p>");
+        assert_eq!(
+            sanitized.stripped,
+            vec![Stripped {
+                element: "script".into(),
+                attribute: None,
+                reason: "not allowed in emails",
+                count: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn event_handlers_are_removed() {
+        let sanitized = sanitize("<img src=\"https://example.com/a.png\" onerror=\"alert(1)\">");
+
+        assert_eq!(sanitized.html, "<img src=\"https://example.com/a.png\">");
+        assert_eq!(sanitized.stripped[0].attribute.as_deref(), Some("onerror"));
+    }
+
+    #[test]
+    fn javascript_urls_are_removed_however_they_are_spelled() {
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            " java\tscript:alert(1)",
+            "javascript&#58;alert(1)",
+            "javascript&#0000058alert(1)",
+            "javascript&colon;alert(1)",
+            "&#x6A;avascript:alert(1)",
+            "data:text/html;base64,PHNjcmlwdD4=",
+        ] {
+            let sanitized = sanitize(&format!("<a href=\"{}\">Click</a>", url));
+            assert_eq!(sanitized.html, "<a>Click</a>", "{} was not removed", url);
+        }
+    }
+
+    #[test]
+    fn relative_urls_and_merge_tags_are_kept() {
+        let html = "<a href=\"{{ unsubscribe_url }}\">Unsubscribe</a><a href=\"/archive?page=2\">Archive</a>";
+        assert_eq!(sanitize(html).html, html);
+    }
+
+    #[test]
+    fn merge_tags_are_kept_after_a_fixed_scheme() {
+        let html = "<a href=\"https://example.com/?ref={{ email }}\">Site</a>\
+            <a href=\"/profiles/{{ name }}\">Profile</a>\
+            <a href=\"mailto:{{ email }}\">Mail</a>";
+        assert_eq!(sanitize(html).html, html);
+    }
+
+    #[test]
+    fn merge_tags_that_could_set_the_scheme_are_removed() {
+        for url in [
+            "{{ website }}",
+            "{{ website }}/path",
+            "java{{ x }}",
+            "{{ name | default: \"https://example.com\" }}",
+        ] {
+            let sanitized = sanitize(&format!("<a href=\"{}\">Site</a>", url));
+            assert_eq!(sanitized.html, "<a>Site</a>", "{} was not removed", url);
+            assert_eq!(
+                sanitized.stripped[0].reason,
+                "a merge tag can't start a URL"
+            );
+        }
+    }
+
+    #[test]
+    fn merge_tags_are_removed_from_css() {
+        let sanitized = sanitize(
+            "<p style=\"background: {{ color }}\">Hi</p><style>p { color: {{ color }} }</style>",
+        );
+
+        assert_eq!(sanitized.html, "<p>Hi</p><style></style>");
+        assert_eq!(sanitized.stripped.len(), 2);
+    }
+
+    #[test]
+    fn unknown_elements_are_unwrapped() {
+        let sanitized = sanitize("<p><blink>Hello</blink></p>");
+
+        assert_eq!(sanitized.html, "<p>Hello</p>");
+        assert_eq!(sanitized.stripped[0].element, "blink");
+    }
+
+    #[test]
+    fn unsafe_css_is_removed() {
+        let sanitized = sanitize(
+            "<p style=\"width: expression(alert(1))\">Hi</p>\
+            <style>p { background: url(javascript:alert(1)) }</style>",
+        );
+
+        assert_eq!(sanitized.html, "<p>Hi</p><style></style>");
+        assert_eq!(sanitized.stripped.len(), 2);
+    }
+}
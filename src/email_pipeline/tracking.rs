@@ -0,0 +1,144 @@
+use anyhow::Context;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use reqwest::Url;
+
+use crate::domain::html_escape;
+
+// Tags the links of an issue so analytics tools can tell where visitors came from.
+#[derive(
+    Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct UtmParameters {
+    pub source: Option<String>,
+    pub medium: Option<String>,
+    pub campaign: Option<String>,
+}
+
+impl UtmParameters {
+    // Parameters already in the link are left as they are.
+    pub fn apply(&self, url: &str) -> String {
+        let Ok(mut parsed) = Url::parse(url) else {
+            return url.to_string();
+        };
+        let existing: Vec<String> = parsed.query_pairs().map(|(key, _)| key.into()).collect();
+        let missing: Vec<_> = [
+            ("utm_source", &self.source),
+            ("utm_medium", &self.medium),
+            ("utm_campaign", &self.campaign),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.as_deref()?)))
+        .filter(|(key, value)| !value.is_empty() && !existing.iter().any(|e| e == key))
+        .collect();
+        if missing.is_empty() {
+            return url.to_string();
+        }
+        parsed.query_pairs_mut().extend_pairs(missing);
+        parsed.into()
+    }
+}
+
+// Replaces the destination of the http(s) links of an HTML part,
+// links `rewrite` returns None for are left alone.
+pub fn rewrite_links(
+    html: &str,
+    rewrite: impl Fn(&str) -> Option<String>,
+) -> Result<String, anyhow::Error> {
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("a[href]", |el| {
+                let href = el.get_attribute("href").unwrap_or_default();
+                // Attributes come back as written, `&` included.
+                let url = href.trim().replace("&amp;", "&");
+                if !(url.starts_with("http://") || url.starts_with("https://")) {
+                    return Ok(());
+                }
+                if let Some(rewritten) = rewrite(&url) {
+                    el.set_attribute("href", &rewritten.replace('&', "&amp;"))?;
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .context("Failed to rewrite the links of the email.")
+}
+
+// Adds an invisible image to the end of the body, fetching it records an open.
+// Emails without a body get it at the very end.
+pub fn add_open_pixel(html: &str, pixel_url: &str) -> String {
+    let pixel = format!(
+        "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" \
+        style=\"display: block; width: 1px; height: 1px; border: 0\">",
+        html_escape(pixel_url)
+    );
+    let mut html = html.to_string();
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(index) => html.insert_str(index, &pixel),
+        None => html.push_str(&pixel),
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_open_pixel, rewrite_links, UtmParameters};
+
+    #[test]
+    fn the_pixel_closes_the_body() {
+        let html = add_open_pixel(
+            "<html><body><p>Hi</p></BODY></html>",
+            "https://example.com/o/abc",
+        );
+
+        assert!(html.starts_with("<html><body><p>Hi</p><img src=\"https://example.com/o/abc\""));
+        assert!(html.ends_with("></BODY></html>"));
+    }
+
+    #[test]
+    fn the_pixel_is_appended_without_a_body() {
+        let html = add_open_pixel("<p>Hi</p>", "https://example.com/o/a&b");
+
+        assert!(html.starts_with("<p>Hi</p><img src=\"https://example.com/o/a&amp;b\""));
+    }
+
+    #[test]
+    fn http_links_are_rewritten() {
+        let html = r#"<a href="https://example.com/a?x=1&amp;y=2">A</a>
+            <a href="mailto:editor@example.com">Write us</a>
+            <a href="https://example.com/b">B</a>"#;
+
+        let rewritten = rewrite_links(html, |url| {
+            (url != "https://example.com/b").then(|| format!("https://r.example.com/?to={}", url))
+        })
+        .unwrap();
+
+        assert!(rewritten
+            .contains(r#"href="https://r.example.com/?to=https://example.com/a?x=1&amp;y=2""#));
+        assert!(rewritten.contains(r#"href="mailto:editor@example.com""#));
+        assert!(rewritten.contains(r#"href="https://example.com/b""#));
+    }
+
+    #[test]
+    fn utm_parameters_are_appended_unless_present() {
+        let utm = UtmParameters {
+            source: Some("newsletter".to_string()),
+            medium: Some("email".to_string()),
+            campaign: Some("spring launch".to_string()),
+        };
+
+        assert_eq!(
+            utm.apply("https://example.com/a?x=1"),
+            "https://example.com/a?x=1&utm_source=newsletter&utm_medium=email&utm_campaign=spring+launch"
+        );
+        assert_eq!(
+            utm.apply("https://example.com/?utm_source=blog#top"),
+            "https://example.com/?utm_source=blog&utm_medium=email&utm_campaign=spring+launch#top"
+        );
+        assert_eq!(
+            UtmParameters::default().apply("https://example.com/a"),
+            "https://example.com/a"
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    audience::get_recipient_by_email,
+    click_tracking::HmacSecret,
+    configuration::Settings,
+    email_clients::EmailClient,
+    newsletter_issues::{get_issue, IssueTemplates, IssueTracking, TRACKING_TOKEN_LENGTH},
+    routes::admin::get_custom_field_definitions,
+    startup::{get_connection_pool, ApplicationBaseUrl},
+    tenant::{get_publication_by_id, Publication},
+};
+
+// How often a failed delivery is tried again before it is given up on.
+const MAX_RETRIES: i16 = 3;
+const RETRY_BACKOFF: chrono::Duration = chrono::Duration::seconds(30);
+// How long a compiled issue is reused, changes to its publication
+// reach the rest of the delivery after at most this long.
+const ISSUE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    let base_url = ApplicationBaseUrl(configuration.application.base_url);
+    let hmac_secret = HmacSecret(configuration.application.hmac_secret);
+    worker_loop(connection_pool, email_client, base_url, hmac_secret).await
+}
+
+async fn worker_loop(
+    connection_pool: PgPool,
+    email_client: EmailClient,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
+    let mut issue_cache = IssueCache::default();
+    loop {
+        match try_execute_task(
+            &connection_pool,
+            &email_client,
+            &base_url,
+            &hmac_secret,
+            &mut issue_cache,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// Sends one email of the queue. Replicas can run this side by side,
+// every task is locked by the worker that picked it up.
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty, subscriber_email = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    issue_cache: &mut IssueCache,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(connection_pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.issue_id))
+        .record("subscriber_email", display(&task.subscriber_email));
+
+    // Failed tasks go back to the queue for a while, so one bad address
+    // or a hiccup of the email provider doesn't block everyone else.
+    let delivery = deliver(
+        connection_pool,
+        email_client,
+        base_url,
+        hmac_secret,
+        issue_cache,
+        &task,
+    )
+    .await;
+    match delivery {
+        Ok(Some(tracking_token)) => {
+            let outcome = DeliveryOutcome::Sent { tracking_token };
+            record_delivery(&mut transaction, &task, outcome).await?;
+            delete_task(&mut transaction, &task).await?;
+        }
+        Ok(None) => delete_task(&mut transaction, &task).await?,
+        Err(e) if task.n_retries < MAX_RETRIES => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a subscriber. Retrying later.",
+            );
+            retry_task(&mut transaction, &task).await?;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a subscriber. Giving up.",
+            );
+            record_delivery(&mut transaction, &task, DeliveryOutcome::Failed).await?;
+            delete_task(&mut transaction, &task).await?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the delivery task.")?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct DeliveryTask {
+    issue_id: Uuid,
+    publication_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+    // Set for the deliveries of an A/B test.
+    variant: Option<i16>,
+}
+
+enum DeliveryOutcome {
+    // Identifies the delivery in its tracking pixel and redirect links,
+    // for issues tracking opens or clicks.
+    Sent { tracking_token: Option<String> },
+    Failed,
+}
+
+impl DeliveryOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Sent { .. } => "sent",
+            DeliveryOutcome::Failed => "failed",
+        }
+    }
+}
+
+// Issues go out to many recipients in a row. What all their emails share is
+// fetched and compiled once per issue and variant, not once per email.
+#[derive(Default)]
+pub struct IssueCache {
+    issues: HashMap<(Uuid, Option<i16>), CachedIssue>,
+}
+
+struct CachedIssue {
+    loaded_at: Instant,
+    publication: Publication,
+    templates: IssueTemplates,
+    tracking: IssueTracking,
+}
+
+impl IssueCache {
+    async fn get(
+        &mut self,
+        connection_pool: &PgPool,
+        task: &DeliveryTask,
+    ) -> Result<&CachedIssue, anyhow::Error> {
+        let now = Instant::now();
+        self.issues
+            .retain(|_, cached| now.duration_since(cached.loaded_at) < ISSUE_CACHE_TTL);
+        let key = (task.issue_id, task.variant);
+        let cached = match self.issues.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(load_issue(connection_pool, task).await?),
+        };
+        Ok(cached)
+    }
+}
+
+async fn load_issue(
+    connection_pool: &PgPool,
+    task: &DeliveryTask,
+) -> Result<CachedIssue, anyhow::Error> {
+    let publication = get_publication_by_id(connection_pool, task.publication_id)
+        .await?
+        .context("The publication of the issue is gone.")?;
+    let issue = get_issue(connection_pool, task.publication_id, task.issue_id)
+        .await?
+        .context("The queued issue is gone.")?;
+    let (title, content) = match task.variant {
+        Some(variant) => {
+            let variant = issue
+                .ab_test
+                .as_ref()
+                .and_then(|ab_test| ab_test.variants.get(variant as usize))
+                .context("The variant of the delivery is gone.")?;
+            (&variant.title, &variant.content)
+        }
+        None => (&issue.title, &issue.content),
+    };
+    let definitions = get_custom_field_definitions(connection_pool, task.publication_id).await?;
+    let templates =
+        IssueTemplates::compile(title, issue.preheader.as_deref(), content, &definitions)?;
+    Ok(CachedIssue {
+        loaded_at: Instant::now(),
+        publication,
+        tracking: issue.tracking(),
+        templates,
+    })
+}
+
+fn generate_tracking_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(TRACKING_TOKEN_LENGTH)
+        .collect()
+}
+
+// Returns None when the subscriber is gone, and the tracking token
+// of the email, if it got one, otherwise.
+
+async fn deliver(
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    issue_cache: &mut IssueCache,
+    task: &DeliveryTask,
+) -> Result<Option<Option<String>>, anyhow::Error> {
+    // Subscribers who left after the issue was queued don't get it.
+    let Some(recipient) =
+        get_recipient_by_email(connection_pool, task.publication_id, &task.subscriber_email)
+            .await?
+    else {
+        return Ok(None);
+    };
+    let issue = issue_cache.get(connection_pool, task).await?;
+    let publication = &issue.publication;
+
+    let publication_url = publication.base_url(base_url);
+    let mut email = issue.templates.render(&recipient, &publication_url);
+    let tracking_token = issue.tracking.needs_token().then(generate_tracking_token);
+    email.html = issue.tracking.apply(
+        &email.html,
+        &publication_url,
+        tracking_token.as_deref(),
+        hmac_secret,
+    )?;
+    email_client
+        .send_email_from(
+            publication.sender.as_ref(),
+            recipient.email,
+            &email.subject,
+            &email.html,
+            &email.text,
+        )
+        .await
+        .context("Failed to send the newsletter issue.")?;
+    Ok(Some(tracking_token))
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    connection_pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, DeliveryTask)>, anyhow::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to start a transaction.")?;
+    let task = sqlx::query!(
+        r#"
+    SELECT issue_delivery_queue.newsletter_issue_id, issue_delivery_queue.subscriber_email,
+        issue_delivery_queue.n_retries, issue_delivery_queue.variant,
+        newsletter_issues.publication_id
+    FROM issue_delivery_queue
+    JOIN newsletter_issues ON newsletter_issues.id = issue_delivery_queue.newsletter_issue_id
+    WHERE issue_delivery_queue.execute_after <= now()
+        AND newsletter_issues.status = 'published'
+    FOR UPDATE OF issue_delivery_queue
+    SKIP LOCKED
+    LIMIT 1"#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to dequeue a delivery task.")?;
+
+    Ok(task.map(|task| {
+        (
+            transaction,
+            DeliveryTask {
+                issue_id: task.newsletter_issue_id,
+                publication_id: task.publication_id,
+                subscriber_email: task.subscriber_email,
+                n_retries: task.n_retries,
+                variant: task.variant,
+            },
+        )
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &DeliveryTask,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let tracking_token = match &outcome {
+        DeliveryOutcome::Sent { tracking_token } => tracking_token.as_deref(),
+        DeliveryOutcome::Failed => None,
+    };
+    sqlx::query!(
+        r#"
+    INSERT INTO issue_deliveries (
+        newsletter_issue_id, subscriber_email, delivered_at, outcome, n_retries, variant,
+        tracking_token
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        task.issue_id,
+        task.subscriber_email,
+        Utc::now(),
+        outcome.as_str(),
+        task.n_retries,
+        task.variant,
+        tracking_token
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the delivery.")?;
+    Ok(())
+}
+
+// Backs off exponentially, 30 seconds after the first failure, then 1 and 2 minutes.
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let backoff = RETRY_BACKOFF * 2i32.pow(task.n_retries as u32);
+    sqlx::query!(
+        r#"
+    UPDATE issue_delivery_queue
+    SET n_retries = n_retries + 1, execute_after = $3
+    WHERE newsletter_issue_id = $1 AND subscriber_email = $2"#,
+        task.issue_id,
+        task.subscriber_email,
+        Utc::now() + backoff
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to put the delivery task back in the queue.")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    DELETE FROM issue_delivery_queue
+    WHERE newsletter_issue_id = $1 AND subscriber_email = $2"#,
+        task.issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to delete the delivery task.")?;
+    Ok(())
+}
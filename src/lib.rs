@@ -1,7 +1,23 @@
+pub mod audience;
+pub mod authentication;
+pub mod click_tracking;
 pub mod configuration;
 pub mod domain;
 pub mod email_clients;
+pub mod email_pipeline;
+pub mod issue_delivery_worker;
+pub mod link_checker;
+pub mod newsletter_issues;
+pub mod outbound;
+pub mod problem;
 pub mod routes;
+pub mod scheduler;
 pub mod startup;
+pub mod suppressions;
 pub mod telemetry;
 pub mod templating;
+pub mod tenant;
+pub mod transactional_email_worker;
+pub mod transactional_emails;
+pub mod webhook_delivery_worker;
+pub mod webhooks;
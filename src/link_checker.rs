@@ -0,0 +1,424 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::{redirect, Client, Method, StatusCode, Url};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::email_pipeline::extract_links;
+use crate::outbound::DestinationPolicy;
+
+// Redirect chains longer than this are reported as dead.
+const MAX_REDIRECTS: usize = 5;
+
+// Checks the links of an issue before it goes out,
+// a broken link can't be fixed once the email is in inboxes.
+pub struct LinkChecker {
+    http_client: Client,
+    // Issues often link the same site many times,
+    // it should not be hammered with requests.
+    max_concurrency_per_host: usize,
+    // Checks run while the editor waits, an issue with thousands of links
+    // would keep them waiting and fan out as many requests.
+    max_links: usize,
+    // Issue links are chosen by tenants, they must not reach our own network.
+    policy: DestinationPolicy,
+}
+
+// One permit pool per host, shared by the checks so redirects to a host
+// count against its limit too.
+#[derive(Clone)]
+struct HostLimits {
+    hosts: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+    max_concurrency_per_host: usize,
+}
+
+impl HostLimits {
+    fn for_host(&self, url: &Url) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(url.host_str().unwrap_or_default().to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_concurrency_per_host)))
+            .clone()
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize, utoipa::ToSchema)]
+pub struct LinkReport {
+    pub checked: usize,
+    pub dead: Vec<DeadLink>,
+    // Links past the limit of the checker, nobody knows if they work.
+    pub unchecked: Vec<String>,
+    pub redirects: Vec<RedirectedLink>,
+    // Links missing from the other part of the email.
+    pub only_in_html: Vec<String>,
+    pub only_in_text: Vec<String>,
+}
+
+impl LinkReport {
+    pub fn has_dead_links(&self) -> bool {
+        !self.dead.is_empty()
+    }
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct DeadLink {
+    pub url: String,
+    pub status: Option<u16>,
+    pub error: String,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct RedirectedLink {
+    pub url: String,
+    pub status: u16,
+    pub location: String,
+}
+
+enum LinkStatus {
+    Alive,
+    Redirected {
+        status: StatusCode,
+        location: Url,
+    },
+    Dead {
+        status: Option<StatusCode>,
+        error: String,
+    },
+}
+
+impl LinkChecker {
+    pub fn new(
+        timeout: Duration,
+        max_concurrency_per_host: usize,
+        max_links: usize,
+        policy: DestinationPolicy,
+    ) -> Self {
+        let http_client = policy
+            .client_builder()
+            .timeout(timeout)
+            // Redirects are followed by hand to report where links end up.
+            .redirect(redirect::Policy::none())
+            .build()
+            .expect("Failed to create the link checker client");
+        Self {
+            http_client,
+            max_concurrency_per_host: max_concurrency_per_host.max(1),
+            max_links,
+            policy,
+        }
+    }
+
+    #[tracing::instrument(name = "Checking the links of an issue", skip_all)]
+    pub async fn check(&self, html: &str, text: &str) -> LinkReport {
+        let html_links = extract_links(html);
+        let text_links = extract_links(text);
+        let mut links: Vec<&String> = html_links
+            .union(&text_links)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let unchecked = links
+            .split_off(links.len().min(self.max_links))
+            .into_iter()
+            .cloned()
+            .collect();
+
+        let mut report = LinkReport {
+            checked: links.len(),
+            unchecked,
+            only_in_html: html_links.difference(&text_links).cloned().collect(),
+            only_in_text: text_links.difference(&html_links).cloned().collect(),
+            ..LinkReport::default()
+        };
+
+        let host_limits = HostLimits {
+            hosts: Arc::default(),
+            max_concurrency_per_host: self.max_concurrency_per_host,
+        };
+        let mut checks = JoinSet::new();
+        for link in links {
+            let url = match Url::parse(link) {
+                Ok(url) => url,
+                Err(e) => {
+                    report.dead.push(DeadLink {
+                        url: link.clone(),
+                        status: None,
+                        error: format!("Invalid URL: {}", e),
+                    });
+                    continue;
+                }
+            };
+            let http_client = self.http_client.clone();
+            let host_limits = host_limits.clone();
+            let policy = self.policy;
+            let link = link.clone();
+
+            checks.spawn(async move {
+                let status = check_link(&http_client, &host_limits, policy, url).await;
+                (link, status)
+            });
+        }
+
+        while let Some(result) = checks.join_next().await {
+            let Ok((url, status)) = result else {
+                continue;
+            };
+            match status {
+                LinkStatus::Alive => {}
+                LinkStatus::Redirected { status, location } => {
+                    report.redirects.push(RedirectedLink {
+                        url,
+                        status: status.as_u16(),
+                        location: location.to_string(),
+                    })
+                }
+                LinkStatus::Dead { status, error } => report.dead.push(DeadLink {
+                    url,
+                    status: status.map(|status| status.as_u16()),
+                    error,
+                }),
+            }
+        }
+
+        // Tasks finish in any order, reports should not.
+        report.dead.sort_by(|a, b| a.url.cmp(&b.url));
+        report.redirects.sort_by(|a, b| a.url.cmp(&b.url));
+        report
+    }
+}
+
+async fn check_link(
+    http_client: &Client,
+    host_limits: &HostLimits,
+    policy: DestinationPolicy,
+    url: Url,
+) -> LinkStatus {
+    let mut current = url.clone();
+    let mut first_redirect = None;
+
+    for _ in 0..=MAX_REDIRECTS {
+        // Redirects may point anywhere, every hop is checked.
+        if let Err(error) = policy.check(&current).await {
+            return LinkStatus::Dead {
+                status: None,
+                error,
+            };
+        }
+        let host_limit = host_limits.for_host(&current);
+        let _permit = host_limit.acquire().await;
+        let response = match request(http_client, &current).await {
+            Ok(response) => response,
+            Err(e) => {
+                let error = if e.is_timeout() {
+                    "Timed out".to_string()
+                } else {
+                    format!("Unreachable: {}", e)
+                };
+                return LinkStatus::Dead {
+                    status: None,
+                    error,
+                };
+            }
+        };
+        let status = response.status();
+
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| current.join(location).ok());
+            match location {
+                Some(location) => {
+                    first_redirect.get_or_insert(status);
+                    current = location;
+                    continue;
+                }
+                None => {
+                    return LinkStatus::Dead {
+                        status: Some(status),
+                        error: "Redirect without a valid location".to_string(),
+                    }
+                }
+            }
+        }
+
+        if !status.is_success() {
+            return LinkStatus::Dead {
+                status: Some(status),
+                error: format!("{} responded with {}", current, status),
+            };
+        }
+
+        return match first_redirect {
+            Some(status) => LinkStatus::Redirected {
+                status,
+                location: current,
+            },
+            None => LinkStatus::Alive,
+        };
+    }
+
+    LinkStatus::Dead {
+        status: None,
+        error: format!("More than {} redirects", MAX_REDIRECTS),
+    }
+}
+
+// HEAD is enough most of the time, some servers only answer GET.
+async fn request(http_client: &Client, url: &Url) -> Result<reqwest::Response, reqwest::Error> {
+    let response = http_client
+        .request(Method::HEAD, url.clone())
+        .send()
+        .await?;
+    if matches!(
+        response.status(),
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+    ) {
+        return http_client.get(url.clone()).send().await;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn link_checker() -> LinkChecker {
+        // The mock servers run on localhost.
+        LinkChecker::new(
+            Duration::from_millis(200),
+            2,
+            10,
+            DestinationPolicy::new(true),
+        )
+    }
+
+    #[tokio::test]
+    async fn dead_links_and_redirects_are_reported() {
+        let server = MockServer::start().await;
+        Mock::given(path("/ok"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(path("/moved"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", "/ok"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/gone"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let html = format!(
+            r#"<a href="{0}/ok">Ok</a> <a href="{0}/moved">Moved</a> <a href="{0}/gone">Gone</a>"#,
+            server.uri()
+        );
+        let text = format!("{0}/ok {0}/moved {0}/gone", server.uri());
+        let report = link_checker().check(&html, &text).await;
+
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.dead.len(), 1);
+        assert_eq!(report.dead[0].url, format!("{}/gone", server.uri()));
+        assert_eq!(report.dead[0].status, Some(404));
+        assert_eq!(report.redirects.len(), 1);
+        assert_eq!(report.redirects[0].location, format!("{}/ok", server.uri()));
+        assert!(report.only_in_html.is_empty() && report.only_in_text.is_empty());
+    }
+
+    #[tokio::test]
+    async fn links_are_retried_with_get_when_head_is_not_allowed() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let link = format!("{}/page", server.uri());
+        let report = link_checker().check(&link, &link).await;
+
+        assert!(!report.has_dead_links());
+    }
+
+    #[tokio::test]
+    async fn slow_links_are_reported_as_dead() {
+        let server = MockServer::start().await;
+        Mock::given(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+            .mount(&server)
+            .await;
+
+        let link = format!("{}/slow", server.uri());
+        let report = link_checker().check(&link, &link).await;
+
+        assert_eq!(report.dead[0].error, "Timed out");
+    }
+
+    #[tokio::test]
+    async fn private_destinations_are_never_requested() {
+        let server = MockServer::start().await;
+        Mock::given(path("/internal"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let checker = LinkChecker::new(
+            Duration::from_millis(200),
+            2,
+            10,
+            DestinationPolicy::new(false),
+        );
+
+        let links = format!(
+            "{}/internal http://169.254.169.254/latest/meta-data/",
+            server.uri()
+        );
+        let report = checker.check(&links, &links).await;
+
+        assert_eq!(report.dead.len(), 2);
+        for dead in report.dead {
+            assert!(dead.error.ends_with("is not a public address."));
+        }
+    }
+
+    #[tokio::test]
+    async fn links_past_the_limit_are_reported_unchecked() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(10)
+            .mount(&server)
+            .await;
+
+        let links = (0..25)
+            .map(|i| format!("{}/{:02}", server.uri(), i))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let report = link_checker().check(&links, &links).await;
+
+        assert_eq!(report.checked, 10);
+        assert_eq!(report.unchecked.len(), 15);
+        assert_eq!(report.unchecked[0], format!("{}/10", server.uri()));
+    }
+
+    #[tokio::test]
+    async fn links_missing_from_one_part_are_reported() {
+        let report = link_checker()
+            .check(
+                r#"<a href="https://example.invalid/a">A</a>"#,
+                "https://example.invalid/b",
+            )
+            .await;
+
+        assert_eq!(report.only_in_html, vec!["https://example.invalid/a"]);
+        assert_eq!(report.only_in_text, vec!["https://example.invalid/b"]);
+    }
+}
@@ -1,11 +1,18 @@
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
 use zero2prod::{
     configuration::get_configuration,
+    issue_delivery_worker::run_worker_until_stopped,
+    scheduler::run_scheduler_until_stopped,
     startup::Application,
     telemetry::{get_subscriber, init_subscriber},
+    transactional_email_worker::run_transactional_email_worker_until_stopped,
+    webhook_delivery_worker::run_webhook_worker_until_stopped,
 };
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
+async fn main() -> Result<(), anyhow::Error> {
     // Initialize the logger
     // On the application level
     let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
@@ -13,8 +20,39 @@ async fn main() -> Result<(), std::io::Error> {
 
     let configurations = get_configuration().expect("Failed to read configuration.");
 
-    let application = Application::build(configurations).await?;
+    let application = Application::build(configurations.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configurations.clone()));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configurations.clone()));
+    let webhook_task = tokio::spawn(run_webhook_worker_until_stopped(configurations.clone()));
+    let transactional_email_task =
+        tokio::spawn(run_transactional_email_worker_until_stopped(configurations));
 
-    application.run_until_stopped().await?;
+    // The server and the background tasks live and die together.
+    tokio::select! {
+        outcome = application_task => report_exit("API", outcome),
+        outcome = worker_task => report_exit("Background worker", outcome),
+        outcome = scheduler_task => report_exit("Scheduler", outcome),
+        outcome = webhook_task => report_exit("Webhook worker", outcome),
+        outcome = transactional_email_task => report_exit("Transactional email worker", outcome),
+    };
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => tracing::info!("{} has exited", task_name),
+        Ok(Err(e)) => tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "{} failed",
+            task_name
+        ),
+        Err(e) => tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "{} task failed to complete",
+            task_name
+        ),
+    }
+}
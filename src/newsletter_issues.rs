@@ -0,0 +1,1482 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audience::{available_merge_tags, get_saved_segment, Audience, LocalSendTime, Recipient},
+    click_tracking::{ClickToken, HmacSecret},
+    domain::{
+        html_escape, CustomFieldDefinition, CustomFieldKind, MergeTemplate, Segment,
+        SubscriberEmail, SubscriberTimeZone,
+    },
+    email_pipeline::{
+        add_open_pixel, post_process_html, render_markdown_issue, rewrite_links, sanitize_html,
+        EmailContent, PostProcessError, Stripped, UtmParameters, GMAIL_CLIPPING_LIMIT,
+    },
+    routes::admin::get_custom_field_definitions,
+    routes::error_chain_fmt,
+    startup::ApplicationBaseUrl,
+    tenant::Publication,
+};
+
+// Issues are either written in Markdown, which is rendered into both parts
+// of the email, or come with hand-written HTML and plain text.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub enum IssueContent {
+    Markdown { markdown: String },
+    Html { html: String, text: String },
+}
+
+// What editors submit when they write an issue.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct IssueData {
+    pub title: String,
+    // Preview text shown next to the subject in the inbox.
+    pub preheader: Option<String>,
+    pub content: IssueContent,
+    // Name of a saved segment, the issue goes to every
+    // confirmed subscriber when it is left out.
+    pub segment: Option<String>,
+    #[serde(default)]
+    pub ab_test: Option<AbTestData>,
+    // Adds a tracking pixel to the HTML part of each delivery.
+    #[serde(default)]
+    pub track_opens: bool,
+    // Routes the links of the HTML part through signed redirects.
+    #[serde(default)]
+    pub track_clicks: bool,
+    // Added to the links of the HTML part, tracked or not.
+    pub utm: Option<UtmParameters>,
+}
+
+impl IssueData {
+    // The content and A/B test are those `render_content` made of the submission.
+    pub fn parts<'a>(&'a self, rendered: &'a RenderedContent) -> IssueParts<'a> {
+        IssueParts {
+            title: &self.title,
+            preheader: self.preheader.as_deref(),
+            content: &rendered.content,
+            segment: self.segment.as_deref(),
+            ab_test: rendered.ab_test.as_ref(),
+            tracking: IssueTracking {
+                track_opens: self.track_opens,
+                track_clicks: self.track_clicks,
+                utm: self.utm.clone(),
+            },
+        }
+    }
+}
+
+const MAX_VARIANTS: usize = 5;
+const MAX_TEST_WINDOW_MINUTES: i32 = 7 * 24 * 60;
+
+// Variants are sent to a random slice of the audience,
+// the one with the best open or click rate goes to everyone else.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct AbTestData {
+    pub variants: Vec<VariantData>,
+    // Percentage of the audience the variants are tested on.
+    pub test_share: i16,
+    pub metric: AbTestMetric,
+    // How long opens or clicks are counted before the winner is picked.
+    pub window_minutes: i32,
+}
+
+// The title and content of the issue are used for whatever a variant leaves out.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct VariantData {
+    pub title: Option<String>,
+    pub content: Option<IssueContent>,
+}
+
+impl AbTestData {
+    fn validate(&self) -> Result<(), String> {
+        if !(2..=MAX_VARIANTS).contains(&self.variants.len()) {
+            return Err(format!(
+                "An A/B test has between 2 and {} variants.",
+                MAX_VARIANTS
+            ));
+        }
+        if !(1..=99).contains(&self.test_share) {
+            return Err("The test slice is between 1 and 99 percent of the audience.".into());
+        }
+        if !(1..=MAX_TEST_WINDOW_MINUTES).contains(&self.window_minutes) {
+            return Err(format!(
+                "An A/B test runs between 1 and {} minutes.",
+                MAX_TEST_WINDOW_MINUTES
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AbTestMetric {
+    Opens,
+    Clicks,
+}
+
+impl AbTestMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AbTestMetric::Opens => "opens",
+            AbTestMetric::Clicks => "clicks",
+        }
+    }
+
+    // The kind of delivery event counted for the metric.
+    pub fn event_kind(&self) -> &'static str {
+        match self {
+            AbTestMetric::Opens => "open",
+            AbTestMetric::Clicks => "click",
+        }
+    }
+}
+
+impl TryFrom<String> for AbTestMetric {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "opens" => Ok(AbTestMetric::Opens),
+            "clicks" => Ok(AbTestMetric::Clicks),
+            other => anyhow::bail!("{} is not a known A/B test metric.", other),
+        }
+    }
+}
+
+pub struct AbTest {
+    pub variants: Vec<Variant>,
+    pub test_share: i16,
+    pub metric: AbTestMetric,
+    pub window_minutes: i32,
+    // Set once the variants went out to the test slice.
+    pub decides_at: Option<DateTime<Utc>>,
+    pub winning_variant: Option<i16>,
+}
+
+pub struct Variant {
+    pub title: String,
+    pub markdown: Option<String>,
+    pub content: EmailContent,
+}
+
+// The content of an issue as it is stored: rendered, sanitized,
+// with merge tags still in place.
+pub struct RenderedContent {
+    pub markdown: Option<String>,
+    pub content: EmailContent,
+    // What the sanitizer removed, reported back to the editor.
+    pub stripped: Vec<Stripped>,
+    pub ab_test: Option<AbTest>,
+}
+
+pub fn render_content(
+    publication: &Publication,
+    data: &IssueData,
+) -> Result<RenderedContent, IssueError> {
+    let (markdown, content, mut stripped) = render_body(publication, &data.title, &data.content)?;
+    let ab_test = match &data.ab_test {
+        Some(ab_test) => {
+            ab_test.validate().map_err(IssueError::ValidationError)?;
+            if ab_test.metric == AbTestMetric::Opens && !data.track_opens {
+                return Err(IssueError::ValidationError(
+                    "An A/B test on opens needs open tracking.".into(),
+                ));
+            }
+            if ab_test.metric == AbTestMetric::Clicks && !data.track_clicks {
+                return Err(IssueError::ValidationError(
+                    "An A/B test on clicks needs click tracking.".into(),
+                ));
+            }
+            let mut variants = Vec::with_capacity(ab_test.variants.len());
+            for variant in &ab_test.variants {
+                let title = variant.title.as_ref().unwrap_or(&data.title);
+                let (markdown, content, variant_stripped) = render_body(
+                    publication,
+                    title,
+                    variant.content.as_ref().unwrap_or(&data.content),
+                )?;
+                if variant.content.is_some() {
+                    stripped.extend(variant_stripped);
+                }
+                variants.push(Variant {
+                    title: title.clone(),
+                    markdown,
+                    content,
+                });
+            }
+            Some(AbTest {
+                variants,
+                test_share: ab_test.test_share,
+                metric: ab_test.metric,
+                window_minutes: ab_test.window_minutes,
+                decides_at: None,
+                winning_variant: None,
+            })
+        }
+        None => None,
+    };
+
+    Ok(RenderedContent {
+        markdown,
+        content,
+        stripped,
+        ab_test,
+    })
+}
+
+fn render_body(
+    publication: &Publication,
+    title: &str,
+    content: &IssueContent,
+) -> Result<(Option<String>, EmailContent, Vec<Stripped>), anyhow::Error> {
+    match content {
+        IssueContent::Markdown { markdown } => {
+            let (content, stripped) = render_markdown_issue(&publication.name, title, markdown)?;
+            Ok((Some(markdown.clone()), content, stripped))
+        }
+        IssueContent::Html { html, text } => {
+            let sanitized = sanitize_html(html)?;
+            let content = EmailContent {
+                html: sanitized.html,
+                text: text.clone(),
+            };
+            Ok((None, content, sanitized.stripped))
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum IssueError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// Identifies a delivery in its open pixel and click redirects.
+pub const TRACKING_TOKEN_LENGTH: usize = 25;
+// Names and text fields are at most 256 characters.
+const LONGEST_TEXT_VALUE: usize = 256;
+// The longest address RFC 5321 allows.
+const LONGEST_EMAIL: usize = 254;
+// Merge values are escaped in the HTML part, `"` becomes `&quot;`:
+// no character takes more bytes, escaped or in UTF-8.
+const LARGEST_CHARACTER: &str = "\"";
+
+// What is added to the HTML part of every delivery of an issue.
+#[derive(Debug, Clone, Default)]
+pub struct IssueTracking {
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    pub utm: Option<UtmParameters>,
+}
+
+impl IssueTracking {
+    pub fn needs_token(&self) -> bool {
+        self.track_opens || self.track_clicks
+    }
+
+    // `publication_url` is the base url of the publication the issue is sent for.
+    pub fn apply(
+        &self,
+        html: &str,
+        publication_url: &str,
+        tracking_token: Option<&str>,
+        hmac_secret: &HmacSecret,
+    ) -> Result<String, anyhow::Error> {
+        let mut html = html.to_string();
+        if self.track_clicks || self.utm.is_some() {
+            // Unsubscribing and managing preferences are no clicks on the content.
+            let own_links = format!("{}/subscriptions/", publication_url);
+            html = rewrite_links(&html, |url| {
+                if url.starts_with(&own_links) {
+                    return None;
+                }
+                let url = match &self.utm {
+                    Some(utm) => utm.apply(url),
+                    None => url.to_string(),
+                };
+                match tracking_token.filter(|_| self.track_clicks) {
+                    Some(tracking_token) => {
+                        let click = ClickToken {
+                            tracking_token: tracking_token.to_string(),
+                            url,
+                        };
+                        Some(format!("{}/r/{}", publication_url, click.sign(hmac_secret)))
+                    }
+                    None => Some(url),
+                }
+            })?;
+        }
+        if let Some(tracking_token) = tracking_token.filter(|_| self.track_opens) {
+            let pixel_url = format!("{}/o/{}", publication_url, tracking_token);
+            html = add_open_pixel(&html, &pixel_url);
+        }
+        Ok(html)
+    }
+}
+
+// An issue ready to be filled in for each recipient.
+pub struct IssueTemplates {
+    subject: MergeTemplate,
+    html: MergeTemplate,
+    text: MergeTemplate,
+}
+
+#[derive(Debug)]
+pub struct Email {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl IssueTemplates {
+    // Templates are checked before anything is sent,
+    // a typo in a merge tag fails the whole issue.
+    pub fn compile(
+        title: &str,
+        preheader: Option<&str>,
+        content: &EmailContent,
+        definitions: &[CustomFieldDefinition],
+    ) -> Result<Self, IssueError> {
+        let merge_tags = available_merge_tags(definitions);
+        let parse = |field: &str, template: &str| {
+            MergeTemplate::parse(template, &merge_tags)
+                .map_err(|e| IssueError::ValidationError(format!("Invalid {}: {}", field, e)))
+        };
+        let html = post_process_html(&content.html, preheader).map_err(|e| match e {
+            PostProcessError::TooLarge { .. } => IssueError::ValidationError(e.to_string()),
+            PostProcessError::UnexpectedError(e) => IssueError::UnexpectedError(e),
+        })?;
+
+        Ok(Self {
+            subject: parse("title", title)?,
+            html: parse("HTML content", &html)?,
+            text: parse("text content", &content.text)?,
+        })
+    }
+
+    // Gmail clips what is sent, not what was written: the HTML part is checked
+    // with every merge tag at its longest and the tracking of the issue added.
+    // `publication_url` is the base url of the publication the issue is sent for.
+    pub fn check_size(
+        &self,
+        definitions: &[CustomFieldDefinition],
+        tracking: &IssueTracking,
+        publication_url: &str,
+    ) -> Result<(), IssueError> {
+        let longest_value = |tag: &str| match tag {
+            "name" => Some(LARGEST_CHARACTER.repeat(LONGEST_TEXT_VALUE)),
+            "email" => Some(LARGEST_CHARACTER.repeat(LONGEST_EMAIL)),
+            "unsubscribe_url" => Some(format!(
+                "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+                publication_url,
+                "x".repeat(TRACKING_TOKEN_LENGTH)
+            )),
+            key => {
+                let definition = definitions.iter().find(|d| d.key == key)?;
+                Some(match definition.kind {
+                    CustomFieldKind::Text => LARGEST_CHARACTER.repeat(LONGEST_TEXT_VALUE),
+                    CustomFieldKind::Enum => definition
+                        .options
+                        .iter()
+                        .max_by_key(|o| html_escape(o).len())?
+                        .clone(),
+                    // Longer than any float or date is written.
+                    CustomFieldKind::Number | CustomFieldKind::Date => "0".repeat(32),
+                    CustomFieldKind::Boolean => "false".to_string(),
+                })
+            }
+        };
+        let html = self.html.render(longest_value, true);
+        // Signatures have the same length whatever the key.
+        let secret = HmacSecret(Secret::new(String::new()));
+        let tracking_token = "x".repeat(TRACKING_TOKEN_LENGTH);
+        let html = tracking
+            .apply(&html, publication_url, Some(&tracking_token), &secret)
+            .map_err(IssueError::UnexpectedError)?;
+        if html.len() > GMAIL_CLIPPING_LIMIT {
+            return Err(IssueError::ValidationError(format!(
+                "The HTML content can reach {} bytes once personalised and tracked, \
+                Gmail clips emails over {} bytes.",
+                html.len(),
+                GMAIL_CLIPPING_LIMIT
+            )));
+        }
+        Ok(())
+    }
+
+    // `base_url` is the base url of the publication the issue is sent for.
+    pub fn render(&self, recipient: &Recipient, base_url: &str) -> Email {
+        let lookup = |tag: &str| recipient.merge_value(tag, base_url);
+        Email {
+            subject: self.subject.render(lookup, false),
+            html: self.html.render(lookup, true),
+            text: self.text.render(lookup, false),
+        }
+    }
+}
+
+// What an issue is prepared from, whether it is stored already or not.
+pub struct IssueParts<'a> {
+    pub title: &'a str,
+    pub preheader: Option<&'a str>,
+    pub content: &'a EmailContent,
+    pub segment: Option<&'a str>,
+    pub ab_test: Option<&'a AbTest>,
+    pub tracking: IssueTracking,
+}
+
+// Everything needed to send an issue, checked up front.
+pub struct PreparedIssue {
+    pub segment: Option<Segment>,
+    pub definitions: Vec<CustomFieldDefinition>,
+    pub templates: IssueTemplates,
+    // One per variant of the A/B test, if there is one.
+    pub variants: Vec<IssueTemplates>,
+}
+
+impl PreparedIssue {
+    #[tracing::instrument(name = "Preparing a newsletter issue", skip_all)]
+    pub async fn prepare(
+        connection_pool: &PgPool,
+        publication: &Publication,
+        base_url: &ApplicationBaseUrl,
+        issue: IssueParts<'_>,
+    ) -> Result<Self, IssueError> {
+        let publication_id = publication.id;
+        let publication_url = publication.base_url(base_url);
+        let IssueParts {
+            title,
+            preheader,
+            content,
+            segment,
+            ab_test,
+            tracking,
+        } = issue;
+        let segment = match segment {
+            Some(name) => {
+                let expression = get_saved_segment(connection_pool, publication_id, name)
+                    .await
+                    .context("Failed to fetch the segment.")?
+                    .ok_or_else(|| {
+                        IssueError::ValidationError(format!("There is no segment called {}.", name))
+                    })?;
+                Some(Segment::parse(&expression).map_err(IssueError::ValidationError)?)
+            }
+            None => None,
+        };
+        let definitions = get_custom_field_definitions(connection_pool, publication_id).await?;
+        let templates = IssueTemplates::compile(title, preheader, content, &definitions)?;
+        templates.check_size(&definitions, &tracking, &publication_url)?;
+        let variants = ab_test
+            .map(|ab_test| ab_test.variants.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .enumerate()
+            .map(|(position, variant)| {
+                IssueTemplates::compile(&variant.title, preheader, &variant.content, &definitions)
+                    .and_then(|templates| {
+                        templates.check_size(&definitions, &tracking, &publication_url)?;
+                        Ok(templates)
+                    })
+                    .map_err(|e| match e {
+                        IssueError::ValidationError(e) => {
+                            IssueError::ValidationError(format!("Variant {}: {}", position, e))
+                        }
+                        e => e,
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prepared = Self {
+            segment,
+            definitions,
+            templates,
+            variants,
+        };
+        // Custom fields can be deleted after a segment was saved.
+        prepared
+            .audience(publication_id)
+            .validate()
+            .map_err(IssueError::ValidationError)?;
+        Ok(prepared)
+    }
+
+    pub fn audience(&self, publication_id: Uuid) -> Audience<'_> {
+        Audience {
+            publication_id,
+            segment: self.segment.as_ref(),
+            definitions: &self.definitions,
+        }
+    }
+}
+
+// Previews and test sends are personalised like the real thing,
+// but never carry the unsubscribe token of an actual subscriber.
+pub fn preview_recipient(sample: Option<Recipient>, email: Option<SubscriberEmail>) -> Recipient {
+    let placeholder = || SubscriberEmail::parse("subscriber@example.com".to_string());
+    let mut recipient = sample.unwrap_or_else(|| Recipient {
+        email: placeholder().expect("The placeholder address is valid."),
+        name: String::new(),
+        custom_fields: serde_json::Value::Object(Default::default()),
+        unsubscribe_token: String::new(),
+    });
+    if let Some(email) = email {
+        recipient.email = email;
+    }
+    recipient.unsubscribe_token = "preview".to_string();
+    recipient
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueStatus {
+    Draft,
+    // Waiting for the scheduler to publish it.
+    Scheduled,
+    // Handed over to the delivery queue.
+    Published,
+    // Workers leave its deliveries in the queue until it is resumed.
+    Paused,
+    // What was left in the queue was dropped.
+    Cancelled,
+}
+
+impl IssueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Published => "published",
+            IssueStatus::Paused => "paused",
+            IssueStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl TryFrom<String> for IssueStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "draft" => Ok(IssueStatus::Draft),
+            "scheduled" => Ok(IssueStatus::Scheduled),
+            "published" => Ok(IssueStatus::Published),
+            "paused" => Ok(IssueStatus::Paused),
+            "cancelled" => Ok(IssueStatus::Cancelled),
+            other => anyhow::bail!("{} is not a known issue status.", other),
+        }
+    }
+}
+
+pub struct NewsletterIssue {
+    pub id: Uuid,
+    pub status: IssueStatus,
+    pub title: String,
+    pub preheader: Option<String>,
+    pub markdown: Option<String>,
+    pub content: EmailContent,
+    pub segment: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub local_send_time: Option<LocalSendTime>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub ab_test: Option<AbTest>,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    pub utm: Option<UtmParameters>,
+}
+
+impl NewsletterIssue {
+    pub fn parts(&self) -> IssueParts<'_> {
+        IssueParts {
+            title: &self.title,
+            preheader: self.preheader.as_deref(),
+            content: &self.content,
+            segment: self.segment.as_deref(),
+            ab_test: self.ab_test.as_ref(),
+            tracking: self.tracking(),
+        }
+    }
+
+    pub fn tracking(&self) -> IssueTracking {
+        IssueTracking {
+            track_opens: self.track_opens,
+            track_clicks: self.track_clicks,
+            utm: self.utm.clone(),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(connection_pool))]
+pub async fn get_issue(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+    SELECT id, status, title, preheader, markdown_content, html_content, text_content,
+        segment, created_at, updated_at, scheduled_at, local_send_time, fallback_time_zone,
+        published_at, track_opens, track_clicks, utm_source, utm_medium, utm_campaign
+    FROM newsletter_issues
+    WHERE id = $1 AND publication_id = $2"#,
+        issue_id,
+        publication_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the newsletter issue.")?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let local_send_time = match (row.local_send_time, row.fallback_time_zone) {
+        (Some(local_time), Some(fallback_time_zone)) => Some(LocalSendTime {
+            local_time,
+            fallback_time_zone: SubscriberTimeZone::parse(fallback_time_zone)
+                .map_err(anyhow::Error::msg)?,
+        }),
+        _ => None,
+    };
+    let utm = UtmParameters {
+        source: row.utm_source,
+        medium: row.utm_medium,
+        campaign: row.utm_campaign,
+    };
+    Ok(Some(NewsletterIssue {
+        id: row.id,
+        status: row.status.try_into()?,
+        title: row.title,
+        preheader: row.preheader,
+        markdown: row.markdown_content,
+        content: EmailContent {
+            html: row.html_content,
+            text: row.text_content,
+        },
+        segment: row.segment,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+        scheduled_at: row.scheduled_at,
+        local_send_time,
+        published_at: row.published_at,
+        ab_test: get_ab_test(connection_pool, issue_id).await?,
+        track_opens: row.track_opens,
+        track_clicks: row.track_clicks,
+        utm: (utm != UtmParameters::default()).then_some(utm),
+    }))
+}
+
+async fn get_ab_test(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<AbTest>, anyhow::Error> {
+    let Some(ab_test) = sqlx::query!(
+        r#"
+    SELECT test_share, metric, window_minutes, decides_at, winning_variant
+    FROM ab_tests
+    WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the A/B test of the issue.")?
+    else {
+        return Ok(None);
+    };
+    let variants = sqlx::query!(
+        r#"
+    SELECT title, markdown_content, html_content, text_content
+    FROM issue_variants
+    WHERE newsletter_issue_id = $1
+    ORDER BY position"#,
+        issue_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the variants of the issue.")?
+    .into_iter()
+    .map(|row| Variant {
+        title: row.title,
+        markdown: row.markdown_content,
+        content: EmailContent {
+            html: row.html_content,
+            text: row.text_content,
+        },
+    })
+    .collect();
+
+    Ok(Some(AbTest {
+        variants,
+        test_share: ab_test.test_share,
+        metric: ab_test.metric.try_into()?,
+        window_minutes: ab_test.window_minutes,
+        decides_at: ab_test.decides_at,
+        winning_variant: ab_test.winning_variant,
+    }))
+}
+
+// Replaces the A/B test of an issue with the one it was last saved with.
+async fn store_ab_test(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    ab_test: Option<&AbTest>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM issue_variants WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM ab_tests WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    let Some(ab_test) = ab_test else {
+        return Ok(());
+    };
+
+    sqlx::query!(
+        r#"
+    INSERT INTO ab_tests (newsletter_issue_id, test_share, metric, window_minutes)
+    VALUES ($1, $2, $3, $4)"#,
+        issue_id,
+        ab_test.test_share,
+        ab_test.metric.as_str(),
+        ab_test.window_minutes
+    )
+    .execute(&mut **transaction)
+    .await?;
+    for (position, variant) in ab_test.variants.iter().enumerate() {
+        sqlx::query!(
+            r#"
+    INSERT INTO issue_variants (
+        newsletter_issue_id, position, title, markdown_content, html_content, text_content
+    )
+    VALUES ($1, $2, $3, $4, $5, $6)"#,
+            issue_id,
+            position as i16,
+            variant.title,
+            variant.markdown,
+            variant.content.html,
+            variant.content.text
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Storing a newsletter issue", skip_all)]
+pub async fn insert_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    publication_id: Uuid,
+    status: IssueStatus,
+    data: &IssueData,
+    rendered: &RenderedContent,
+) -> Result<Uuid, sqlx::Error> {
+    let issue_id = Uuid::new_v4();
+    let now = Utc::now();
+    let published_at = (status == IssueStatus::Published).then_some(now);
+    let utm = data.utm.clone().unwrap_or_default();
+    sqlx::query!(
+        r#"
+    INSERT INTO newsletter_issues (
+        id, publication_id, status, title, preheader, markdown_content,
+        html_content, text_content, segment, created_at, updated_at, published_at,
+        track_opens, track_clicks, utm_source, utm_medium, utm_campaign
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $10, $11, $12, $13, $14, $15, $16)"#,
+        issue_id,
+        publication_id,
+        status.as_str(),
+        data.title,
+        data.preheader,
+        rendered.markdown,
+        rendered.content.html,
+        rendered.content.text,
+        data.segment,
+        now,
+        published_at,
+        data.track_opens,
+        data.track_clicks,
+        utm.source,
+        utm.medium,
+        utm.campaign
+    )
+    .execute(&mut **transaction)
+    .await?;
+    store_ab_test(transaction, issue_id, rendered.ab_test.as_ref()).await?;
+
+    Ok(issue_id)
+}
+
+// Only drafts can change. Returns false when the issue is not a draft (anymore).
+#[tracing::instrument(name = "Updating a draft issue", skip(connection_pool, data, rendered))]
+pub async fn update_draft(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+    data: &IssueData,
+    rendered: &RenderedContent,
+) -> Result<bool, sqlx::Error> {
+    let utm = data.utm.clone().unwrap_or_default();
+    let mut transaction = connection_pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET title = $3, preheader = $4, markdown_content = $5, html_content = $6,
+        text_content = $7, segment = $8, updated_at = $9, track_opens = $10,
+        track_clicks = $11, utm_source = $12, utm_medium = $13, utm_campaign = $14
+    WHERE id = $1 AND publication_id = $2 AND status = 'draft'"#,
+        issue_id,
+        publication_id,
+        data.title,
+        data.preheader,
+        rendered.markdown,
+        rendered.content.html,
+        rendered.content.text,
+        data.segment,
+        Utc::now(),
+        data.track_opens,
+        data.track_clicks,
+        utm.source,
+        utm.medium,
+        utm.campaign
+    )
+    .execute(&mut *transaction)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    store_ab_test(&mut transaction, issue_id, rendered.ab_test.as_ref()).await?;
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+// Moves an issue to the delivery queue's side, exactly once: the row is locked
+// by the first request, the others find it published once they get to it.
+#[tracing::instrument(name = "Marking an issue as published", skip(transaction))]
+pub async fn mark_published(
+    transaction: &mut Transaction<'_, Postgres>,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let now = Utc::now();
+    let result = sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET status = 'published', published_at = $3, updated_at = $3,
+        scheduled_at = NULL, local_send_time = NULL, fallback_time_zone = NULL
+    WHERE id = $1 AND publication_id = $2 AND status IN ('draft', 'scheduled')"#,
+        issue_id,
+        publication_id,
+        now
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Queues the deliveries of an issue as it is published. Issues with an A/B test
+// go to their test slice first, the rest of the audience gets the winner later.
+#[tracing::instrument(name = "Starting the delivery of an issue", skip_all)]
+pub async fn start_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    audience: Audience<'_>,
+    issue_id: Uuid,
+    local_send_time: Option<&LocalSendTime>,
+    ab_test: Option<&AbTest>,
+) -> Result<(), anyhow::Error> {
+    let Some(ab_test) = ab_test else {
+        audience
+            .enqueue_delivery(transaction, issue_id, local_send_time, None)
+            .await?;
+        return Ok(());
+    };
+
+    audience
+        .enqueue_test_slice(transaction, issue_id, ab_test.test_share)
+        .await?;
+    // Every variant goes to the same number of subscribers, give or take one.
+    sqlx::query!(
+        r#"
+    UPDATE issue_delivery_queue
+    SET variant = numbered.variant
+    FROM (
+        SELECT subscriber_email, (row_number() OVER (ORDER BY random()) - 1) % $2 AS variant
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+    ) AS numbered
+    WHERE issue_delivery_queue.newsletter_issue_id = $1
+        AND issue_delivery_queue.subscriber_email = numbered.subscriber_email"#,
+        issue_id,
+        ab_test.variants.len() as i64
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to assign the variants of the A/B test.")?;
+    sqlx::query!(
+        "UPDATE ab_tests SET decides_at = $2 WHERE newsletter_issue_id = $1",
+        issue_id,
+        Utc::now() + Duration::minutes(ab_test.window_minutes.into())
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to start the A/B test.")?;
+    Ok(())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct VariantResult {
+    pub variant: i16,
+    // Subscribers of the test slice who got the variant.
+    pub delivered: i64,
+    // Those of them who opened, or clicked, depending on the metric of the test.
+    pub engaged: i64,
+}
+
+#[tracing::instrument(name = "Get the results of an A/B test", skip(connection_pool))]
+pub async fn get_ab_test_results(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+    metric: AbTestMetric,
+) -> Result<Vec<VariantResult>, sqlx::Error> {
+    sqlx::query_as!(
+        VariantResult,
+        r#"
+    SELECT
+        issue_variants.position AS variant,
+        COUNT(DISTINCT issue_deliveries.subscriber_email) AS "delivered!",
+        COUNT(DISTINCT delivery_events.subscriber_email) AS "engaged!"
+    FROM issue_variants
+    JOIN ab_tests ON ab_tests.newsletter_issue_id = issue_variants.newsletter_issue_id
+    LEFT JOIN issue_deliveries
+        ON issue_deliveries.newsletter_issue_id = issue_variants.newsletter_issue_id
+        AND issue_deliveries.variant = issue_variants.position
+        AND issue_deliveries.outcome = 'sent'
+        -- The rest of the audience got the winner after the test was decided.
+        AND (ab_tests.decided_at IS NULL OR issue_deliveries.delivered_at <= ab_tests.decided_at)
+    LEFT JOIN delivery_events
+        ON delivery_events.newsletter_issue_id = issue_deliveries.newsletter_issue_id
+        AND delivery_events.subscriber_email = issue_deliveries.subscriber_email
+        AND delivery_events.kind = $2
+    WHERE issue_variants.newsletter_issue_id = $1
+    GROUP BY issue_variants.position
+    ORDER BY issue_variants.position"#,
+        issue_id,
+        metric.event_kind()
+    )
+    .fetch_all(connection_pool)
+    .await
+}
+
+// The variant with the best rate wins, the first one of them on a tie.
+pub fn pick_winner(results: &[VariantResult]) -> Option<i16> {
+    // Rates are compared cross-multiplied, engaged / delivered never needs rounding.
+    let rate_cmp = |a: &VariantResult, b: &VariantResult| {
+        let rate = |r: &VariantResult| if r.delivered == 0 { 0 } else { r.engaged };
+        (i128::from(rate(a)) * i128::from(b.delivered.max(1)))
+            .cmp(&(i128::from(rate(b)) * i128::from(a.delivered.max(1))))
+    };
+    results
+        .iter()
+        .reduce(|best, result| {
+            if rate_cmp(result, best).is_gt() {
+                result
+            } else {
+                best
+            }
+        })
+        .map(|winner| winner.variant)
+}
+
+#[tracing::instrument(name = "Recording the winner of an A/B test", skip(transaction))]
+pub async fn record_winner(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    winning_variant: i16,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE ab_tests SET winning_variant = $2, decided_at = $3
+    WHERE newsletter_issue_id = $1"#,
+        issue_id,
+        winning_variant,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+// When a scheduled issue goes out.
+#[derive(Debug, Clone)]
+pub enum IssueSchedule {
+    // At the same moment for everyone.
+    At(DateTime<Utc>),
+    // In waves, as each time zone reaches the same local time.
+    LocalTime(LocalSendTime),
+}
+
+impl IssueSchedule {
+    // When the scheduler hands the issue over to the delivery queue. Waves start
+    // as soon as the first time zone on Earth, fourteen hours ahead of UTC, gets there.
+    pub fn starts_at(&self) -> DateTime<Utc> {
+        match self {
+            IssueSchedule::At(scheduled_at) => *scheduled_at,
+            IssueSchedule::LocalTime(local_send_time) => {
+                Utc.from_utc_datetime(&local_send_time.local_time) - Duration::hours(14)
+            }
+        }
+    }
+
+    fn local_send_time(&self) -> Option<&LocalSendTime> {
+        match self {
+            IssueSchedule::At(_) => None,
+            IssueSchedule::LocalTime(local_send_time) => Some(local_send_time),
+        }
+    }
+}
+
+// Drafts get scheduled, scheduled issues rescheduled.
+// Returns false once the issue was published.
+#[tracing::instrument(name = "Scheduling an issue", skip(connection_pool))]
+pub async fn schedule_issue(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+    schedule: &IssueSchedule,
+) -> Result<bool, sqlx::Error> {
+    let local_send_time = schedule.local_send_time();
+    let result = sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET status = 'scheduled', scheduled_at = $3, local_send_time = $4, fallback_time_zone = $5,
+        updated_at = $6
+    WHERE id = $1 AND publication_id = $2 AND status IN ('draft', 'scheduled')"#,
+        issue_id,
+        publication_id,
+        schedule.starts_at(),
+        local_send_time.map(|local_send_time| local_send_time.local_time),
+        local_send_time.map(|local_send_time| local_send_time.fallback_time_zone.as_ref()),
+        Utc::now()
+    )
+    .execute(connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Turns a scheduled issue back into a draft.
+// Returns false when it isn't scheduled, e.g. because the scheduler got to it first.
+#[tracing::instrument(name = "Cancelling a scheduled issue", skip(connection_pool))]
+pub async fn unschedule_issue(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET status = 'draft', updated_at = $3,
+        scheduled_at = NULL, local_send_time = NULL, fallback_time_zone = NULL
+    WHERE id = $1 AND publication_id = $2 AND status = 'scheduled'"#,
+        issue_id,
+        publication_id,
+        Utc::now()
+    )
+    .execute(connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// How far the delivery of a published issue got.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct DeliveryProgress {
+    // Subscribers who got the issue.
+    pub delivered: i64,
+    // Deliveries still in the queue.
+    pub pending: i64,
+    // Deliveries that were given up on.
+    pub failed: i64,
+    // Deliveries that failed at least once, whatever became of them.
+    pub retried: i64,
+}
+
+#[tracing::instrument(name = "Get the delivery progress of an issue", skip(connection_pool))]
+pub async fn get_delivery_progress(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<DeliveryProgress, sqlx::Error> {
+    sqlx::query_as!(
+        DeliveryProgress,
+        r#"
+    SELECT
+        (SELECT COUNT(*) FROM issue_deliveries
+            WHERE newsletter_issue_id = $1 AND outcome = 'sent') AS "delivered!",
+        (SELECT COUNT(*) FROM issue_delivery_queue WHERE newsletter_issue_id = $1) AS "pending!",
+        (SELECT COUNT(*) FROM issue_deliveries
+            WHERE newsletter_issue_id = $1 AND outcome = 'failed') AS "failed!",
+        (SELECT COUNT(*) FROM issue_deliveries WHERE newsletter_issue_id = $1 AND n_retries > 0)
+        + (SELECT COUNT(*) FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1 AND n_retries > 0) AS "retried!"
+    "#,
+        issue_id
+    )
+    .fetch_one(connection_pool)
+    .await
+}
+
+// Where the A/B test of a published issue stands.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AbTestStatus {
+    pub decides_at: Option<DateTime<Utc>>,
+    pub winning_variant: Option<i16>,
+}
+
+#[tracing::instrument(name = "Get the status of an A/B test", skip(connection_pool))]
+pub async fn get_ab_test_status(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<AbTestStatus>, sqlx::Error> {
+    sqlx::query_as!(
+        AbTestStatus,
+        "SELECT decides_at, winning_variant FROM ab_tests WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+}
+
+// Opens of an issue, as recorded by its tracking pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenStats {
+    pub delivered: i64,
+    // Subscribers who opened the issue at least once.
+    pub unique_opens: i64,
+    pub total_opens: i64,
+    // Pixels fetched by mail proxies rather than by a reader.
+    pub prefetches: i64,
+}
+
+impl OpenStats {
+    // Share of the delivered emails that were opened.
+    pub fn open_rate(&self) -> f64 {
+        if self.delivered == 0 {
+            return 0.0;
+        }
+        self.unique_opens as f64 / self.delivered as f64
+    }
+}
+
+#[tracing::instrument(name = "Get the open statistics of an issue", skip(connection_pool))]
+pub async fn get_open_stats(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<OpenStats, sqlx::Error> {
+    sqlx::query_as!(
+        OpenStats,
+        r#"
+    SELECT
+        (SELECT COUNT(*) FROM issue_deliveries
+            WHERE newsletter_issue_id = $1 AND outcome = 'sent') AS "delivered!",
+        (SELECT COUNT(DISTINCT subscriber_email) FROM delivery_events
+            WHERE newsletter_issue_id = $1 AND kind = 'open') AS "unique_opens!",
+        (SELECT COUNT(*) FROM delivery_events
+            WHERE newsletter_issue_id = $1 AND kind = 'open') AS "total_opens!",
+        (SELECT COUNT(*) FROM delivery_events
+            WHERE newsletter_issue_id = $1 AND kind = 'prefetch') AS "prefetches!"
+    "#,
+        issue_id
+    )
+    .fetch_one(connection_pool)
+    .await
+}
+
+// Clicks on the links of an issue, as recorded by its redirects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClickStats {
+    pub delivered: i64,
+    // Subscribers who clicked at least one link.
+    pub unique_clicks: i64,
+    pub total_clicks: i64,
+    // Most clicked first.
+    pub links: Vec<LinkClicks>,
+}
+
+impl ClickStats {
+    // Share of the delivered emails with at least one click.
+    pub fn click_rate(&self) -> f64 {
+        if self.delivered == 0 {
+            return 0.0;
+        }
+        self.unique_clicks as f64 / self.delivered as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct LinkClicks {
+    pub url: String,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+}
+
+#[tracing::instrument(name = "Get the click statistics of an issue", skip(connection_pool))]
+pub async fn get_click_stats(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<ClickStats, sqlx::Error> {
+    let totals = sqlx::query!(
+        r#"
+    SELECT
+        (SELECT COUNT(*) FROM issue_deliveries
+            WHERE newsletter_issue_id = $1 AND outcome = 'sent') AS "delivered!",
+        (SELECT COUNT(DISTINCT subscriber_email) FROM delivery_events
+            WHERE newsletter_issue_id = $1 AND kind = 'click') AS "unique_clicks!",
+        (SELECT COUNT(*) FROM delivery_events
+            WHERE newsletter_issue_id = $1 AND kind = 'click') AS "total_clicks!"
+    "#,
+        issue_id
+    )
+    .fetch_one(connection_pool)
+    .await?;
+    let links = sqlx::query_as!(
+        LinkClicks,
+        r#"
+    SELECT url AS "url!", COUNT(*) AS "clicks!",
+        COUNT(DISTINCT subscriber_email) AS "unique_clicks!"
+    FROM delivery_events
+    WHERE newsletter_issue_id = $1 AND kind = 'click' AND url IS NOT NULL
+    GROUP BY url
+    ORDER BY 2 DESC, url"#,
+        issue_id
+    )
+    .fetch_all(connection_pool)
+    .await?;
+
+    Ok(ClickStats {
+        delivered: totals.delivered,
+        unique_clicks: totals.unique_clicks,
+        total_clicks: totals.total_clicks,
+        links,
+    })
+}
+
+#[tracing::instrument(name = "Get the status of an issue", skip(connection_pool))]
+pub async fn get_issue_status(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<Option<IssueStatus>, anyhow::Error> {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE id = $1 AND publication_id = $2",
+        issue_id,
+        publication_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the status of the issue.")?
+    .map(|row| IssueStatus::try_from(row.status))
+    .transpose()
+}
+
+// Stops workers from picking up the remaining deliveries of an issue.
+// Returns false when there is nothing left to pause, A/B tests waiting
+// for their winner can be paused too.
+#[tracing::instrument(name = "Pausing the delivery of an issue", skip(connection_pool))]
+pub async fn pause_delivery(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET status = 'paused', updated_at = $3
+    WHERE id = $1 AND publication_id = $2 AND status = 'published'
+        AND (
+            EXISTS (SELECT 1 FROM issue_delivery_queue WHERE newsletter_issue_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM ab_tests
+                WHERE newsletter_issue_id = $1 AND winning_variant IS NULL
+            )
+        )"#,
+        issue_id,
+        publication_id,
+        Utc::now()
+    )
+    .execute(connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(name = "Resuming the delivery of an issue", skip(connection_pool))]
+pub async fn resume_delivery(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET status = 'published', updated_at = $3
+    WHERE id = $1 AND publication_id = $2 AND status = 'paused'"#,
+        issue_id,
+        publication_id,
+        Utc::now()
+    )
+    .execute(connection_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Drops the remaining deliveries of a published or paused issue.
+// Emails being sent right now still go out, the queue entries
+// stay locked until their worker is done with them.
+#[tracing::instrument(name = "Cancelling the delivery of an issue", skip(transaction))]
+pub async fn cancel_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET status = 'cancelled', updated_at = $3
+    WHERE id = $1 AND publication_id = $2 AND status IN ('published', 'paused')
+        AND (
+            EXISTS (SELECT 1 FROM issue_delivery_queue WHERE newsletter_issue_id = $1)
+            OR EXISTS (
+                SELECT 1 FROM ab_tests
+                WHERE newsletter_issue_id = $1 AND winning_variant IS NULL
+            )
+        )"#,
+        issue_id,
+        publication_id,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick_winner, IssueTemplates, IssueTracking, VariantResult};
+    use crate::email_pipeline::{EmailContent, GMAIL_CLIPPING_LIMIT};
+    use claims::{assert_err, assert_none, assert_ok, assert_some_eq};
+
+    const PUBLICATION_URL: &str = "https://newsletter.example.com";
+
+    fn result(variant: i16, delivered: i64, engaged: i64) -> VariantResult {
+        VariantResult {
+            variant,
+            delivered,
+            engaged,
+        }
+    }
+
+    #[test]
+    fn the_variant_with_the_best_rate_wins() {
+        let results = vec![result(0, 100, 10), result(1, 50, 6), result(2, 100, 11)];
+        assert_some_eq!(pick_winner(&results), 1);
+    }
+
+    #[test]
+    fn ties_go_to_the_first_variant() {
+        let results = vec![result(0, 10, 1), result(1, 20, 2)];
+        assert_some_eq!(pick_winner(&results), 0);
+    }
+
+    #[test]
+    fn variants_nobody_got_count_as_never_engaging() {
+        let results = vec![result(0, 0, 0), result(1, 10, 0)];
+        assert_some_eq!(pick_winner(&results), 0);
+        let results = vec![result(0, 10, 0), result(1, 0, 0), result(2, 10, 1)];
+        assert_some_eq!(pick_winner(&results), 2);
+    }
+
+    #[test]
+    fn there_is_no_winner_without_variants() {
+        assert_none!(pick_winner(&[]));
+    }
+
+    // Just under the limit as written, and made of links.
+    fn templates_near_the_limit() -> IssueTemplates {
+        let link = r#"<a href="https://example.com/">Link</a>"#;
+        let html = link.repeat((GMAIL_CLIPPING_LIMIT - 2048) / link.len());
+        let content = EmailContent {
+            html,
+            text: "Text".into(),
+        };
+        IssueTemplates::compile("Title", None, &content, &[]).unwrap()
+    }
+
+    #[test]
+    fn the_size_is_checked_with_the_tracking_added() {
+        let templates = templates_near_the_limit();
+
+        assert_ok!(templates.check_size(&[], &IssueTracking::default(), PUBLICATION_URL));
+        let tracking = IssueTracking {
+            track_clicks: true,
+            ..IssueTracking::default()
+        };
+        assert_err!(templates.check_size(&[], &tracking, PUBLICATION_URL));
+    }
+
+    #[test]
+    fn the_size_is_checked_with_the_longest_merge_values() {
+        let tag = "<p>{{ name }}</p>";
+        let content = EmailContent {
+            html: tag.repeat(200),
+            text: "Text".into(),
+        };
+        let templates = IssueTemplates::compile("Title", None, &content, &[]).unwrap();
+
+        assert_err!(templates.check_size(&[], &IssueTracking::default(), PUBLICATION_URL));
+    }
+
+    #[test]
+    fn merge_values_are_measured_once_escaped() {
+        // Under the limit with 4 bytes a character, over it with 6.
+        let content = EmailContent {
+            html: "{{ name }}".repeat(GMAIL_CLIPPING_LIMIT / (256 * 5)),
+            text: "Text".into(),
+        };
+        let templates = IssueTemplates::compile("Title", None, &content, &[]).unwrap();
+
+        assert_err!(templates.check_size(&[], &IssueTracking::default(), PUBLICATION_URL));
+    }
+}
@@ -0,0 +1,160 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Resolve, Resolving},
+    ClientBuilder, Url,
+};
+
+// Tenants choose where the service sends requests, the links of their issues
+// and their webhook endpoints. Those requests must not reach the network the
+// service runs in: loopback, private, link-local and unique-local addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct DestinationPolicy {
+    // Only for local development and tests, where everything is on localhost.
+    allow_private: bool,
+}
+
+impl DestinationPolicy {
+    pub fn new(allow_private: bool) -> Self {
+        Self { allow_private }
+    }
+
+    pub fn allows_private(&self) -> bool {
+        self.allow_private
+    }
+
+    // Clients built from this only connect to addresses the policy allows,
+    // so a host can't resolve to another address once it was checked.
+    pub fn client_builder(&self) -> ClientBuilder {
+        let builder = ClientBuilder::new();
+        if self.allow_private {
+            builder
+        } else {
+            builder.dns_resolver(Arc::new(PublicResolver))
+        }
+    }
+
+    // Gives the reason the URL can't be requested, for the tenant to read.
+    pub async fn check(&self, url: &Url) -> Result<(), String> {
+        if self.allow_private {
+            return Ok(());
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("{} has no host.", url))?;
+        // IPv6 hosts keep their brackets in URLs.
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        let addresses: Vec<IpAddr> = match literal.parse() {
+            Ok(ip) => vec![ip],
+            Err(_) => resolve(host, url.port_or_known_default().unwrap_or_default())
+                .await
+                .map_err(|_| format!("{} can't be resolved.", host))?
+                .into_iter()
+                .map(|address| address.ip())
+                .collect(),
+        };
+        if addresses.is_empty() || !addresses.into_iter().all(is_public) {
+            return Err(format!("{} is not a public address.", host));
+        }
+        Ok(())
+    }
+}
+
+async fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>, std::io::Error> {
+    Ok(tokio::net::lookup_host((host, port)).await?.collect())
+}
+
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = resolve(name.as_str(), 0).await?;
+            if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+                return Err(format!("{} is not a public address.", name.as_str()).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "This network" and the carrier-grade NAT range.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique-local fc00::/7 and link-local fe80::/10.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} is public", ip);
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{} is not public", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn urls_pointing_inside_are_rejected() {
+        let policy = DestinationPolicy::new(false);
+        for url in [
+            "http://127.0.0.1:8000/admin",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+            "http://localhost/",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert!(policy.check(&url).await.is_err(), "{} was allowed", url);
+        }
+        assert!(DestinationPolicy::new(true)
+            .check(&Url::parse("http://127.0.0.1/").unwrap())
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,143 @@
+use actix_web::{
+    body::{self, BoxBody, EitherBody, MessageBody},
+    dev::ServiceResponse,
+    http::header::{self, HeaderValue},
+    middleware::ErrorHandlerResponse,
+    HttpMessage, HttpResponse, ResponseError,
+};
+use reqwest::StatusCode;
+use tracing_actix_web::RequestId;
+use utoipa::ToSchema;
+
+use crate::domain::ValidationErrors;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+// Every error is answered with a problem details document (RFC 7807),
+// so clients have one shape to handle whatever went wrong.
+#[derive(serde::Serialize, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    // Left out for unexpected errors, what went wrong stays in the logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    // What is wrong with each invalid field of the request, by field name.
+    #[serde(skip_serializing_if = "ValidationErrors::is_empty")]
+    #[schema(value_type = std::collections::BTreeMap<String, FieldError>)]
+    errors: ValidationErrors,
+    // Set on the way out, to find the request in the logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    // Members specific to one kind of problem, e.g. the link report of an issue.
+    #[serde(flatten)]
+    extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+// How a `ValidationError` of the client crate is documented.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct FieldError {
+    // One of `empty`, `too_long`, `forbidden_character`, `invalid_format` or `unknown_field`.
+    #[schema(example = "too_long")]
+    code: String,
+    message: String,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, detail: Option<String>) -> Self {
+        Self {
+            problem_type: "about:blank".into(),
+            title: status.canonical_reason().unwrap_or_default().into(),
+            status: status.as_u16(),
+            detail,
+            errors: ValidationErrors::new(),
+            request_id: None,
+            extensions: serde_json::Map::new(),
+        }
+    }
+
+    // Client errors explain themselves, internal error chains are kept out.
+    pub fn from_error(error: &(impl ResponseError + ?Sized)) -> Self {
+        let status = error.status_code();
+        let detail = (!status.is_server_error()).then(|| error.to_string());
+        Self::new(status, detail)
+    }
+
+    pub fn with_errors(mut self, errors: ValidationErrors) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn with_extension(mut self, name: &str, value: serde_json::Value) -> Self {
+        self.extensions.insert(name.into(), value);
+        self
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(StatusCode::from_u16(self.status).expect("A valid status code."))
+            .content_type(PROBLEM_JSON)
+            .json(self)
+    }
+}
+
+// Error responses are turned into problem documents on their way out:
+// the request id is added to those that already are, the others, e.g.
+// from extractors, are answered with one built from their error.
+// Pages shown to a browser, like a form with its errors, are left alone.
+pub fn problem_details<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>>
+where
+    B: MessageBody + 'static,
+{
+    let request_id = res
+        .request()
+        .extensions()
+        .get::<RequestId>()
+        .map(ToString::to_string);
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default();
+    let (is_page, is_problem) = (
+        content_type.starts_with("text/html"),
+        content_type.starts_with(PROBLEM_JSON),
+    );
+
+    if is_page {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
+    if !is_problem {
+        let mut problem = match res.response().error() {
+            Some(error) => Problem::from_error(error.as_response_error()),
+            None => Problem::new(res.status(), None),
+        };
+        problem.request_id = request_id;
+        let res = res.map_body(|head, _| {
+            head.headers_mut()
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+            BoxBody::new(serde_json::to_string(&problem).expect("A serializable problem."))
+        });
+        return Ok(ErrorHandlerResponse::Response(res.map_into_right_body()));
+    }
+
+    Ok(ErrorHandlerResponse::Future(Box::pin(async move {
+        let (req, res) = res.into_parts();
+        let (res, body) = res.into_parts();
+        let body = body::to_bytes(body).await.ok().unwrap_or_default();
+        let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(mut problem)) => {
+                if let Some(request_id) = request_id {
+                    problem.insert("request_id".into(), request_id.into());
+                }
+                serde_json::Value::Object(problem).to_string().into()
+            }
+            _ => body,
+        };
+        let res = res.set_body(EitherBody::right(BoxBody::new(body)));
+        Ok(ServiceResponse::new(req, res))
+    })))
+}
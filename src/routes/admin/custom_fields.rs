@@ -0,0 +1,132 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{
+    authentication::PublicationAdmin,
+    domain::{CustomFieldDefinition, CustomFieldKind},
+};
+
+#[derive(serde::Deserialize)]
+pub struct CustomFieldData {
+    key: String,
+    label: String,
+    kind: String,
+    #[serde(default)]
+    options: Vec<String>,
+    #[serde(default)]
+    required: bool,
+}
+
+#[derive(serde::Serialize)]
+struct CustomFieldResponse {
+    key: String,
+    label: String,
+    kind: &'static str,
+    options: Vec<String>,
+    required: bool,
+}
+
+impl From<CustomFieldDefinition> for CustomFieldResponse {
+    fn from(definition: CustomFieldDefinition) -> Self {
+        Self {
+            key: definition.key,
+            label: definition.label,
+            kind: definition.kind.as_str(),
+            options: definition.options,
+            required: definition.required,
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Creating a custom field definition",
+    skip_all,
+    fields(publication = %admin.publication.slug, key = %body.key)
+)]
+pub async fn create_custom_field(
+    body: web::Json<CustomFieldData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let body = body.into_inner();
+    let kind = CustomFieldKind::try_from(body.kind).map_err(AdminError::ValidationError)?;
+    let definition =
+        CustomFieldDefinition::parse(body.key, body.label, kind, body.options, body.required)
+            .map_err(AdminError::ValidationError)?;
+
+    let result = sqlx::query!(
+        r#"
+    INSERT INTO custom_field_definitions
+        (id, publication_id, key, label, kind, options, required, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    ON CONFLICT (publication_id, key) DO NOTHING"#,
+        Uuid::new_v4(),
+        admin.publication.id,
+        definition.key,
+        definition.label,
+        definition.kind.as_str(),
+        &definition.options,
+        definition.required,
+        Utc::now()
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to store the custom field definition.")?;
+
+    if result.rows_affected() == 0 {
+        return Err(AdminError::Conflict(format!(
+            "A custom field called {} already exists.",
+            definition.key
+        )));
+    }
+
+    Ok(HttpResponse::Created().json(CustomFieldResponse::from(definition)))
+}
+
+#[tracing::instrument(
+    name = "Listing custom field definitions",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn list_custom_fields(
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let definitions = get_custom_field_definitions(&connection_pool, admin.publication.id).await?;
+    let definitions: Vec<_> = definitions
+        .into_iter()
+        .map(CustomFieldResponse::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(definitions))
+}
+
+#[tracing::instrument(name = "Get custom field definitions", skip(connection_pool))]
+pub async fn get_custom_field_definitions(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+) -> Result<Vec<CustomFieldDefinition>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+    SELECT key, label, kind, options, required FROM custom_field_definitions
+    WHERE publication_id = $1
+    ORDER BY created_at"#,
+        publication_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the custom field definitions.")?;
+
+    rows.into_iter()
+        .map(|r| {
+            let kind = CustomFieldKind::try_from(r.kind).map_err(anyhow::Error::msg)?;
+            CustomFieldDefinition::parse(r.key, r.label, kind, r.options, r.required)
+                .map_err(anyhow::Error::msg)
+                .context("A stored custom field definition is invalid.")
+        })
+        .collect()
+}
@@ -0,0 +1,137 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use super::AdminError;
+use crate::{
+    authentication::PublicationAdmin,
+    transactional_emails::{validate_template_name, EmailTemplate, TemplateError},
+};
+
+#[derive(serde::Deserialize)]
+pub struct EmailTemplatePath {
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+struct EmailTemplateResponse {
+    name: String,
+    #[serde(flatten)]
+    template: EmailTemplate,
+}
+
+impl From<TemplateError> for AdminError {
+    fn from(e: TemplateError) -> Self {
+        match e {
+            TemplateError::ValidationError(e) => AdminError::ValidationError(e),
+            TemplateError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        }
+    }
+}
+
+// Creates the template or replaces it, emails already requested keep their content.
+#[tracing::instrument(
+    name = "Saving an email template",
+    skip_all,
+    fields(publication = %admin.publication.slug, template = %path.name)
+)]
+pub async fn put_email_template(
+    path: web::Path<EmailTemplatePath>,
+    body: web::Json<EmailTemplate>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    validate_template_name(&path.name).map_err(AdminError::ValidationError)?;
+    let template = body.into_inner();
+    template.compile()?;
+
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+    INSERT INTO email_templates
+        (publication_id, name, subject, html, text, variables, created_at, updated_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+    ON CONFLICT (publication_id, name) DO UPDATE
+    SET subject = EXCLUDED.subject, html = EXCLUDED.html, text = EXCLUDED.text,
+        variables = EXCLUDED.variables, updated_at = EXCLUDED.updated_at"#,
+        admin.publication.id,
+        path.name,
+        template.subject,
+        template.html,
+        template.text,
+        &template.variables,
+        now
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to store the email template.")?;
+
+    Ok(HttpResponse::Ok().json(EmailTemplateResponse {
+        name: path.into_inner().name,
+        template,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Listing email templates",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn list_email_templates(
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let templates: Vec<EmailTemplateResponse> = sqlx::query!(
+        r#"
+    SELECT name, subject, html, text, variables
+    FROM email_templates
+    WHERE publication_id = $1
+    ORDER BY name"#,
+        admin.publication.id
+    )
+    .fetch_all(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the email templates.")?
+    .into_iter()
+    .map(|row| EmailTemplateResponse {
+        name: row.name,
+        template: EmailTemplate {
+            subject: row.subject,
+            html: row.html,
+            text: row.text,
+            variables: row.variables,
+        },
+    })
+    .collect();
+
+    Ok(HttpResponse::Ok().json(templates))
+}
+
+#[tracing::instrument(
+    name = "Deleting an email template",
+    skip_all,
+    fields(publication = %admin.publication.slug, template = %path.name)
+)]
+pub async fn delete_email_template(
+    path: web::Path<EmailTemplatePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let result = sqlx::query!(
+        "DELETE FROM email_templates WHERE publication_id = $1 AND name = $2",
+        admin.publication.id,
+        path.name
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to delete the email template.")?;
+    if result.rows_affected() == 0 {
+        return Err(AdminError::NotFound(format!(
+            "There is no email template called {}.",
+            path.name
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
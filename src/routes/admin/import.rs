@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{types::Json, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{
+    get_custom_field_definitions,
+    tags::{replace_subscriber_tags, upsert_tags},
+    AdminError,
+};
+use crate::{
+    authentication::PublicationAdmin,
+    domain::{CustomFieldDefinition, CustomFields, SubscriberEmail, SubscriberName, TagName},
+};
+
+// One parsed and validated line of an import file.
+struct ImportedSubscriber {
+    email: SubscriberEmail,
+    name: SubscriberName,
+    custom_fields: CustomFields,
+    // `None` when the file has no `tags` column,
+    // existing tags are left alone in that case.
+    tags: Option<Vec<TagName>>,
+}
+
+#[derive(serde::Serialize)]
+struct ImportSummary {
+    created: u64,
+    updated: u64,
+}
+
+// The file needs `email` and `name` columns,
+// an optional `tags` column with `;` separated tag names,
+// and any number of columns named after custom fields.
+// Nothing is imported unless every line is valid.
+#[tracing::instrument(
+    name = "Importing subscribers from CSV",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn import_subscribers(
+    body: String,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let definitions = get_custom_field_definitions(&connection_pool, admin.publication.id).await?;
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| AdminError::ValidationError(format!("Invalid CSV header: {}", e)))?
+        .clone();
+    for required in ["email", "name"] {
+        if !headers.iter().any(|header| header == required) {
+            return Err(AdminError::ValidationError(format!(
+                "The CSV file has no {} column.",
+                required
+            )));
+        }
+    }
+
+    let mut subscribers = Vec::new();
+    let mut errors = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        // Line 1 is the header.
+        let line = index + 2;
+        let parsed = record
+            .map_err(|e| e.to_string())
+            .and_then(|record| parse_record(&headers, &record, &definitions));
+        match parsed {
+            Ok(subscriber) => subscribers.push(subscriber),
+            Err(error) => errors.push(format!("line {}: {}", line, error)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(AdminError::ValidationError(errors.join("\n")));
+    }
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let mut summary = ImportSummary {
+        created: 0,
+        updated: 0,
+    };
+    for subscriber in subscribers {
+        let (subscriber_id, created) =
+            upsert_subscriber(&mut transaction, admin.publication.id, &subscriber)
+                .await
+                .context("Failed to store an imported subscriber.")?;
+        if created {
+            summary.created += 1;
+        } else {
+            summary.updated += 1;
+        }
+
+        if let Some(tags) = &subscriber.tags {
+            let tag_ids = upsert_tags(&mut transaction, admin.publication.id, tags)
+                .await
+                .context("Failed to store the tags of an imported subscriber.")?;
+            replace_subscriber_tags(&mut transaction, subscriber_id, &tag_ids)
+                .await
+                .context("Failed to assign tags to an imported subscriber.")?;
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to import subscribers.")?;
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+fn parse_record(
+    headers: &csv::StringRecord,
+    record: &csv::StringRecord,
+    definitions: &[CustomFieldDefinition],
+) -> Result<ImportedSubscriber, String> {
+    let mut email = None;
+    let mut name = None;
+    let mut tags = None;
+    let mut raw_fields = HashMap::new();
+
+    for (header, value) in headers.iter().zip(record.iter()) {
+        match header {
+            "email" => {
+                email = Some(SubscriberEmail::parse(value.to_string()).map_err(|e| e.message)?)
+            }
+            "name" => name = Some(SubscriberName::parse(value.to_string()).map_err(|e| e.message)?),
+            "tags" => {
+                tags = Some(
+                    value
+                        .split(';')
+                        .filter(|tag| !tag.trim().is_empty())
+                        .map(|tag| TagName::parse(tag.to_string()))
+                        .collect::<Result<Vec<_>, _>>()?,
+                )
+            }
+            key => {
+                raw_fields.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    Ok(ImportedSubscriber {
+        email: email.ok_or("The email is missing.")?,
+        name: name.ok_or("The name is missing.")?,
+        custom_fields: CustomFields::parse(definitions, raw_fields).map_err(|e| e.to_string())?,
+        tags,
+    })
+}
+
+// Imported lists come from an existing audience that already opted in,
+// so new subscribers are stored as confirmed.
+// Existing subscribers keep custom fields that the file does not mention.
+async fn upsert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    publication_id: Uuid,
+    subscriber: &ImportedSubscriber,
+) -> Result<(Uuid, bool), sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+    INSERT INTO subscriptions
+        (id, publication_id, email, name, subscribed_at, status, custom_fields)
+    VALUES ($1, $2, $3, $4, $5, 'confirmed', $6)
+    ON CONFLICT (publication_id, email) DO UPDATE
+    SET name = EXCLUDED.name,
+        custom_fields = subscriptions.custom_fields || EXCLUDED.custom_fields
+    RETURNING id, (xmax = 0) AS "created!""#,
+        Uuid::new_v4(),
+        publication_id,
+        subscriber.email.as_ref(),
+        subscriber.name.as_ref(),
+        Utc::now(),
+        Json(subscriber.custom_fields.as_ref()) as _,
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    Ok((row.id, row.created))
+}
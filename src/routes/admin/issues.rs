@@ -0,0 +1,675 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{
+    audience::{get_recipient_by_email, Recipient},
+    authentication::PublicationAdmin,
+    domain::SubscriberEmail,
+    email_clients::EmailClient,
+    email_pipeline::UtmParameters,
+    newsletter_issues::{
+        self, cancel_delivery, get_ab_test_results, get_ab_test_status, get_click_stats,
+        get_delivery_progress, get_issue_status, get_open_stats, insert_issue, pause_delivery,
+        preview_recipient, render_content, resume_delivery, update_draft, AbTest, AbTestMetric,
+        AbTestStatus, DeliveryProgress, IssueData, IssueError, IssueStatus, NewsletterIssue,
+        PreparedIssue,
+    },
+    startup::ApplicationBaseUrl,
+};
+
+// Test sends are for the editor and a few reviewers, not a second audience.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+impl From<IssueError> for AdminError {
+    fn from(e: IssueError) -> Self {
+        match e {
+            IssueError::ValidationError(e) => AdminError::ValidationError(e),
+            IssueError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct IssuePath {
+    pub(super) issue_id: Uuid,
+}
+
+#[derive(serde::Serialize)]
+struct IssueResponse {
+    id: Uuid,
+    status: &'static str,
+    title: String,
+    preheader: Option<String>,
+    // Only set for issues written in Markdown.
+    markdown: Option<String>,
+    html: String,
+    text: String,
+    segment: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    // When the first email goes out.
+    scheduled_at: Option<DateTime<Utc>>,
+    // Only set for issues delivered in time zone waves.
+    local_send_time: Option<NaiveDateTime>,
+    fallback_time_zone: Option<String>,
+    published_at: Option<DateTime<Utc>>,
+    ab_test: Option<AbTestResponse>,
+    track_opens: bool,
+    track_clicks: bool,
+    utm: Option<UtmParameters>,
+}
+
+#[derive(serde::Serialize)]
+struct AbTestResponse {
+    test_share: i16,
+    metric: AbTestMetric,
+    window_minutes: i32,
+    decides_at: Option<DateTime<Utc>>,
+    winning_variant: Option<i16>,
+    // With the title and content of the issue filled in where a variant left them out.
+    variants: Vec<VariantResponse>,
+}
+
+#[derive(serde::Serialize)]
+struct VariantResponse {
+    title: String,
+    markdown: Option<String>,
+    html: String,
+    text: String,
+}
+
+impl From<AbTest> for AbTestResponse {
+    fn from(ab_test: AbTest) -> Self {
+        Self {
+            test_share: ab_test.test_share,
+            metric: ab_test.metric,
+            window_minutes: ab_test.window_minutes,
+            decides_at: ab_test.decides_at,
+            winning_variant: ab_test.winning_variant,
+            variants: ab_test
+                .variants
+                .into_iter()
+                .map(|variant| VariantResponse {
+                    title: variant.title,
+                    markdown: variant.markdown,
+                    html: variant.content.html,
+                    text: variant.content.text,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<NewsletterIssue> for IssueResponse {
+    fn from(issue: NewsletterIssue) -> Self {
+        Self {
+            id: issue.id,
+            status: issue.status.as_str(),
+            title: issue.title,
+            preheader: issue.preheader,
+            markdown: issue.markdown,
+            html: issue.content.html,
+            text: issue.content.text,
+            segment: issue.segment,
+            created_at: issue.created_at,
+            updated_at: issue.updated_at,
+            scheduled_at: issue.scheduled_at,
+            local_send_time: issue
+                .local_send_time
+                .as_ref()
+                .map(|local_send_time| local_send_time.local_time),
+            fallback_time_zone: issue
+                .local_send_time
+                .map(|local_send_time| local_send_time.fallback_time_zone.as_ref().to_string()),
+            published_at: issue.published_at,
+            ab_test: issue.ab_test.map(AbTestResponse::from),
+            track_opens: issue.track_opens,
+            track_clicks: issue.track_clicks,
+            utm: issue.utm,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct IssueSummary {
+    id: Uuid,
+    status: String,
+    title: String,
+    segment: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    scheduled_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+async fn fetch_issue(
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, AdminError> {
+    newsletter_issues::get_issue(connection_pool, admin.publication.id, issue_id)
+        .await?
+        .ok_or_else(|| AdminError::NotFound(format!("There is no issue {}.", issue_id)))
+}
+
+// Scheduled issues have to be cancelled before they can be edited.
+fn not_a_draft(issue: &NewsletterIssue) -> AdminError {
+    AdminError::Conflict(format!(
+        "Only drafts can be edited, issue {} is {}.",
+        issue.id,
+        issue.status.as_str()
+    ))
+}
+
+async fn find_subscriber(
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    email: &str,
+) -> Result<Recipient, AdminError> {
+    get_recipient_by_email(connection_pool, admin.publication.id, email)
+        .await?
+        .ok_or_else(|| AdminError::NotFound(format!("There is no subscriber {}.", email)))
+}
+
+// Drafts are checked like issues about to be sent,
+// mistakes show up while writing rather than when publishing.
+async fn prepare(
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    base_url: &ApplicationBaseUrl,
+    issue: &NewsletterIssue,
+) -> Result<PreparedIssue, AdminError> {
+    Ok(
+        PreparedIssue::prepare(connection_pool, &admin.publication, base_url, issue.parts())
+            .await?,
+    )
+}
+
+#[tracing::instrument(
+    name = "Creating a draft issue",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn create_issue(
+    body: web::Json<IssueData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, AdminError> {
+    let rendered = render_content(&admin.publication, &body)?;
+    PreparedIssue::prepare(
+        &connection_pool,
+        &admin.publication,
+        &base_url,
+        body.parts(&rendered),
+    )
+    .await?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let issue_id = insert_issue(
+        &mut transaction,
+        admin.publication.id,
+        IssueStatus::Draft,
+        &body,
+        &rendered,
+    )
+    .await
+    .context("Failed to store the draft issue.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the draft issue.")?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "issue_id": issue_id,
+        "stripped": rendered.stripped,
+    })))
+}
+
+#[tracing::instrument(
+    name = "Updating a draft issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn update_issue(
+    path: web::Path<IssuePath>,
+    body: web::Json<IssueData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, AdminError> {
+    let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+    if issue.status != IssueStatus::Draft {
+        return Err(not_a_draft(&issue));
+    }
+
+    let rendered = render_content(&admin.publication, &body)?;
+    PreparedIssue::prepare(
+        &connection_pool,
+        &admin.publication,
+        &base_url,
+        body.parts(&rendered),
+    )
+    .await?;
+
+    let updated = update_draft(
+        &connection_pool,
+        admin.publication.id,
+        path.issue_id,
+        &body,
+        &rendered,
+    )
+    .await
+    .context("Failed to update the draft issue.")?;
+    // The issue could have been scheduled or published in the meantime.
+    if !updated {
+        let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+        return Err(not_a_draft(&issue));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "issue_id": path.issue_id,
+        "stripped": rendered.stripped,
+    })))
+}
+
+#[tracing::instrument(
+    name = "Listing newsletter issues",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn list_issues(
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+    SELECT id, status, title, segment, created_at, updated_at, scheduled_at, published_at
+    FROM newsletter_issues
+    WHERE publication_id = $1
+    ORDER BY updated_at DESC"#,
+        admin.publication.id
+    )
+    .fetch_all(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the newsletter issues.")?;
+
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+// Issues come back with their source,
+// so they can be edited and sent again.
+#[tracing::instrument(
+    name = "Fetching a newsletter issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn get_issue(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+    Ok(HttpResponse::Ok().json(IssueResponse::from(issue)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewQuery {
+    // Email address of the subscriber whose merge tags fill in the preview.
+    subscriber: Option<String>,
+}
+
+// The issue as a subscriber would get it. Without a subscriber,
+// the longest-standing member of the issue's audience is used.
+#[tracing::instrument(
+    name = "Previewing a newsletter issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn preview_issue(
+    path: web::Path<IssuePath>,
+    query: web::Query<PreviewQuery>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, AdminError> {
+    let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+    let prepared = prepare(&connection_pool, &admin, &base_url, &issue).await?;
+    let sample = match &query.subscriber {
+        Some(email) => Some(find_subscriber(&connection_pool, &admin, email).await?),
+        None => {
+            prepared
+                .audience(admin.publication.id)
+                .sample(&connection_pool)
+                .await?
+        }
+    };
+
+    let email = prepared.templates.render(
+        &preview_recipient(sample, None),
+        &admin.publication.base_url(&base_url),
+    );
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "subject": email.subject,
+        "html": email.html,
+        "text": email.text,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendData {
+    emails: Vec<String>,
+    // Email address of the subscriber whose merge tags fill in the test.
+    subscriber: Option<String>,
+}
+
+// Sends the issue to the given addresses only,
+// real subscribers never hear about it.
+#[tracing::instrument(
+    name = "Sending a test of a newsletter issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn send_test_issue(
+    path: web::Path<IssuePath>,
+    body: web::Json<TestSendData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, AdminError> {
+    let body = body.into_inner();
+    if body.emails.is_empty() || body.emails.len() > MAX_TEST_RECIPIENTS {
+        return Err(AdminError::ValidationError(format!(
+            "A test goes to between 1 and {} addresses.",
+            MAX_TEST_RECIPIENTS
+        )));
+    }
+    let emails = body
+        .emails
+        .into_iter()
+        .map(SubscriberEmail::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AdminError::ValidationError(e.message))?;
+
+    let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+    let prepared = prepare(&connection_pool, &admin, &base_url, &issue).await?;
+    let sample = match &body.subscriber {
+        Some(email) => Some(find_subscriber(&connection_pool, &admin, email).await?),
+        None => None,
+    };
+
+    let base_url = admin.publication.base_url(&base_url);
+    let sent = emails.len();
+    for email in emails {
+        let recipient = preview_recipient(sample.clone(), Some(email));
+        let rendered = prepared.templates.render(&recipient, &base_url);
+        email_client
+            .send_email_from(
+                admin.publication.sender.as_ref(),
+                recipient.email.clone(),
+                &format!("[Test] {}", rendered.subject),
+                &rendered.html,
+                &rendered.text,
+            )
+            .await
+            .with_context(|| format!("Failed to send a test issue to {:?}", recipient.email))?;
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "sent": sent })))
+}
+
+// What the delivery endpoints and the progress stream report.
+#[derive(serde::Serialize, PartialEq)]
+pub(super) struct DeliveryReport {
+    issue_id: Uuid,
+    status: IssueStatus,
+    #[serde(flatten)]
+    progress: DeliveryProgress,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ab_test: Option<AbTestStatus>,
+}
+
+impl DeliveryReport {
+    // Nothing is left to send, and nothing will be queued anymore.
+    pub(super) fn is_finished(&self) -> bool {
+        let winner_sent = match &self.ab_test {
+            Some(ab_test) => ab_test.winning_variant.is_some(),
+            None => true,
+        };
+        match self.status {
+            IssueStatus::Published => winner_sent && self.progress.pending == 0,
+            IssueStatus::Cancelled => self.progress.pending == 0,
+            _ => false,
+        }
+    }
+}
+
+pub(super) async fn delivery_report(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    issue_id: Uuid,
+) -> Result<DeliveryReport, AdminError> {
+    let status = get_issue_status(connection_pool, publication_id, issue_id)
+        .await?
+        .ok_or_else(|| AdminError::NotFound(format!("There is no issue {}.", issue_id)))?;
+    let progress = get_delivery_progress(connection_pool, issue_id)
+        .await
+        .context("Failed to fetch the delivery progress of the issue.")?;
+    let ab_test = get_ab_test_status(connection_pool, issue_id)
+        .await
+        .context("Failed to fetch the A/B test of the issue.")?;
+    Ok(DeliveryReport {
+        issue_id,
+        status,
+        progress,
+        ab_test,
+    })
+}
+
+async fn delivery_response(
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    issue_id: Uuid,
+) -> Result<HttpResponse, AdminError> {
+    let report = delivery_report(connection_pool, admin.publication.id, issue_id).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+async fn nothing_to(
+    action: &str,
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    issue_id: Uuid,
+) -> AdminError {
+    match fetch_issue(connection_pool, admin, issue_id).await {
+        Ok(issue) => AdminError::Conflict(format!(
+            "There is no delivery to {} for issue {}, it is {}.",
+            action,
+            issue.id,
+            issue.status.as_str()
+        )),
+        Err(e) => e,
+    }
+}
+
+#[tracing::instrument(
+    name = "Fetching the delivery progress of an issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn get_issue_delivery(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    delivery_response(&connection_pool, &admin, path.issue_id).await
+}
+
+// Emails already handed to the email provider can't be taken back,
+// the rest wait in the queue until the delivery is resumed.
+#[tracing::instrument(
+    name = "Pausing the delivery of an issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn pause_issue_delivery(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    if !pause_delivery(&connection_pool, admin.publication.id, path.issue_id)
+        .await
+        .context("Failed to pause the delivery.")?
+    {
+        return Err(nothing_to("pause", &connection_pool, &admin, path.issue_id).await);
+    }
+    delivery_response(&connection_pool, &admin, path.issue_id).await
+}
+
+#[tracing::instrument(
+    name = "Resuming the delivery of an issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn resume_issue_delivery(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    if !resume_delivery(&connection_pool, admin.publication.id, path.issue_id)
+        .await
+        .context("Failed to resume the delivery.")?
+    {
+        return Err(nothing_to("resume", &connection_pool, &admin, path.issue_id).await);
+    }
+    delivery_response(&connection_pool, &admin, path.issue_id).await
+}
+
+// Subscribers who did not get the issue yet never will.
+#[tracing::instrument(
+    name = "Cancelling the delivery of an issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn cancel_issue_delivery(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    if !cancel_delivery(&mut transaction, admin.publication.id, path.issue_id)
+        .await
+        .context("Failed to cancel the delivery.")?
+    {
+        return Err(nothing_to("cancel", &connection_pool, &admin, path.issue_id).await);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the cancelled delivery.")?;
+    delivery_response(&connection_pool, &admin, path.issue_id).await
+}
+
+// How each variant of the A/B test of an issue did so far, and which one won.
+#[tracing::instrument(
+    name = "Fetching the results of an A/B test",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn get_ab_test_results_of_issue(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+    let ab_test = issue
+        .ab_test
+        .ok_or_else(|| AdminError::NotFound(format!("Issue {} has no A/B test.", path.issue_id)))?;
+    let results = get_ab_test_results(&connection_pool, issue.id, ab_test.metric)
+        .await
+        .context("Failed to fetch the results of the A/B test.")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "issue_id": issue.id,
+        "metric": ab_test.metric,
+        "decides_at": ab_test.decides_at,
+        "winning_variant": ab_test.winning_variant,
+        "variants": results,
+    })))
+}
+
+// Unique and total opens of an issue, prefetches by mail proxies are left out of both.
+#[tracing::instrument(
+    name = "Fetching the open statistics of an issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn get_issue_opens(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+    if !issue.track_opens {
+        return Err(AdminError::NotFound(format!(
+            "Issue {} does not track opens.",
+            issue.id
+        )));
+    }
+    let stats = get_open_stats(&connection_pool, issue.id)
+        .await
+        .context("Failed to fetch the open statistics of the issue.")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "issue_id": issue.id,
+        "delivered": stats.delivered,
+        "unique_opens": stats.unique_opens,
+        "total_opens": stats.total_opens,
+        "prefetches": stats.prefetches,
+        "open_rate": stats.open_rate(),
+    })))
+}
+
+// Clicks on each link of an issue, the unsubscribe link is not tracked.
+#[tracing::instrument(
+    name = "Fetching the click statistics of an issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn get_issue_clicks(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let issue = fetch_issue(&connection_pool, &admin, path.issue_id).await?;
+    if !issue.track_clicks {
+        return Err(AdminError::NotFound(format!(
+            "Issue {} does not track clicks.",
+            issue.id
+        )));
+    }
+    let stats = get_click_stats(&connection_pool, issue.id)
+        .await
+        .context("Failed to fetch the click statistics of the issue.")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "issue_id": issue.id,
+        "delivered": stats.delivered,
+        "unique_clicks": stats.unique_clicks,
+        "total_clicks": stats.total_clicks,
+        "click_rate": stats.click_rate(),
+        "links": stats.links,
+    })))
+}
@@ -0,0 +1,61 @@
+mod custom_fields;
+mod email_templates;
+mod import;
+mod issues;
+mod progress;
+mod segments;
+mod subscribers;
+mod tags;
+mod webhooks;
+
+use actix_web::ResponseError;
+use reqwest::StatusCode;
+
+use crate::routes::error_chain_fmt;
+
+pub use custom_fields::{create_custom_field, get_custom_field_definitions, list_custom_fields};
+pub use email_templates::{delete_email_template, list_email_templates, put_email_template};
+pub use import::import_subscribers;
+pub use issues::{
+    cancel_issue_delivery, create_issue, get_ab_test_results_of_issue, get_issue, get_issue_clicks,
+    get_issue_delivery, get_issue_opens, list_issues, pause_issue_delivery, preview_issue,
+    resume_issue_delivery, send_test_issue, update_issue,
+};
+pub use progress::{issue_progress_page, stream_issue_progress};
+pub use segments::{create_segment, get_segment, list_segments, preview_segment};
+pub use subscribers::{update_subscriber_fields, update_subscriber_tags};
+pub use tags::{create_tag, list_tags, replace_subscriber_tags, upsert_tags};
+pub use webhooks::{
+    create_webhook, delete_webhook, list_webhook_deliveries, list_webhooks, replay_webhook_delivery,
+};
+
+// Shared by the admin handlers,
+// they all fail in the same handful of ways.
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AdminError::NotFound(_) => StatusCode::NOT_FOUND,
+            AdminError::Conflict(_) => StatusCode::CONFLICT,
+            AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use actix_web::{
+    http::header::{CacheControl, CacheDirective, ContentType},
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+    issues::{delivery_report, DeliveryReport, IssuePath},
+    AdminError,
+};
+use crate::{authentication::PublicationAdmin, templating::IssueProgressTemplate};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Proxies drop connections that stay silent for too long.
+const KEEP_ALIVE_POLLS: u32 = 15;
+
+struct ProgressStream {
+    connection_pool: web::Data<PgPool>,
+    publication_id: Uuid,
+    issue_id: Uuid,
+    last: Option<DeliveryReport>,
+    idle_polls: u32,
+}
+
+impl ProgressStream {
+    // Waits for the next event to send, None once the delivery is over.
+    async fn next_event(&mut self) -> Option<Bytes> {
+        if self.last.as_ref().is_some_and(DeliveryReport::is_finished) {
+            return None;
+        }
+        loop {
+            if self.last.is_some() {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+            let report =
+                match delivery_report(&self.connection_pool, self.publication_id, self.issue_id)
+                    .await
+                {
+                    Ok(report) => report,
+                    Err(e) => {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Failed to report the delivery progress. Closing the stream.",
+                        );
+                        return None;
+                    }
+                };
+
+            if self.last.as_ref() != Some(&report) {
+                let event = progress_event(&report);
+                self.last = Some(report);
+                self.idle_polls = 0;
+                return Some(event);
+            }
+            self.idle_polls += 1;
+            if self.idle_polls >= KEEP_ALIVE_POLLS {
+                self.idle_polls = 0;
+                return Some(Bytes::from_static(b": keep-alive\n\n"));
+            }
+        }
+    }
+}
+
+// A `progress` event for every change, the last one is a `done` event.
+fn progress_event(report: &DeliveryReport) -> Bytes {
+    let event = if report.is_finished() {
+        "done"
+    } else {
+        "progress"
+    };
+    let data = serde_json::to_string(report).expect("A delivery report is always serializable.");
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+// Server-Sent Events with the delivery progress of the issue,
+// the stream ends once the delivery is over.
+#[tracing::instrument(
+    name = "Streaming the delivery progress of an issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn stream_issue_progress(
+    path: web::Path<IssuePath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    // Unknown issues are a 404, not an empty stream.
+    delivery_report(&connection_pool, admin.publication.id, path.issue_id).await?;
+
+    let stream = ProgressStream {
+        connection_pool,
+        publication_id: admin.publication.id,
+        issue_id: path.issue_id,
+        last: None,
+        idle_polls: 0,
+    };
+    let events = futures_util::stream::unfold(stream, |mut stream| async move {
+        let event = stream.next_event().await?;
+        Some((Ok::<_, actix_web::Error>(event), stream))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(events))
+}
+
+// A live progress bar for the issue. Browsers can't attach the admin token
+// to page loads, the page holds no data and asks for the token to open the stream.
+#[tracing::instrument(name = "Showing the delivery progress page", skip_all)]
+pub async fn issue_progress_page(
+    path: web::Path<IssuePath>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    // Relative to wherever the publication was routed, e.g. under `/p/{publication}`.
+    let progress_url = request.path().trim_end_matches("/page").to_string();
+    let page = IssueProgressTemplate {
+        issue_id: &path.issue_id.to_string(),
+        progress_url: &progress_url,
+    }
+    .render()
+    .map_err(|e| AdminError::UnexpectedError(e.into()))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
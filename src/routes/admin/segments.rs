@@ -0,0 +1,170 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{get_custom_field_definitions, AdminError};
+use crate::{
+    audience::{get_saved_segment, Audience},
+    authentication::PublicationAdmin,
+    domain::Segment,
+};
+
+#[derive(serde::Deserialize)]
+pub struct SegmentData {
+    name: String,
+    expression: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewData {
+    expression: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SegmentPath {
+    segment: String,
+}
+
+#[derive(serde::Serialize)]
+struct SegmentResponse {
+    name: String,
+    expression: String,
+    subscribers: i64,
+}
+
+#[tracing::instrument(
+    name = "Saving a segment",
+    skip_all,
+    fields(publication = %admin.publication.slug, segment = %body.name)
+)]
+pub async fn create_segment(
+    body: web::Json<SegmentData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let SegmentData { name, expression } = body.into_inner();
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > 64 {
+        return Err(AdminError::ValidationError(format!(
+            "{} is not a valid segment name.",
+            name
+        )));
+    }
+
+    let subscribers = count_audience(&connection_pool, admin.publication.id, &expression).await?;
+
+    let result = sqlx::query!(
+        r#"
+    INSERT INTO segments (id, publication_id, name, expression, created_at)
+    VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (publication_id, name) DO NOTHING"#,
+        Uuid::new_v4(),
+        admin.publication.id,
+        name,
+        expression,
+        Utc::now()
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to store the segment.")?;
+
+    if result.rows_affected() == 0 {
+        return Err(AdminError::Conflict(format!(
+            "A segment called {} already exists.",
+            name
+        )));
+    }
+
+    Ok(HttpResponse::Created().json(SegmentResponse {
+        name,
+        expression,
+        subscribers,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Listing segments",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn list_segments(
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let segments = sqlx::query!(
+        "SELECT name, expression FROM segments WHERE publication_id = $1 ORDER BY name",
+        admin.publication.id
+    )
+    .fetch_all(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the segments.")?;
+
+    let segments: Vec<_> = segments
+        .into_iter()
+        .map(|r| serde_json::json!({"name": r.name, "expression": r.expression}))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(segments))
+}
+
+// A saved segment along with its current size.
+#[tracing::instrument(
+    name = "Previewing a saved segment",
+    skip_all,
+    fields(publication = %admin.publication.slug, segment = %path.segment)
+)]
+pub async fn get_segment(
+    path: web::Path<SegmentPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let name = path.into_inner().segment;
+    let expression = get_saved_segment(&connection_pool, admin.publication.id, &name)
+        .await
+        .context("Failed to fetch the segment.")?
+        .ok_or_else(|| AdminError::NotFound(format!("There is no segment called {}.", name)))?;
+
+    let subscribers = count_audience(&connection_pool, admin.publication.id, &expression).await?;
+
+    Ok(HttpResponse::Ok().json(SegmentResponse {
+        name,
+        expression,
+        subscribers,
+    }))
+}
+
+// Lets editors try out an expression before saving it.
+#[tracing::instrument(
+    name = "Previewing a segment expression",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn preview_segment(
+    body: web::Json<PreviewData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let subscribers =
+        count_audience(&connection_pool, admin.publication.id, &body.expression).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "subscribers": subscribers })))
+}
+
+async fn count_audience(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    expression: &str,
+) -> Result<i64, AdminError> {
+    let segment = Segment::parse(expression).map_err(AdminError::ValidationError)?;
+    let definitions = get_custom_field_definitions(connection_pool, publication_id).await?;
+    let audience = Audience {
+        publication_id,
+        segment: Some(&segment),
+        definitions: &definitions,
+    };
+    audience.validate().map_err(AdminError::ValidationError)?;
+
+    Ok(audience.count(connection_pool).await?)
+}
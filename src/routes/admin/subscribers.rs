@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::{types::Json, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{
+    get_custom_field_definitions,
+    tags::{replace_subscriber_tags, upsert_tags},
+    AdminError,
+};
+use crate::{
+    authentication::PublicationAdmin,
+    domain::{raw_custom_fields, CustomFields, TagName},
+};
+
+// Deserialised by name, the `/p/{publication}` prefix
+// adds a second segment to the path of these routes.
+#[derive(serde::Deserialize)]
+pub struct SubscriberPath {
+    subscriber_id: Uuid,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FieldsData {
+    fields: HashMap<String, serde_json::Value>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagsData {
+    tags: Vec<String>,
+}
+
+#[tracing::instrument(
+    name = "Updating the custom fields of a subscriber",
+    skip_all,
+    fields(publication = %admin.publication.slug, subscriber_id = %path.subscriber_id)
+)]
+pub async fn update_subscriber_fields(
+    path: web::Path<SubscriberPath>,
+    body: web::Json<FieldsData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let subscriber_id = path.subscriber_id;
+    let definitions = get_custom_field_definitions(&connection_pool, admin.publication.id).await?;
+    let raw = raw_custom_fields(body.into_inner().fields);
+    let fields = CustomFields::parse(&definitions, raw)
+        .map_err(|e| AdminError::ValidationError(e.to_string()))?;
+
+    let result = sqlx::query!(
+        r#"
+    UPDATE subscriptions SET custom_fields = $1
+    WHERE id = $2 AND publication_id = $3"#,
+        Json(fields.as_ref()) as _,
+        subscriber_id,
+        admin.publication.id
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to update the custom fields of a subscriber.")?;
+
+    if result.rows_affected() == 0 {
+        return Err(AdminError::NotFound(format!(
+            "There is no subscriber with id {}.",
+            subscriber_id
+        )));
+    }
+
+    Ok(HttpResponse::Ok().json(fields.as_ref()))
+}
+
+#[tracing::instrument(
+    name = "Updating the tags of a subscriber",
+    skip_all,
+    fields(publication = %admin.publication.slug, subscriber_id = %path.subscriber_id)
+)]
+pub async fn update_subscriber_tags(
+    path: web::Path<SubscriberPath>,
+    body: web::Json<TagsData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let subscriber_id = path.subscriber_id;
+    let tags = body
+        .0
+        .tags
+        .into_iter()
+        .map(TagName::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(AdminError::ValidationError)?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    if !subscriber_exists(&mut transaction, admin.publication.id, subscriber_id)
+        .await
+        .context("Failed to look up the subscriber.")?
+    {
+        return Err(AdminError::NotFound(format!(
+            "There is no subscriber with id {}.",
+            subscriber_id
+        )));
+    }
+
+    let tag_ids = upsert_tags(&mut transaction, admin.publication.id, &tags)
+        .await
+        .context("Failed to store the tags.")?;
+    replace_subscriber_tags(&mut transaction, subscriber_id, &tag_ids)
+        .await
+        .context("Failed to assign the tags to the subscriber.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update subscriber tags.")?;
+
+    let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tags": tags })))
+}
+
+async fn subscriber_exists(
+    transaction: &mut Transaction<'_, Postgres>,
+    publication_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE id = $1 AND publication_id = $2",
+        subscriber_id,
+        publication_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    Ok(row.is_some())
+}
@@ -0,0 +1,139 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{authentication::PublicationAdmin, domain::TagName};
+
+#[derive(serde::Deserialize)]
+pub struct TagData {
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+struct TagResponse {
+    name: String,
+    subscribers: i64,
+}
+
+#[tracing::instrument(
+    name = "Creating a tag",
+    skip_all,
+    fields(publication = %admin.publication.slug, tag = %body.name)
+)]
+pub async fn create_tag(
+    body: web::Json<TagData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let name = TagName::parse(body.0.name).map_err(AdminError::ValidationError)?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    upsert_tags(
+        &mut transaction,
+        admin.publication.id,
+        std::slice::from_ref(&name),
+    )
+    .await
+    .context("Failed to store the tag.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a tag.")?;
+
+    Ok(HttpResponse::Created().json(TagResponse {
+        name: name.as_ref().to_string(),
+        subscribers: 0,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Listing tags",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn list_tags(
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let tags = sqlx::query_as!(
+        TagResponse,
+        r#"
+    SELECT tags.name, COUNT(subscription_tags.subscriber_id) AS "subscribers!"
+    FROM tags
+    LEFT JOIN subscription_tags ON subscription_tags.tag_id = tags.id
+    WHERE tags.publication_id = $1
+    GROUP BY tags.id
+    ORDER BY tags.name"#,
+        admin.publication.id
+    )
+    .fetch_all(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the tags.")?;
+
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+// Tags are created on first use, so assigning a tag never fails
+// because nobody created it beforehand.
+#[tracing::instrument(name = "Storing tags", skip(transaction))]
+pub async fn upsert_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    publication_id: Uuid,
+    names: &[TagName],
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let names: Vec<String> = names.iter().map(|name| name.as_ref().to_string()).collect();
+
+    sqlx::query!(
+        r#"
+    INSERT INTO tags (id, publication_id, name, created_at)
+    SELECT gen_random_uuid(), $1, name, $3 FROM UNNEST($2::text[]) AS name
+    ON CONFLICT (publication_id, name) DO NOTHING"#,
+        publication_id,
+        &names,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    let rows = sqlx::query!(
+        "SELECT id FROM tags WHERE publication_id = $1 AND name = ANY($2)",
+        publication_id,
+        &names
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| r.id).collect())
+}
+
+#[tracing::instrument(name = "Replacing the tags of a subscriber", skip(transaction))]
+pub async fn replace_subscriber_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    tag_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM subscription_tags WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+    INSERT INTO subscription_tags (subscriber_id, tag_id)
+    SELECT $1, tag_id FROM UNNEST($2::uuid[]) AS tag_id"#,
+        subscriber_id,
+        tag_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
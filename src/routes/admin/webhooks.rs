@@ -0,0 +1,305 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use reqwest::Url;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AdminError;
+use crate::{
+    authentication::PublicationAdmin,
+    outbound::DestinationPolicy,
+    webhooks::{check_endpoint, SubscriberEvent},
+};
+
+// How much of the delivery log of an endpoint is shown, most recent first.
+const DELIVERY_LOG_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct WebhookData {
+    url: String,
+    // Every event type when left out.
+    events: Option<Vec<String>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct WebhookPath {
+    webhook_id: Uuid,
+}
+
+#[derive(serde::Deserialize)]
+pub struct WebhookDeliveryPath {
+    webhook_id: Uuid,
+    delivery_id: Uuid,
+}
+
+#[derive(serde::Serialize)]
+struct WebhookResponse {
+    id: Uuid,
+    url: String,
+    events: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryResponse {
+    id: Uuid,
+    event_id: Uuid,
+    event_type: String,
+    // pending, delivered or failed.
+    status: String,
+    n_attempts: i16,
+    created_at: DateTime<Utc>,
+    last_attempt_at: Option<DateTime<Utc>>,
+    last_response_status: Option<i16>,
+    last_error: Option<String>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+fn generate_webhook_secret() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect()
+}
+
+async fn parse_webhook(
+    data: WebhookData,
+    policy: &DestinationPolicy,
+) -> Result<(Url, Vec<String>), String> {
+    let url =
+        Url::parse(&data.url).map_err(|e| format!("{} is not a valid URL: {}", data.url, e))?;
+    check_endpoint(policy, &url).await?;
+    let events = match data.events {
+        Some(events) if events.is_empty() => {
+            return Err("A webhook listens to at least one event type.".to_string())
+        }
+        Some(events) => events
+            .iter()
+            .map(|event| SubscriberEvent::parse(event).map(|event| event.as_str().to_string()))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => SubscriberEvent::ALL
+            .iter()
+            .map(|event| event.as_str().to_string())
+            .collect(),
+    };
+    Ok((url, events))
+}
+
+// The secret signing the payloads is only ever shown here.
+#[tracing::instrument(
+    name = "Creating a webhook",
+    skip_all,
+    fields(publication = %admin.publication.slug, url = %body.url)
+)]
+pub async fn create_webhook(
+    body: web::Json<WebhookData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    destination_policy: web::Data<DestinationPolicy>,
+) -> Result<HttpResponse, AdminError> {
+    let (url, events) = parse_webhook(body.into_inner(), &destination_policy)
+        .await
+        .map_err(AdminError::ValidationError)?;
+    let id = Uuid::new_v4();
+    let secret = generate_webhook_secret();
+    let created_at = Utc::now();
+    sqlx::query!(
+        r#"
+    INSERT INTO webhook_endpoints (id, publication_id, url, secret, event_types, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6)"#,
+        id,
+        admin.publication.id,
+        url.as_str(),
+        secret,
+        &events,
+        created_at
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to store the webhook.")?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "id": id,
+        "url": url.as_str(),
+        "events": events,
+        "secret": secret,
+        "created_at": created_at,
+    })))
+}
+
+#[tracing::instrument(
+    name = "Listing webhooks",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn list_webhooks(
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let webhooks = sqlx::query_as!(
+        WebhookResponse,
+        r#"
+    SELECT id, url, event_types AS events, created_at
+    FROM webhook_endpoints
+    WHERE publication_id = $1
+    ORDER BY created_at"#,
+        admin.publication.id
+    )
+    .fetch_all(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the webhooks.")?;
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+// Events still in the queue for the endpoint are dropped with it.
+#[tracing::instrument(
+    name = "Deleting a webhook",
+    skip_all,
+    fields(publication = %admin.publication.slug, webhook_id = %path.webhook_id)
+)]
+pub async fn delete_webhook(
+    path: web::Path<WebhookPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let result = sqlx::query!(
+        "DELETE FROM webhook_endpoints WHERE id = $1 AND publication_id = $2",
+        path.webhook_id,
+        admin.publication.id
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to delete the webhook.")?;
+    if result.rows_affected() == 0 {
+        return Err(no_webhook(path.webhook_id));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(
+    name = "Fetching the delivery log of a webhook",
+    skip_all,
+    fields(publication = %admin.publication.slug, webhook_id = %path.webhook_id)
+)]
+pub async fn list_webhook_deliveries(
+    path: web::Path<WebhookPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let exists = sqlx::query!(
+        "SELECT 1 AS found FROM webhook_endpoints WHERE id = $1 AND publication_id = $2",
+        path.webhook_id,
+        admin.publication.id
+    )
+    .fetch_optional(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the webhook.")?;
+    if exists.is_none() {
+        return Err(no_webhook(path.webhook_id));
+    }
+
+    let deliveries = sqlx::query_as!(
+        DeliveryResponse,
+        r#"
+    SELECT id, event_id, event_type, status, n_attempts, created_at, last_attempt_at,
+        last_response_status, last_error, delivered_at
+    FROM webhook_deliveries
+    WHERE endpoint_id = $1
+    ORDER BY created_at DESC
+    LIMIT $2"#,
+        path.webhook_id,
+        DELIVERY_LOG_SIZE
+    )
+    .fetch_all(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the webhook deliveries.")?;
+
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+// Puts a delivery back in the queue, with the same event id, so endpoints
+// that already processed it can tell. Deliveries still in the queue can't be replayed.
+#[tracing::instrument(
+    name = "Replaying a webhook delivery",
+    skip_all,
+    fields(
+        publication = %admin.publication.slug,
+        webhook_id = %path.webhook_id,
+        delivery_id = %path.delivery_id
+    )
+)]
+pub async fn replay_webhook_delivery(
+    path: web::Path<WebhookDeliveryPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AdminError> {
+    let delivery = sqlx::query_as!(
+        DeliveryResponse,
+        r#"
+    UPDATE webhook_deliveries
+    SET status = 'pending', n_attempts = 0, execute_after = $4
+    FROM webhook_endpoints
+    WHERE webhook_deliveries.id = $1
+        AND webhook_deliveries.endpoint_id = $2
+        AND webhook_endpoints.id = webhook_deliveries.endpoint_id
+        AND webhook_endpoints.publication_id = $3
+        AND webhook_deliveries.status <> 'pending'
+    RETURNING webhook_deliveries.id, webhook_deliveries.event_id,
+        webhook_deliveries.event_type, webhook_deliveries.status,
+        webhook_deliveries.n_attempts, webhook_deliveries.created_at,
+        webhook_deliveries.last_attempt_at, webhook_deliveries.last_response_status,
+        webhook_deliveries.last_error, webhook_deliveries.delivered_at"#,
+        path.delivery_id,
+        path.webhook_id,
+        admin.publication.id,
+        Utc::now()
+    )
+    .fetch_optional(connection_pool.get_ref())
+    .await
+    .context("Failed to replay the webhook delivery.")?;
+
+    match delivery {
+        Some(delivery) => Ok(HttpResponse::Accepted().json(delivery)),
+        None => Err(nothing_to_replay(&connection_pool, &admin, &path).await?),
+    }
+}
+
+async fn nothing_to_replay(
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    path: &WebhookDeliveryPath,
+) -> Result<AdminError, AdminError> {
+    let delivery = sqlx::query!(
+        r#"
+    SELECT 1 AS found FROM webhook_deliveries
+    JOIN webhook_endpoints ON webhook_endpoints.id = webhook_deliveries.endpoint_id
+    WHERE webhook_deliveries.id = $1 AND webhook_deliveries.endpoint_id = $2
+        AND webhook_endpoints.publication_id = $3"#,
+        path.delivery_id,
+        path.webhook_id,
+        admin.publication.id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the webhook delivery.")?;
+
+    Ok(match delivery {
+        Some(_) => AdminError::Conflict(format!(
+            "Delivery {} is still in the queue.",
+            path.delivery_id
+        )),
+        None => AdminError::NotFound(format!(
+            "Webhook {} has no delivery {}.",
+            path.webhook_id, path.delivery_id
+        )),
+    })
+}
+
+fn no_webhook(webhook_id: Uuid) -> AdminError {
+    AdminError::NotFound(format!("There is no webhook {}.", webhook_id))
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::ApiError;
+use crate::{
+    authentication::PublicationAdmin,
+    domain::{SubscriberEmail, ValidationErrors},
+    suppressions::is_suppressed,
+    transactional_emails::{get_email_template, get_transactional_email, TransactionalEmailStatus},
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct SendEmailData {
+    template: String,
+    to: String,
+    #[serde(default)]
+    variables: HashMap<String, serde_json::Value>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct EmailPath {
+    message_id: Uuid,
+}
+
+// Receipts carry amounts and dates, numbers and booleans are taken as they are written.
+fn variable_values(
+    variables: HashMap<String, serde_json::Value>,
+) -> Result<HashMap<String, String>, String> {
+    variables
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => Ok((name, value)),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                Ok((name, value.to_string()))
+            }
+            serde_json::Value::Null => Ok((name, String::new())),
+            _ => Err(format!(
+                "The value of {} has to be a string or a number.",
+                name
+            )),
+        })
+        .collect()
+}
+
+// Renders the template right away and queues the email, callers follow
+// up on the message id. Suppressed addresses are recorded but never sent to.
+#[utoipa::path(
+    post,
+    path = "/emails",
+    tag = "v1",
+    request_body = SendEmailData,
+    responses(
+        (status = 202, description = "The email is queued, or recorded as suppressed. \
+            The body holds its `id` and `status`."),
+        ApiError
+    ),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Sending a transactional email",
+    skip_all,
+    fields(publication = %admin.publication.slug, template = %body.template, message_id = tracing::field::Empty)
+)]
+pub async fn send_email(
+    body: web::Json<SendEmailData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let SendEmailData {
+        template: template_name,
+        to,
+        variables,
+    } = body.into_inner();
+    let recipient = SubscriberEmail::parse(to)
+        .map_err(|e| ApiError::InvalidFields(ValidationErrors::single("to", e)))?;
+    let values = variable_values(variables).map_err(ApiError::ValidationError)?;
+    let template = get_email_template(&connection_pool, admin.publication.id, &template_name)
+        .await
+        .context("Failed to fetch the email template.")?
+        .ok_or_else(|| {
+            ApiError::ValidationError(format!(
+                "There is no email template called {}.",
+                template_name
+            ))
+        })?;
+    let email = template.compile()?.render(&values)?;
+
+    let status = if is_suppressed(&connection_pool, recipient.as_ref())
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        "suppressed"
+    } else {
+        "queued"
+    };
+    let id = Uuid::new_v4();
+    tracing::Span::current().record("message_id", tracing::field::display(id));
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+    INSERT INTO transactional_emails
+        (id, publication_id, template_name, recipient, subject, html, text,
+        status, created_at, execute_after)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)"#,
+        id,
+        admin.publication.id,
+        template_name,
+        recipient.as_ref(),
+        email.subject,
+        email.html,
+        email.text,
+        status,
+        now
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to queue the transactional email.")?;
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({"id": id, "status": status})))
+}
+
+#[utoipa::path(
+    get,
+    path = "/emails/{message_id}",
+    tag = "v1",
+    params(EmailPath),
+    responses(
+        (status = 200, body = TransactionalEmailStatus),
+        ApiError
+    ),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Fetching the status of a transactional email",
+    skip_all,
+    fields(publication = %admin.publication.slug, message_id = %path.message_id)
+)]
+pub async fn get_email(
+    path: web::Path<EmailPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let email = get_transactional_email(&connection_pool, admin.publication.id, path.message_id)
+        .await
+        .context("Failed to fetch the transactional email.")?
+        .ok_or_else(|| ApiError::NotFound(format!("There is no email {}.", path.message_id)))?;
+
+    Ok(HttpResponse::Ok().json(email))
+}
@@ -0,0 +1,123 @@
+// Versioned endpoints for other services, authenticated with the
+// same publication tokens as the admin endpoints.
+mod emails;
+mod subscribers;
+
+use std::collections::BTreeMap;
+
+use actix_web::{
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use reqwest::StatusCode;
+use utoipa::{
+    openapi::{response::Response, RefOr},
+    IntoResponses, OpenApi,
+};
+
+use crate::{
+    domain::ValidationErrors,
+    problem::Problem,
+    routes::{error_chain_fmt, openapi::problem_responses},
+    transactional_emails::TemplateError,
+};
+
+pub use emails::{get_email, send_email};
+pub use subscribers::{
+    create_subscriber, delete_subscriber, get_subscriber, list_subscribers, update_subscriber,
+};
+
+// Paths are relative to `/api/v1`, where the document is nested.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        emails::send_email,
+        emails::get_email,
+        subscribers::list_subscribers,
+        subscribers::create_subscriber,
+        subscribers::get_subscriber,
+        subscribers::update_subscriber,
+        subscribers::delete_subscriber,
+    ),
+    components(schemas(Problem))
+)]
+pub struct ApiV1Doc;
+
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    InvalidFields(ValidationErrors),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ValidationError(_) | ApiError::InvalidFields(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = Problem::from_error(self);
+        match self {
+            ApiError::InvalidFields(errors) => problem.with_errors(errors.clone()),
+            _ => problem,
+        }
+        .response()
+    }
+}
+
+impl IntoResponses for ApiError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem_responses(&[
+            (400, "The request is invalid."),
+            (401, "The publication token is missing or invalid."),
+            (404, "There is no such resource."),
+            (409, "The request conflicts with what is stored."),
+            (500, "Something went wrong on our side."),
+        ])
+    }
+}
+
+impl From<TemplateError> for ApiError {
+    fn from(e: TemplateError) -> Self {
+        match e {
+            TemplateError::ValidationError(e) => ApiError::ValidationError(e),
+            TemplateError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+        }
+    }
+}
+
+// Bodies, query strings and paths the extractors can't make sense of
+// get the statuses of the API, with a problem details document.
+pub fn configure_extractors(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::JsonConfig::default().error_handler(
+        |e: JsonPayloadError, _: &HttpRequest| ApiError::ValidationError(e.to_string()).into(),
+    ))
+    .app_data(
+        web::QueryConfig::default().error_handler(|e: QueryPayloadError, _: &HttpRequest| {
+            ApiError::ValidationError(e.to_string()).into()
+        }),
+    )
+    .app_data(
+        web::PathConfig::default().error_handler(|e: PathError, _: &HttpRequest| {
+            ApiError::NotFound(e.to_string()).into()
+        }),
+    );
+}
@@ -0,0 +1,616 @@
+use std::collections::HashMap;
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::ApiError;
+use crate::{
+    authentication::PublicationAdmin,
+    domain::{
+        raw_custom_fields, CustomFields, FormDataSubscriber, NewSubscriber, SubscriberName,
+        SubscriberTimeZone, TagName, ValidationCode, ValidationError, ValidationErrors,
+    },
+    email_clients::EmailClient,
+    routes::{
+        admin::{get_custom_field_definitions, replace_subscriber_tags, upsert_tags},
+        subscription::{
+            generate_subscription_token, insert_subscriber, send_confirmatioin_email, store_token,
+        },
+    },
+    startup::ApplicationBaseUrl,
+    suppressions::is_suppressed,
+    webhooks::{emit_subscriber_event, SubscriberEvent},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+const STATUSES: [&str; 5] = [
+    "pending_confirmation",
+    "confirmed",
+    "unsubscribed",
+    "bounced",
+    "complained",
+];
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct SubscriberPath {
+    subscriber_id: Uuid,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListParameters {
+    #[param(pattern = "^(pending_confirmation|confirmed|unsubscribed|bounced|complained)$")]
+    status: Option<String>,
+    // Subscribed at or after, RFC 3339.
+    subscribed_after: Option<DateTime<Utc>>,
+    // Subscribed strictly before, RFC 3339.
+    subscribed_before: Option<DateTime<Utc>>,
+    // The `next_cursor` of the previous page.
+    cursor: Option<String>,
+    #[param(minimum = 1, maximum = 100, default = 50)]
+    limit: Option<i64>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewSubscriberData {
+    email: String,
+    name: String,
+    time_zone: Option<String>,
+    #[serde(default)]
+    custom_fields: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    tags: Vec<String>,
+    // Audiences that already gave their consent elsewhere, e.g. when
+    // moving from another provider, don't go through double opt-in again.
+    #[serde(default)]
+    skip_confirmation: bool,
+}
+
+// Fields left out are left as they are.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct SubscriberUpdate {
+    name: Option<String>,
+    // An empty string clears it.
+    time_zone: Option<String>,
+    // Replaces every custom field of the subscriber.
+    custom_fields: Option<HashMap<String, serde_json::Value>>,
+    tags: Option<Vec<String>>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct SubscriberResponse {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    time_zone: Option<String>,
+    custom_fields: serde_json::Value,
+    tags: Vec<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct SubscriberPage {
+    data: Vec<SubscriberResponse>,
+    // None on the last page.
+    next_cursor: Option<String>,
+}
+
+// Where a page ends, subscribers are listed by subscription date then id.
+// Unlike offsets, cursors don't skip or repeat anyone when subscribers
+// come and go between two pages.
+#[derive(Debug, PartialEq)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!(
+            "{}|{}",
+            self.subscribed_at
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            self.id
+        ))
+    }
+
+    fn decode(s: &str) -> Option<Self> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(s).ok()?).ok()?;
+        let (subscribed_at, id) = decoded.split_once('|')?;
+        Some(Self {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .ok()?
+                .with_timezone(&Utc),
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+fn parse_tags(tags: Vec<String>) -> Result<Vec<TagName>, ApiError> {
+    tags.into_iter()
+        .map(TagName::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApiError::ValidationError)
+}
+
+fn no_subscriber(subscriber_id: Uuid) -> ApiError {
+    ApiError::NotFound(format!("There is no subscriber with id {}.", subscriber_id))
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscribers",
+    tag = "v1",
+    params(ListParameters),
+    responses((status = 200, body = SubscriberPage), ApiError),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Listing subscribers",
+    skip_all,
+    fields(publication = %admin.publication.slug)
+)]
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let parameters = parameters.into_inner();
+    if let Some(status) = &parameters.status {
+        if !STATUSES.contains(&status.as_str()) {
+            return Err(ApiError::ValidationError(format!(
+                "{} is not a subscriber status. Statuses are: {}.",
+                status,
+                STATUSES.join(", ")
+            )));
+        }
+    }
+    let limit = parameters.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::ValidationError(format!(
+            "The limit has to be between 1 and {}.",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let cursor = parameters
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            Cursor::decode(cursor)
+                .ok_or_else(|| ApiError::ValidationError("The cursor is not valid.".into()))
+        })
+        .transpose()?;
+
+    // One more than asked for, to tell whether there is a next page.
+    let mut subscribers = sqlx::query_as!(
+        SubscriberResponse,
+        r#"
+    SELECT subscriptions.id, subscriptions.email, subscriptions.name, subscriptions.status,
+        subscriptions.subscribed_at, subscriptions.time_zone, subscriptions.custom_fields,
+        ARRAY(
+            SELECT tags.name FROM subscription_tags
+            JOIN tags ON tags.id = subscription_tags.tag_id
+            WHERE subscription_tags.subscriber_id = subscriptions.id
+            ORDER BY tags.name
+        ) AS "tags!"
+    FROM subscriptions
+    WHERE subscriptions.publication_id = $1
+        AND ($2::text IS NULL OR subscriptions.status = $2)
+        AND ($3::timestamptz IS NULL OR subscriptions.subscribed_at >= $3)
+        AND ($4::timestamptz IS NULL OR subscriptions.subscribed_at < $4)
+        AND ($5::timestamptz IS NULL OR (subscriptions.subscribed_at, subscriptions.id) > ($5, $6))
+    ORDER BY subscriptions.subscribed_at, subscriptions.id
+    LIMIT $7"#,
+        admin.publication.id,
+        parameters.status,
+        parameters.subscribed_after,
+        parameters.subscribed_before,
+        cursor.as_ref().map(|cursor| cursor.subscribed_at),
+        cursor.as_ref().map(|cursor| cursor.id),
+        limit + 1
+    )
+    .fetch_all(connection_pool.get_ref())
+    .await
+    .context("Failed to fetch the subscribers.")?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        data: subscribers,
+        next_cursor,
+    }))
+}
+
+async fn fetch_subscriber(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberResponse>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberResponse,
+        r#"
+    SELECT subscriptions.id, subscriptions.email, subscriptions.name, subscriptions.status,
+        subscriptions.subscribed_at, subscriptions.time_zone, subscriptions.custom_fields,
+        ARRAY(
+            SELECT tags.name FROM subscription_tags
+            JOIN tags ON tags.id = subscription_tags.tag_id
+            WHERE subscription_tags.subscriber_id = subscriptions.id
+            ORDER BY tags.name
+        ) AS "tags!"
+    FROM subscriptions
+    WHERE subscriptions.id = $1 AND subscriptions.publication_id = $2"#,
+        subscriber_id,
+        publication_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the subscriber.")
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscribers/{subscriber_id}",
+    tag = "v1",
+    params(SubscriberPath),
+    responses((status = 200, body = SubscriberResponse), ApiError),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Fetching a subscriber",
+    skip_all,
+    fields(publication = %admin.publication.slug, subscriber_id = %path.subscriber_id)
+)]
+pub async fn get_subscriber(
+    path: web::Path<SubscriberPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber = fetch_subscriber(&connection_pool, admin.publication.id, path.subscriber_id)
+        .await?
+        .ok_or_else(|| no_subscriber(path.subscriber_id))?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscribers",
+    tag = "v1",
+    request_body = NewSubscriberData,
+    responses(
+        (status = 201, body = SubscriberResponse, headers(
+            ("Location" = String, description = "Where the new subscriber can be fetched.")
+        )),
+        ApiError
+    ),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Creating a subscriber",
+    skip_all,
+    fields(
+        publication = %admin.publication.slug,
+        subscriber_email = %body.email,
+        skip_confirmation = body.skip_confirmation
+    )
+)]
+pub async fn create_subscriber(
+    request: HttpRequest,
+    body: web::Json<NewSubscriberData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ApiError> {
+    let data = body.into_inner();
+    let publication = admin.publication;
+    let definitions = get_custom_field_definitions(&connection_pool, publication.id).await?;
+    let mut errors = ValidationErrors::new();
+    let new_subscriber = errors.merge(NewSubscriber::try_from(FormDataSubscriber {
+        email: data.email,
+        name: data.name,
+        time_zone: data.time_zone,
+        custom_fields: HashMap::new(),
+    }));
+    let custom_fields = errors.merge(CustomFields::parse(
+        &definitions,
+        raw_custom_fields(data.custom_fields),
+    ));
+    let (Some(new_subscriber), Some(custom_fields)) = (new_subscriber, custom_fields) else {
+        return Err(ApiError::InvalidFields(errors));
+    };
+    if is_suppressed(&connection_pool, new_subscriber.email.as_ref())
+        .await
+        .context("Failed to check for a suppressed address.")?
+    {
+        return Err(ApiError::ValidationError(
+            "This address can't be subscribed.".into(),
+        ));
+    }
+    let tags = parse_tags(data.tags)?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let status = if data.skip_confirmation {
+        "confirmed"
+    } else {
+        "pending_confirmation"
+    };
+    let subscriber_id = insert_subscriber(
+        &publication,
+        &new_subscriber,
+        &custom_fields,
+        status,
+        &mut transaction,
+    )
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db_error) if db_error.is_unique_violation() => ApiError::Conflict(format!(
+            "{} is already a subscriber.",
+            new_subscriber.email.as_ref()
+        )),
+        _ => ApiError::UnexpectedError(
+            anyhow::Error::new(e).context("Failed to insert the new subscriber."),
+        ),
+    })?;
+    if !tags.is_empty() {
+        let tag_ids = upsert_tags(&mut transaction, publication.id, &tags)
+            .await
+            .context("Failed to store the tags of the new subscriber.")?;
+        replace_subscriber_tags(&mut transaction, subscriber_id, &tag_ids)
+            .await
+            .context("Failed to assign tags to the new subscriber.")?;
+    }
+    let subscription_token = generate_subscription_token();
+    if data.skip_confirmation {
+        for event in [SubscriberEvent::Subscribed, SubscriberEvent::Confirmed] {
+            emit_subscriber_event(&mut transaction, subscriber_id, event)
+                .await
+                .context("Failed to emit a subscriber event.")?;
+        }
+    } else {
+        store_token(&mut transaction, &subscription_token, subscriber_id)
+            .await
+            .context("Failed to store the confirmation token of the new subscriber.")?;
+        emit_subscriber_event(&mut transaction, subscriber_id, SubscriberEvent::Subscribed)
+            .await
+            .context("Failed to emit the subscription event.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+
+    if !data.skip_confirmation {
+        send_confirmatioin_email(
+            &email_client,
+            &publication,
+            new_subscriber,
+            &definitions,
+            &custom_fields,
+            &publication.base_url(&base_url),
+            &subscription_token,
+        )
+        .await
+        .context("Failed to send confirmation email.")?;
+    }
+
+    let subscriber = fetch_subscriber(&connection_pool, publication.id, subscriber_id)
+        .await?
+        .context("The new subscriber is gone.")?;
+    Ok(HttpResponse::Created()
+        .insert_header((
+            header::LOCATION,
+            format!("{}/{}", request.path(), subscriber_id),
+        ))
+        .json(subscriber))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/subscribers/{subscriber_id}",
+    tag = "v1",
+    params(SubscriberPath),
+    request_body = SubscriberUpdate,
+    responses((status = 200, body = SubscriberResponse), ApiError),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Updating a subscriber",
+    skip_all,
+    fields(publication = %admin.publication.slug, subscriber_id = %path.subscriber_id)
+)]
+pub async fn update_subscriber(
+    path: web::Path<SubscriberPath>,
+    body: web::Json<SubscriberUpdate>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber_id = path.subscriber_id;
+    let update = body.into_inner();
+    let mut errors = ValidationErrors::new();
+    let name = errors.check("name", update.name.map(SubscriberName::parse).transpose());
+    let time_zone = errors.check(
+        "time_zone",
+        update
+            .time_zone
+            .map(|time_zone| {
+                (!time_zone.trim().is_empty())
+                    .then(|| SubscriberTimeZone::parse(time_zone))
+                    .transpose()
+            })
+            .transpose()
+            .map_err(|e| ValidationError::new(ValidationCode::InvalidFormat, e)),
+    );
+    let custom_fields = match update.custom_fields {
+        Some(fields) => {
+            let definitions =
+                get_custom_field_definitions(&connection_pool, admin.publication.id).await?;
+            errors
+                .merge(CustomFields::parse(&definitions, raw_custom_fields(fields)))
+                .map(Some)
+        }
+        None => Some(None),
+    };
+    let (Some(name), Some(time_zone), Some(custom_fields)) = (name, time_zone, custom_fields)
+    else {
+        return Err(ApiError::InvalidFields(errors));
+    };
+    let tags = update.tags.map(parse_tags).transpose()?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let current = sqlx::query!(
+        r#"
+    SELECT name, time_zone, custom_fields FROM subscriptions
+    WHERE id = $1 AND publication_id = $2
+    FOR UPDATE"#,
+        subscriber_id,
+        admin.publication.id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscriber.")?
+    .ok_or_else(|| no_subscriber(subscriber_id))?;
+
+    sqlx::query!(
+        r#"
+    UPDATE subscriptions SET name = $2, time_zone = $3, custom_fields = $4
+    WHERE id = $1"#,
+        subscriber_id,
+        name.as_ref().map_or(current.name.as_str(), AsRef::as_ref),
+        match &time_zone {
+            Some(time_zone) => time_zone.as_ref().map(AsRef::as_ref),
+            None => current.time_zone.as_deref(),
+        },
+        match &custom_fields {
+            Some(fields) => serde_json::Value::Object(fields.as_ref().clone()),
+            None => current.custom_fields,
+        }
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the subscriber.")?;
+    if let Some(tags) = &tags {
+        let tag_ids = upsert_tags(&mut transaction, admin.publication.id, tags)
+            .await
+            .context("Failed to store the tags of the subscriber.")?;
+        replace_subscriber_tags(&mut transaction, subscriber_id, &tag_ids)
+            .await
+            .context("Failed to assign tags to the subscriber.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a subscriber.")?;
+
+    let subscriber = fetch_subscriber(&connection_pool, admin.publication.id, subscriber_id)
+        .await?
+        .ok_or_else(|| no_subscriber(subscriber_id))?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+// Forgets the subscriber altogether, unlike unsubscribing. Past deliveries
+// are kept for the statistics of the issues they were part of.
+#[utoipa::path(
+    delete,
+    path = "/subscribers/{subscriber_id}",
+    tag = "v1",
+    params(SubscriberPath),
+    responses((status = 204, description = "The subscriber is gone."), ApiError),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Deleting a subscriber",
+    skip_all,
+    fields(publication = %admin.publication.slug, subscriber_id = %path.subscriber_id)
+)]
+pub async fn delete_subscriber(
+    path: web::Path<SubscriberPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ApiError> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    sqlx::query!(
+        r#"
+    DELETE FROM subscription_tokens
+    USING subscriptions
+    WHERE subscription_tokens.subscriber_id = subscriptions.id
+        AND subscriptions.id = $1 AND subscriptions.publication_id = $2"#,
+        path.subscriber_id,
+        admin.publication.id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the confirmation tokens of the subscriber.")?;
+    let result = sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = $1 AND publication_id = $2",
+        path.subscriber_id,
+        admin.publication.id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscriber.")?;
+    if result.rows_affected() == 0 {
+        return Err(no_subscriber(path.subscriber_id));
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a subscriber.")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cursor;
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_none, assert_some_eq};
+    use uuid::Uuid;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp_opt(1_715_000_000, 123_456_000).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        let encoded = cursor.encode();
+
+        assert!(encoded
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_".contains(c)));
+        assert_some_eq!(Cursor::decode(&encoded), cursor);
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        assert_none!(Cursor::decode("garbage"));
+        assert_none!(Cursor::decode(""));
+    }
+}
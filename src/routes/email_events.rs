@@ -0,0 +1,184 @@
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::PgPool;
+
+use crate::{
+    routes::error_chain_fmt,
+    suppressions::{suppress, SuppressionReason},
+};
+
+// Header carrying `sha256=` and the hex HMAC of `{timestamp}.{body}`,
+// signed the way our own webhooks are.
+const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+// Unix seconds at which the event was signed.
+const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+// Events signed longer ago, or that far ahead, could be replays.
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 5 * 60;
+
+pub struct EmailWebhookSecret(pub Secret<String>);
+
+// Delivery events posted by the email provider, in its own format.
+// Everything but bounces and spam complaints is acknowledged and dropped.
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+enum EmailEvent {
+    Bounce {
+        #[serde(rename = "Type")]
+        kind: String,
+        #[serde(rename = "Email")]
+        email: String,
+    },
+    SpamComplaint {
+        #[serde(rename = "Email")]
+        email: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+// Soft bounces, e.g. full mailboxes, are worth trying again later.
+const HARD_BOUNCES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+impl EmailEvent {
+    fn suppression(&self) -> Option<(&str, SuppressionReason)> {
+        match self {
+            EmailEvent::Bounce { kind, email } if HARD_BOUNCES.contains(&kind.as_str()) => {
+                Some((email, SuppressionReason::Bounced))
+            }
+            EmailEvent::SpamComplaint { email } => Some((email, SuppressionReason::Complained)),
+            _ => None,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Receiving an email event", skip_all)]
+pub async fn receive_email_event(
+    request: HttpRequest,
+    body: web::Bytes,
+    connection_pool: web::Data<PgPool>,
+    secret: web::Data<EmailWebhookSecret>,
+) -> Result<HttpResponse, EmailEventError> {
+    verify_signature(&request, &body, &secret)?;
+    let event: EmailEvent =
+        serde_json::from_slice(&body).map_err(|e| EmailEventError::InvalidEvent(e.to_string()))?;
+
+    if let Some((email, reason)) = event.suppression() {
+        tracing::info!(reason = reason.as_str(), "Suppressing an address.");
+        let mut transaction = connection_pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool.")?;
+        suppress(&mut transaction, email, reason)
+            .await
+            .context("Failed to suppress the address.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the suppression.")?;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn verify_signature(
+    request: &HttpRequest,
+    body: &[u8],
+    secret: &EmailWebhookSecret,
+) -> Result<(), EmailEventError> {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let signature = header(SIGNATURE_HEADER)
+        .and_then(|value| value.strip_prefix("sha256="))
+        .and_then(|value| hex::decode(value).ok())
+        .ok_or(EmailEventError::InvalidSignature)?;
+    let timestamp: i64 = header(TIMESTAMP_HEADER)
+        .and_then(|value| value.parse().ok())
+        .ok_or(EmailEventError::InvalidSignature)?;
+    if (Utc::now().timestamp() - timestamp).abs() > SIGNATURE_TOLERANCE_SECONDS {
+        return Err(EmailEventError::InvalidSignature);
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
+        .expect("HMAC takes keys of any size.");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| EmailEventError::InvalidSignature)
+}
+
+#[derive(thiserror::Error)]
+pub enum EmailEventError {
+    #[error("The signature of the event is missing or invalid.")]
+    InvalidSignature,
+    #[error("The event is not valid: {0}")]
+    InvalidEvent(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailEventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for EmailEventError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            EmailEventError::InvalidSignature => StatusCode::UNAUTHORIZED,
+            EmailEventError::InvalidEvent(_) => StatusCode::BAD_REQUEST,
+            EmailEventError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailEvent, SuppressionReason};
+    use claims::{assert_none, assert_some_eq};
+
+    fn parse(event: serde_json::Value) -> EmailEvent {
+        serde_json::from_value(event).unwrap()
+    }
+
+    #[test]
+    fn hard_bounces_and_complaints_suppress() {
+        let bounce = parse(serde_json::json!({
+            "RecordType": "Bounce", "Type": "HardBounce", "Email": "ada@example.com"
+        }));
+        let complaint = parse(serde_json::json!({
+            "RecordType": "SpamComplaint", "Email": "ada@example.com"
+        }));
+
+        assert_some_eq!(
+            bounce.suppression(),
+            ("ada@example.com", SuppressionReason::Bounced)
+        );
+        assert_some_eq!(
+            complaint.suppression(),
+            ("ada@example.com", SuppressionReason::Complained)
+        );
+    }
+
+    #[test]
+    fn other_events_are_ignored() {
+        let soft_bounce = parse(serde_json::json!({
+            "RecordType": "Bounce", "Type": "SoftBounce", "Email": "ada@example.com"
+        }));
+        let delivery = parse(serde_json::json!({
+            "RecordType": "Delivery", "Recipient": "ada@example.com"
+        }));
+
+        assert_none!(soft_bounce.suppression());
+        assert_none!(delivery.suppression());
+    }
+}
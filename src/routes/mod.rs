@@ -1,3 +1,13 @@
+pub mod admin;
+pub mod api;
+pub mod email_events;
 pub mod health_check;
+pub mod newsletter;
+pub mod openapi;
+pub mod preferences;
 pub mod subscription;
 pub mod subscription_confirm;
+pub mod tracking;
+pub mod unsubscribe;
+
+pub use subscription::error_chain_fmt;
@@ -0,0 +1,458 @@
+use std::collections::BTreeMap;
+
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use utoipa::{
+    openapi::{
+        content::ContentBuilder,
+        response::{Response, ResponseBuilder},
+        schema::{AllOfBuilder, ObjectBuilder, Ref},
+        RefOr,
+    },
+    IntoResponses,
+};
+use uuid::Uuid;
+
+use crate::{
+    audience::LocalSendTime,
+    authentication::PublicationAdmin,
+    domain::SubscriberTimeZone,
+    email_pipeline::EmailContent,
+    link_checker::{LinkChecker, LinkReport},
+    newsletter_issues::{
+        get_issue, insert_issue, mark_published, render_content, schedule_issue, start_delivery,
+        unschedule_issue, AbTest, IssueData, IssueError, IssueSchedule, IssueStatus,
+        NewsletterIssue, PreparedIssue,
+    },
+    problem::Problem,
+    routes::{error_chain_fmt, openapi::problem_responses},
+    startup::ApplicationBaseUrl,
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct BodyData {
+    #[serde(flatten)]
+    issue: IssueData,
+    #[serde(default)]
+    link_check: LinkCheckMode,
+}
+
+// What to do when the links of an issue are checked before sending.
+#[derive(serde::Deserialize, utoipa::ToSchema, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkCheckMode {
+    // Dead links stop the issue from going out.
+    Block,
+    // Problems are reported, the issue is sent anyway.
+    #[default]
+    Warn,
+    Skip,
+}
+
+// Issues are handed over to the delivery queue, emails go out in the background.
+#[utoipa::path(
+    post,
+    path = "/newsletters",
+    tag = "newsletters",
+    request_body = BodyData,
+    responses(
+        (status = 200, description = "The issue is queued, with its id, what the sanitizer \
+            stripped and the link report."),
+        (status = 401, description = "The publication token is missing or invalid."),
+        PublishError
+    ),
+    security(("publication_token" = []))
+)]
+#[tracing::instrument(
+    name = "Publishing a newsletter issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, username = %admin.username)
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    link_checker: web::Data<LinkChecker>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, PublishError> {
+    let publication = &admin.publication;
+    let issue = &body.issue;
+
+    // HTML from editors is sanitized, what was removed is reported back to them.
+    let rendered = render_content(publication, issue)?;
+    let prepared = PreparedIssue::prepare(
+        &connection_pool,
+        publication,
+        &base_url,
+        issue.parts(&rendered),
+    )
+    .await?;
+    let links = check_links(
+        &link_checker,
+        body.link_check,
+        &rendered.content,
+        rendered.ab_test.as_ref(),
+    )
+    .await?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let issue_id = insert_issue(
+        &mut transaction,
+        publication.id,
+        IssueStatus::Published,
+        issue,
+        &rendered,
+    )
+    .await
+    .context("Failed to store the newsletter issue.")?;
+    start_delivery(
+        &mut transaction,
+        prepared.audience(publication.id),
+        issue_id,
+        None,
+        rendered.ab_test.as_ref(),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the newsletter issue.")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "issue_id": issue_id,
+        "stripped": rendered.stripped,
+        "links": links,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DraftPath {
+    issue_id: Uuid,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishDraftData {
+    #[serde(default)]
+    link_check: LinkCheckMode,
+}
+
+async fn get_unpublished_issue(
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, PublishError> {
+    let issue = get_issue(connection_pool, admin.publication.id, issue_id)
+        .await?
+        .ok_or_else(|| PublishError::NotFound(format!("There is no issue {}.", issue_id)))?;
+    // Paused and cancelled issues were published too.
+    if !matches!(issue.status, IssueStatus::Draft | IssueStatus::Scheduled) {
+        return Err(already_published(issue_id));
+    }
+    Ok(issue)
+}
+
+fn already_published(issue_id: Uuid) -> PublishError {
+    PublishError::Conflict(format!("Issue {} was already published.", issue_id))
+}
+
+async fn prepare(
+    connection_pool: &PgPool,
+    admin: &PublicationAdmin,
+    base_url: &ApplicationBaseUrl,
+    issue: &NewsletterIssue,
+) -> Result<PreparedIssue, PublishError> {
+    Ok(
+        PreparedIssue::prepare(connection_pool, &admin.publication, base_url, issue.parts())
+            .await?,
+    )
+}
+
+// Sends a draft, or a scheduled issue ahead of time, to its audience.
+// An issue is only ever published once, it can't be edited afterwards.
+#[tracing::instrument(
+    name = "Publishing a draft issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn publish_draft(
+    path: web::Path<DraftPath>,
+    body: web::Json<PublishDraftData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    link_checker: web::Data<LinkChecker>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, PublishError> {
+    let publication = &admin.publication;
+    let issue = get_unpublished_issue(&connection_pool, &admin, path.issue_id).await?;
+    let prepared = prepare(&connection_pool, &admin, &base_url, &issue).await?;
+    let links = check_links(
+        &link_checker,
+        body.link_check,
+        &issue.content,
+        issue.ab_test.as_ref(),
+    )
+    .await?;
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // Two concurrent requests, or the scheduler, could get here too, only one of them sends.
+    if !mark_published(&mut transaction, publication.id, issue.id)
+        .await
+        .context("Failed to mark the issue as published.")?
+    {
+        return Err(already_published(issue.id));
+    }
+    start_delivery(
+        &mut transaction,
+        prepared.audience(publication.id),
+        issue.id,
+        None,
+        issue.ab_test.as_ref(),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the newsletter issue.")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "issue_id": issue.id,
+        "links": links,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleData {
+    #[serde(flatten)]
+    schedule: ScheduleTime,
+    #[serde(default)]
+    link_check: LinkCheckMode,
+}
+
+// Either a moment, e.g. `"scheduled_at": "2024-03-25T08:00:00Z"`, or a wall-clock
+// time for time zone waves, e.g. `"local_time": "2024-03-25T09:00:00"`.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum ScheduleTime {
+    At {
+        scheduled_at: DateTime<Utc>,
+    },
+    LocalTime {
+        local_time: NaiveDateTime,
+        // For subscribers who never told us their time zone, UTC by default.
+        fallback_time_zone: Option<String>,
+    },
+}
+
+impl TryFrom<ScheduleTime> for IssueSchedule {
+    type Error = String;
+
+    fn try_from(schedule: ScheduleTime) -> Result<Self, Self::Error> {
+        match schedule {
+            ScheduleTime::At { scheduled_at } => Ok(IssueSchedule::At(scheduled_at)),
+            ScheduleTime::LocalTime {
+                local_time,
+                fallback_time_zone,
+            } => Ok(IssueSchedule::LocalTime(LocalSendTime {
+                local_time,
+                fallback_time_zone: SubscriberTimeZone::parse(
+                    fallback_time_zone.unwrap_or_else(|| "UTC".into()),
+                )?,
+            })),
+        }
+    }
+}
+
+// Schedules a draft, or moves a scheduled issue to another time.
+// The issue is checked now, editors don't hear back when the scheduler sends it.
+#[tracing::instrument(
+    name = "Scheduling a newsletter issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn schedule_newsletter(
+    path: web::Path<DraftPath>,
+    body: web::Json<ScheduleData>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+    link_checker: web::Data<LinkChecker>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, PublishError> {
+    let body = body.into_inner();
+    let schedule = IssueSchedule::try_from(body.schedule).map_err(PublishError::ValidationError)?;
+    // For waves, the first time zone has to be ahead of us still.
+    if schedule.starts_at() <= Utc::now() {
+        return Err(PublishError::ValidationError(
+            "Issues can only be scheduled in the future.".into(),
+        ));
+    }
+    let issue = get_unpublished_issue(&connection_pool, &admin, path.issue_id).await?;
+    // The test slice and the winner are sent at a single moment.
+    if issue.ab_test.is_some() && matches!(schedule, IssueSchedule::LocalTime(_)) {
+        return Err(PublishError::ValidationError(
+            "Issues with an A/B test can't be delivered in time zone waves.".into(),
+        ));
+    }
+    prepare(&connection_pool, &admin, &base_url, &issue).await?;
+    let links = check_links(
+        &link_checker,
+        body.link_check,
+        &issue.content,
+        issue.ab_test.as_ref(),
+    )
+    .await?;
+
+    if !schedule_issue(&connection_pool, admin.publication.id, issue.id, &schedule)
+        .await
+        .context("Failed to schedule the issue.")?
+    {
+        return Err(already_published(issue.id));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "issue_id": issue.id,
+        "scheduled_at": schedule.starts_at(),
+        "links": links,
+    })))
+}
+
+// Turns a scheduled issue back into a draft, as long as the scheduler
+// has not started sending it.
+#[tracing::instrument(
+    name = "Cancelling a scheduled newsletter issue",
+    skip_all,
+    fields(publication = %admin.publication.slug, issue_id = %path.issue_id)
+)]
+pub async fn cancel_scheduled_newsletter(
+    path: web::Path<DraftPath>,
+    admin: PublicationAdmin,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PublishError> {
+    let issue = get_unpublished_issue(&connection_pool, &admin, path.issue_id).await?;
+    if !unschedule_issue(&connection_pool, admin.publication.id, issue.id)
+        .await
+        .context("Failed to cancel the scheduled issue.")?
+    {
+        return Err(PublishError::Conflict(format!(
+            "Issue {} is not scheduled.",
+            issue.id
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// The links of every variant are checked along with those of the issue.
+async fn check_links(
+    link_checker: &LinkChecker,
+    mode: LinkCheckMode,
+    content: &EmailContent,
+    ab_test: Option<&AbTest>,
+) -> Result<Option<LinkReport>, PublishError> {
+    if mode == LinkCheckMode::Skip {
+        return Ok(None);
+    }
+    let variants = ab_test.map(|ab_test| ab_test.variants.as_slice());
+    let contents = std::iter::once(content)
+        .chain(variants.unwrap_or_default().iter().map(|v| &v.content))
+        .collect::<Vec<_>>();
+    let html = contents.iter().map(|c| c.html.as_str()).collect::<Vec<_>>();
+    let text = contents.iter().map(|c| c.text.as_str()).collect::<Vec<_>>();
+    let report = link_checker.check(&html.join("\n"), &text.join("\n")).await;
+    if mode == LinkCheckMode::Block && report.has_dead_links() {
+        return Err(PublishError::DeadLinks(report));
+    }
+    Ok(Some(report))
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("The issue links to pages that can't be reached.")]
+    DeadLinks(LinkReport),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<IssueError> for PublishError {
+    fn from(e: IssueError) -> Self {
+        match e {
+            IssueError::ValidationError(e) => PublishError::ValidationError(e),
+            IssueError::UnexpectedError(e) => PublishError::UnexpectedError(e),
+        }
+    }
+}
+
+impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::NotFound(_) => StatusCode::NOT_FOUND,
+            PublishError::Conflict(_) => StatusCode::CONFLICT,
+            PublishError::DeadLinks(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // Editors get the whole report back to fix their links.
+    fn error_response(&self) -> HttpResponse {
+        let problem = Problem::from_error(self);
+        match self {
+            PublishError::DeadLinks(report) => problem.with_extension(
+                "links",
+                serde_json::to_value(report).expect("A serializable link report."),
+            ),
+            _ => problem,
+        }
+        .response()
+    }
+}
+
+impl IntoResponses for PublishError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        let mut responses = problem_responses(&[
+            (400, "The issue is invalid."),
+            (404, "There is no such issue or segment."),
+            (409, "The issue is not in a state that allows it."),
+            (500, "Something went wrong on our side."),
+        ]);
+        let dead_links = AllOfBuilder::new()
+            .item(Ref::from_schema_name("Problem"))
+            .item(
+                ObjectBuilder::new()
+                    .property("links", Ref::from_schema_name("LinkReport"))
+                    .required("links"),
+            );
+        responses.insert(
+            "422".into(),
+            ResponseBuilder::new()
+                .description("Blocked by dead links, the whole report is sent back in `links`.")
+                .content(
+                    "application/problem+json",
+                    ContentBuilder::new().schema(Some(dead_links)).build(),
+                )
+                .build()
+                .into(),
+        );
+        responses
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use actix_web::HttpResponse;
+use utoipa::{
+    openapi::{
+        content::ContentBuilder,
+        response::{Response, ResponseBuilder},
+        schema::Ref,
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        RefOr,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    link_checker::LinkReport,
+    problem::{FieldError, Problem},
+    routes::{api, newsletter, preferences, subscription, subscription_confirm, unsubscribe},
+};
+
+// Partner teams generate their clients from this document, so it is built
+// from the handlers and types themselves instead of being written by hand.
+// Paths are given for the default publication, every one of them is also
+// served under `/p/{publication}` for the others.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "zero2prod",
+        description = "Newsletter subscriptions, publishing and the v1 API. \
+            Every path is also available under `/p/{publication}`."
+    ),
+    paths(
+        crate::startup::health_check,
+        subscription::subsribe,
+        subscription_confirm::subscription_confirm,
+        unsubscribe::unsubscribe,
+        preferences::update_preferences,
+        newsletter::publish_newsletter,
+    ),
+    components(schemas(LinkReport, Problem, FieldError)),
+    nest((path = "/api/v1", api = api::ApiV1Doc)),
+    modifiers(&PublicationToken),
+    tags(
+        (name = "subscriptions", description = "Signing up, confirming and leaving."),
+        (name = "newsletters", description = "Publishing issues."),
+        (name = "v1", description = "Versioned API for other services."),
+    )
+)]
+pub struct ApiDoc;
+
+// Admin endpoints take the API token of the publication.
+struct PublicationToken;
+
+impl Modify for PublicationToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "publication_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+    }
+}
+
+#[tracing::instrument(name = "Serving the OpenAPI document")]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+// Every error is answered with a problem details document.
+pub fn problem_responses(responses: &[(u16, &str)]) -> BTreeMap<String, RefOr<Response>> {
+    responses
+        .iter()
+        .map(|(status, description)| {
+            (
+                status.to_string(),
+                schema_response(description, "application/problem+json", "Problem"),
+            )
+        })
+        .collect()
+}
+
+// The schema has to be listed in the components of the document.
+pub fn schema_response(description: &str, content_type: &str, schema: &str) -> RefOr<Response> {
+    ResponseBuilder::new()
+        .description(description)
+        .content(
+            content_type,
+            ContentBuilder::new()
+                .schema(Some(Ref::from_schema_name(schema)))
+                .build(),
+        )
+        .build()
+        .into()
+}
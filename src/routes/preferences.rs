@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use utoipa::{
+    openapi::{response::Response, RefOr},
+    IntoResponses,
+};
+
+use crate::{
+    domain::SubscriberTimeZone,
+    routes::{error_chain_fmt, openapi::problem_responses},
+    tenant::Publication,
+};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Parameters {
+    unsubscribe_token: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct PreferencesData {
+    // The preference center fills this in from the time zone the browser reports.
+    time_zone: String,
+}
+
+// Subscribers reach their preferences through the token of their `{{ unsubscribe_url }}`.
+#[utoipa::path(
+    post,
+    path = "/subscriptions/preferences",
+    tag = "subscriptions",
+    params(Parameters),
+    request_body(content = PreferencesData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The preferences are saved."),
+        PreferencesError
+    )
+)]
+#[tracing::instrument(
+    name = "Updating subscriber preferences",
+    skip_all,
+    fields(publication = %publication.slug)
+)]
+pub async fn update_preferences(
+    parameters: web::Query<Parameters>,
+    form: web::Form<PreferencesData>,
+    publication: Publication,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, PreferencesError> {
+    let time_zone = SubscriberTimeZone::parse(form.into_inner().time_zone)
+        .map_err(PreferencesError::ValidationError)?;
+
+    let result = sqlx::query!(
+        r#"
+    UPDATE subscriptions SET time_zone = $3
+    WHERE unsubscribe_token = $1 AND publication_id = $2"#,
+        parameters.unsubscribe_token,
+        publication.id,
+        time_zone.as_ref()
+    )
+    .execute(connection_pool.get_ref())
+    .await
+    .context("Failed to update the subscriber preferences.")?;
+
+    if result.rows_affected() == 0 {
+        return Err(PreferencesError::UnknownToken);
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The preferences link is not valid.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PreferencesError::UnknownToken => StatusCode::NOT_FOUND,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponses for PreferencesError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem_responses(&[
+            (400, "The time zone is not valid."),
+            (404, "The preferences link is not valid."),
+            (500, "Something went wrong on our side."),
+        ])
+    }
+}
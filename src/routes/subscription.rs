@@ -1,19 +1,60 @@
-use actix_web::{web, HttpResponse, ResponseError};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+};
+
+use actix_multipart::Multipart;
+use actix_web::{
+    dev::Payload,
+    http::header::{self, ContentType},
+    web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
 use anyhow::Context;
+use askama::Template;
 use chrono::Utc;
+use futures_util::TryStreamExt;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::StatusCode;
-use sqlx::{Executor, PgPool, Postgres, Transaction};
+use sqlx::{types::Json, Executor, PgPool, Postgres, Transaction};
 use thiserror;
+use utoipa::{
+    openapi::{response::Response, RefOr},
+    IntoResponses,
+};
 use uuid::Uuid;
 
 use crate::{
-    domain::{FormDataSubscriber, NewSubscriber, SubscriberEmail, SubscriberName},
+    audience::display_value,
+    domain::{
+        raw_custom_fields, CustomFieldDefinition, CustomFields, FormDataSubscriber, MergeTemplate,
+        NewSubscriber, ValidationErrors,
+    },
     email_clients::EmailClient,
+    problem::Problem,
+    routes::{admin::get_custom_field_definitions, openapi::problem_responses},
     startup::ApplicationBaseUrl,
+    suppressions::is_suppressed,
+    templating::SubscribeTemplate,
+    tenant::Publication,
+    webhooks::{emit_subscriber_event, SubscriberEvent},
 };
 
-#[tracing::instrument(name="Adding a new subscriber", skip_all, fields(subscriber_email=%form.email, subscriber_name=%form.name))]
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content(
+        (FormDataSubscriber = "application/x-www-form-urlencoded"),
+        (FormDataSubscriber = "application/json"),
+        (FormDataSubscriber = "multipart/form-data"),
+    )),
+    responses(
+        (status = 200, description = "A confirmation email is on its way."),
+        SubscribeError
+    )
+)]
+#[tracing::instrument(name="Adding a new subscriber", skip_all, fields(subscriber_email=%form.0.email, subscriber_name=%form.0.name))]
 // The web::Form<> and web::Data annotations are telling the framework
 // what to extract from the http request.
 // After extraction, form and connection_pool will be of the type annotated inside the <>
@@ -22,12 +63,234 @@ use crate::{
 // And actix will automatically extract R(response) the response from the Result<R, E>.
 // E the error type needs to implement ResponseError, for it to be able to convert into HttpResponse as well.
 pub async fn subsribe(
-    form: web::Form<FormDataSubscriber>,
+    request: HttpRequest,
+    form: SubscriptionBody,
+    publication: Publication,
     connection_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let SubscriptionBody(form) = form;
+    let definitions = get_custom_field_definitions(&connection_pool, publication.id).await?;
+    if !accepts_html(&request) {
+        add_subscriber(
+            form,
+            &definitions,
+            &publication,
+            &connection_pool,
+            &email_client,
+            &base_url,
+        )
+        .await?;
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    // A browser submitting the form gets it back, with what was wrong next to each field.
+    let (name, email) = (form.name.clone(), form.email.clone());
+    let values = form.custom_fields.clone();
+    let outcome = add_subscriber(
+        form,
+        &definitions,
+        &publication,
+        &connection_pool,
+        &email_client,
+        &base_url,
+    )
+    .await;
+    let no_errors = ValidationErrors::new();
+    let mut page = SubscribeTemplate {
+        publication_name: &publication.name,
+        name: &name,
+        email: &email,
+        fields: &definitions,
+        values: &values,
+        errors: &no_errors,
+        message: None,
+        subscribed: false,
+    };
+    let message;
+    let status = match &outcome {
+        Ok(()) => {
+            page.subscribed = true;
+            StatusCode::OK
+        }
+        Err(SubscribeError::InvalidFields(errors)) => {
+            page.errors = errors;
+            StatusCode::BAD_REQUEST
+        }
+        Err(e) if e.status_code().is_client_error() => {
+            message = e.to_string();
+            page.message = Some(&message);
+            e.status_code()
+        }
+        Err(_) => return outcome.map(|_| HttpResponse::Ok().finish()),
+    };
+    render_form(status, &page)
+}
+
+// The subscription form, posted by a browser, or sent as JSON or multipart
+// by apps and scripts. Every kind of body goes through the same validation.
+pub struct SubscriptionBody(pub FormDataSubscriber);
+
+// Custom fields may be sent as JSON numbers and booleans,
+// they are validated like their form counterparts.
+#[derive(serde::Deserialize)]
+struct JsonSubscriber {
+    email: String,
+    name: String,
+    #[serde(default)]
+    time_zone: Option<String>,
+    #[serde(flatten)]
+    custom_fields: HashMap<String, serde_json::Value>,
+}
+
+impl From<JsonSubscriber> for FormDataSubscriber {
+    fn from(json: JsonSubscriber) -> Self {
+        Self {
+            email: json.email,
+            name: json.name,
+            time_zone: json.time_zone,
+            custom_fields: raw_custom_fields(json.custom_fields),
+        }
+    }
+}
+
+// As much as an urlencoded form may carry.
+const MAX_MULTIPART_SIZE: usize = 16 * 1024;
+
+impl FromRequest for SubscriptionBody {
+    type Error = SubscribeError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let essence = match req.mime_type() {
+            Ok(Some(mime)) => mime.essence_str().to_string(),
+            _ => String::new(),
+        };
+        match essence.as_str() {
+            "application/x-www-form-urlencoded" => {
+                let form = web::Form::<FormDataSubscriber>::from_request(req, payload);
+                Box::pin(async move { Ok(Self(form.await.map_err(malformed)?.into_inner())) })
+            }
+            "application/json" => {
+                let json = web::Json::<JsonSubscriber>::from_request(req, payload);
+                Box::pin(
+                    async move { Ok(Self(json.await.map_err(malformed)?.into_inner().into())) },
+                )
+            }
+            "multipart/form-data" => {
+                let multipart = Multipart::new(req.headers(), payload.take());
+                Box::pin(async move { Ok(Self(read_multipart(multipart).await?)) })
+            }
+            _ => {
+                let content_type = req.content_type().to_string();
+                Box::pin(async move { Err(SubscribeError::UnsupportedMediaType(content_type)) })
+            }
+        }
+    }
+}
+
+// Text fields only, collected like the fields of an urlencoded form.
+async fn read_multipart(mut multipart: Multipart) -> Result<FormDataSubscriber, SubscribeError> {
+    let mut fields = serde_json::Map::new();
+    let mut size = 0;
+    while let Some(mut field) = multipart.try_next().await.map_err(malformed)? {
+        let name = field.name().to_string();
+        if field.content_disposition().get_filename().is_some() {
+            return Err(SubscribeError::MalformedBody(format!(
+                "The {} field is a file, only text fields are accepted.",
+                name
+            )));
+        }
+        let mut value = Vec::new();
+        while let Some(chunk) = field.try_next().await.map_err(malformed)? {
+            size += chunk.len();
+            if size > MAX_MULTIPART_SIZE {
+                return Err(SubscribeError::MalformedBody(format!(
+                    "The body is larger than {} bytes.",
+                    MAX_MULTIPART_SIZE
+                )));
+            }
+            value.extend_from_slice(&chunk);
+        }
+        let value = String::from_utf8(value).map_err(|_| {
+            SubscribeError::MalformedBody(format!("The {} field is not valid UTF-8.", name))
+        })?;
+        fields.insert(name, value.into());
+    }
+    serde_json::from_value(serde_json::Value::Object(fields)).map_err(malformed)
+}
+
+fn malformed(e: impl std::fmt::Display) -> SubscribeError {
+    SubscribeError::MalformedBody(e.to_string())
+}
+
+// The signup form of the publication, for readers without a page of their own.
+// It asks for the custom fields of the publication too, required ones included.
+#[tracing::instrument(name = "Showing the subscription form", skip_all)]
+pub async fn subscription_form(
+    publication: Publication,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SubscribeError> {
+    let definitions = get_custom_field_definitions(&connection_pool, publication.id).await?;
+    let page = SubscribeTemplate {
+        publication_name: &publication.name,
+        name: "",
+        email: "",
+        fields: &definitions,
+        values: &HashMap::new(),
+        errors: &ValidationErrors::new(),
+        message: None,
+        subscribed: false,
+    };
+    render_form(StatusCode::OK, &page)
+}
+
+fn render_form(
+    status: StatusCode,
+    page: &SubscribeTemplate<'_>,
+) -> Result<HttpResponse, SubscribeError> {
+    let page = page
+        .render()
+        .context("Failed to render the subscription form.")?;
+    Ok(HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+// Forms submitted by a browser ask for a page back, API clients don't.
+fn accepts_html(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+async fn add_subscriber(
+    mut form: FormDataSubscriber,
+    definitions: &[CustomFieldDefinition],
+    publication: &Publication,
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+) -> Result<(), SubscribeError> {
+    let raw_custom_fields = std::mem::take(&mut form.custom_fields);
+    // The custom fields are reported along with the others, in one go.
+    let mut errors = ValidationErrors::new();
+    let new_subscriber = errors.merge(NewSubscriber::try_from(form));
+    let custom_fields = errors.merge(CustomFields::parse(definitions, raw_custom_fields));
+    let (Some(new_subscriber), Some(custom_fields)) = (new_subscriber, custom_fields) else {
+        return Err(SubscribeError::InvalidFields(errors));
+    };
+
+    // Addresses that bounced or complained are not mailed again, not even to confirm.
+    if is_suppressed(connection_pool, new_subscriber.email.as_ref())
+        .await
+        .context("Failed to check for a suppressed address.")?
+    {
+        return Err(SubscribeError::SuppressedAddress);
+    }
 
     // Pick up a connection from the pool
     // for the upcoming transaction.
@@ -37,13 +300,22 @@ pub async fn subsribe(
         .context("Failed to acquire a Postgres connection from the pool.")?;
 
     let subscription_token = generate_subscription_token();
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction)
-        .await
-        .context("Failed to insert new subscriber in the databse.")?;
+    let subscriber_id = insert_subscriber(
+        publication,
+        &new_subscriber,
+        &custom_fields,
+        "pending_confirmation",
+        &mut transaction,
+    )
+    .await
+    .context("Failed to insert new subscriber in the databse.")?;
 
     store_token(&mut transaction, &subscription_token, subscriber_id)
         .await
         .context("Failed to store the confirmation token for a a new subscriber.")?;
+    emit_subscriber_event(&mut transaction, subscriber_id, SubscriberEvent::Subscribed)
+        .await
+        .context("Failed to emit the subscription event.")?;
 
     // End the transaction by explicitly calling commit
     // on the connection used for the transaction.
@@ -53,78 +325,113 @@ pub async fn subsribe(
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
     send_confirmatioin_email(
-        &email_client,
+        email_client,
+        publication,
         new_subscriber,
-        &base_url.0,
+        definitions,
+        &custom_fields,
+        &publication.base_url(base_url),
         &subscription_token,
     )
     .await
     .context("Failed to send confirmation email.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
 }
 
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, transaction)
+    skip(publication, new_subscriber, custom_fields, transaction),
+    fields(publication = %publication.slug)
 )]
-async fn insert_subscriber(
+pub async fn insert_subscriber(
+    publication: &Publication,
     new_subscriber: &NewSubscriber,
+    custom_fields: &CustomFields,
+    status: &str,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<Uuid, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     let query = sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, $5)"#,
+    INSERT INTO subscriptions
+        (id, publication_id, email, name, subscribed_at, status, custom_fields, time_zone)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
         subscriber_id,
+        publication.id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now(),
-        "pending_confirmation"
+        status,
+        Json(custom_fields.as_ref()) as _,
+        new_subscriber.time_zone.as_ref().map(AsRef::as_ref)
     );
 
     transaction.execute(query).await?;
     Ok(subscriber_id)
 }
 
-pub fn parse_subscriber(form: FormDataSubscriber) -> Result<NewSubscriber, String> {
-    let name = SubscriberName::parse(form.name)?;
-    let email = SubscriberEmail::parse(form.email)?;
-    Ok(NewSubscriber { name, email })
+pub fn parse_subscriber(form: FormDataSubscriber) -> Result<NewSubscriber, ValidationErrors> {
+    form.try_into()
 }
 
 #[tracing::instrument(name = "Sending confirmation email.", skip_all)]
 pub async fn send_confirmatioin_email(
     email_client: &EmailClient,
+    publication: &Publication,
     new_subscriber: NewSubscriber,
+    definitions: &[CustomFieldDefinition],
+    custom_fields: &CustomFields,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
 
+    // The publication copy can greet the new subscriber by name,
+    // there is no unsubscribe link to offer before they confirm.
+    let merge_tags: Vec<&str> = ["name", "email"]
+        .into_iter()
+        .chain(definitions.iter().map(|definition| definition.key.as_str()))
+        .collect();
+    let subject = MergeTemplate::parse(&publication.confirmation_subject, &merge_tags)
+        .map_err(anyhow::Error::msg)
+        .context("The publication has an invalid confirmation subject.")?;
+    let message = MergeTemplate::parse(&publication.confirmation_message, &merge_tags)
+        .map_err(anyhow::Error::msg)
+        .context("The publication has an invalid confirmation message.")?;
+    let lookup = |tag: &str| match tag {
+        "name" => Some(new_subscriber.name.as_ref().to_string()),
+        "email" => Some(new_subscriber.email.as_ref().to_string()),
+        key => custom_fields.as_ref().get(key).map(display_value),
+    };
+
     email_client
-        .send_email(
-            new_subscriber.email,
-            "Welcome",
+        .send_email_from(
+            publication.sender.as_ref(),
+            new_subscriber.email.clone(),
+            &subject.render(lookup, false),
             &format!(
-                "Welcome to our newsletter!<br/>\
+                "{}<br/>\
                 Click <a href=\"{}\">here</a> to confirm your subscription.",
+                message.render(lookup, true),
                 confirmation_link
             ),
             &format!(
-                "Welcome to our newsletter!<br/>\
+                "{}<br/>\
                 Visit {} to confirm your subscription.",
+                message.render(lookup, false),
                 confirmation_link
             ),
         )
-        .await
+        .await?;
+
+    Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -152,7 +459,15 @@ pub async fn store_token(
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
-    ValidationError(String),
+    InvalidFields(ValidationErrors),
+    #[error("This address can't be subscribed.")]
+    SuppressedAddress,
+    #[error("The request body is malformed: {0}")]
+    MalformedBody(String),
+    #[error(
+        "Subscriptions can't be sent as '{0}', send a form, JSON or multipart/form-data instead."
+    )]
+    UnsupportedMediaType(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -167,18 +482,44 @@ impl std::fmt::Debug for SubscribeError {
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::InvalidFields(_)
+            | SubscribeError::SuppressedAddress
+            | SubscribeError::MalformedBody(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let problem = Problem::from_error(self);
+        match self {
+            SubscribeError::InvalidFields(errors) => problem.with_errors(errors.clone()),
+            _ => problem,
+        }
+        .response()
+    }
+}
+
+impl IntoResponses for SubscribeError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem_responses(&[
+            (
+                400,
+                "The body is malformed, the form is invalid, or the address can't be subscribed.",
+            ),
+            (
+                415,
+                "The body is neither a form, JSON nor multipart/form-data.",
+            ),
+            (500, "Something went wrong on our side."),
+        ])
+    }
 }
 
 //------------------------------------------------------------------------
 
 pub struct StoreTokenError(sqlx::Error);
 
-impl ResponseError for StoreTokenError {}
-
 impl std::fmt::Display for StoreTokenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -200,7 +541,7 @@ impl std::error::Error for StoreTokenError {
     }
 }
 
-fn error_chain_fmt(
+pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
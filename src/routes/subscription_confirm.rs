@@ -1,31 +1,74 @@
+use std::collections::BTreeMap;
+
 use actix_web::{web, HttpResponse, ResponseError};
 use anyhow::Context;
 use reqwest::StatusCode;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use utoipa::{
+    openapi::{response::Response, RefOr},
+    IntoResponses,
+};
 use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
+use crate::{
+    routes::openapi::problem_responses,
+    tenant::Publication,
+    webhooks::{emit_subscriber_event, SubscriberEvent},
+};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Parameters {
     subscription_token: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscription is confirmed."),
+        ConfirmationError
+    )
+)]
 #[tracing::instrument(name = "Confirming a pending subscription", skip_all)]
 pub async fn subscription_confirm(
     parameters: web::Query<Parameters>,
+    publication: Publication,
     connection_pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let id = get_subscriber_id(&connection_pool, &parameters.subscription_token)
-        .await
-        .context("No database connection.")?;
+    let id = get_subscriber_id(
+        &connection_pool,
+        &publication,
+        &parameters.subscription_token,
+    )
+    .await
+    .context("No database connection.")?;
 
     match id {
         None => Err(ConfirmationError::NoRecordError(
             "Record does not exit in the database.".to_string(),
         )),
         Some(subscriber_id) => {
-            update_status(&connection_pool, subscriber_id)
+            let mut transaction = connection_pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool.")?;
+            // Following the link again confirms nothing new, and an old link
+            // doesn't bring back subscribers who left or were suppressed.
+            if update_status(&mut transaction, &publication, subscriber_id)
                 .await
-                .context("Failed to update confirmation status in the database.")?;
+                .context("Failed to update confirmation status in the database.")?
+            {
+                emit_subscriber_event(&mut transaction, subscriber_id, SubscriberEvent::Confirmed)
+                    .await
+                    .context("Failed to emit the confirmation event.")?;
+            }
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit the confirmation.")?;
             Ok(HttpResponse::Ok().finish())
         }
     }
@@ -41,17 +84,36 @@ pub enum ConfirmationError {
 
 impl ResponseError for ConfirmationError {
     fn status_code(&self) -> reqwest::StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            ConfirmationError::NoRecordError(_) => StatusCode::NOT_FOUND,
+            ConfirmationError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponses for ConfirmationError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem_responses(&[
+            (404, "The subscription token is not valid."),
+            (500, "Something went wrong on our side."),
+        ])
     }
 }
 
+// Tokens are only valid for the publication the subscriber signed up to.
 async fn get_subscriber_id(
     connection_pool: &PgPool,
+    publication: &Publication,
     subscription_token: &str,
 ) -> Result<Option<Uuid>, sqlx::Error> {
     let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1",
+        r#"
+    SELECT subscription_tokens.subscriber_id FROM subscription_tokens
+    JOIN subscriptions ON subscriptions.id = subscription_tokens.subscriber_id
+    WHERE subscription_tokens.subscription_token = $1
+    AND subscriptions.publication_id = $2"#,
         subscription_token,
+        publication.id,
     )
     .fetch_optional(connection_pool)
     .await
@@ -63,17 +125,25 @@ async fn get_subscriber_id(
     Ok(result.map(|r| r.subscriber_id))
 }
 
-async fn update_status(connection_pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
-        subscriber_id
+// Returns false unless the subscription was waiting for its confirmation.
+async fn update_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    publication: &Publication,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+    UPDATE subscriptions SET status = 'confirmed'
+    WHERE id = $1 AND publication_id = $2 AND status = 'pending_confirmation'"#,
+        subscriber_id,
+        publication.id
     )
-    .execute(connection_pool)
+    .execute(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
 
-    Ok(())
+    Ok(result.rows_affected() == 1)
 }
@@ -0,0 +1,244 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{
+    http::header::{self, CacheControl, CacheDirective},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+
+use crate::{
+    click_tracking::{ClickToken, HmacSecret},
+    routes::error_chain_fmt,
+};
+
+// The smallest transparent GIF there is.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+// Reloading the same email, or following the same link again,
+// within a minute is not another open or click.
+const DEDUPLICATION_WINDOW: chrono::Duration = chrono::Duration::minutes(1);
+
+// User agents of link scanners and crawlers fetching images on their own.
+const AUTOMATED_AGENTS: [&str; 6] = [
+    "bot",
+    "crawler",
+    "spider",
+    "barracuda",
+    "proofpoint",
+    "mimecast",
+];
+
+#[derive(serde::Deserialize)]
+pub struct TrackingPath {
+    tracking_token: String,
+}
+
+// The tracking pixel of a delivery. The image is served whatever happens,
+// a broken image in the middle of an email is worse than a lost open.
+#[tracing::instrument(name = "Recording an open", skip_all)]
+pub async fn record_open(
+    path: web::Path<TrackingPath>,
+    request: HttpRequest,
+    connection_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let client_ip = request
+        .connection_info()
+        .realip_remote_addr()
+        .and_then(parse_ip);
+    let kind = if is_prefetch(user_agent, client_ip) {
+        "prefetch"
+    } else {
+        "open"
+    };
+    if let Err(e) = store_event(&connection_pool, &path.tracking_token, kind, None).await {
+        tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to record an open.");
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![
+            CacheDirective::NoStore,
+            CacheDirective::NoCache,
+            CacheDirective::MustRevalidate,
+            CacheDirective::Private,
+        ]))
+        .body(PIXEL)
+}
+
+// Clicks are told apart by their `url`, opens have none.
+async fn store_event(
+    connection_pool: &PgPool,
+    tracking_token: &str,
+    kind: &str,
+    url: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+    INSERT INTO delivery_events (newsletter_issue_id, subscriber_email, kind, occurred_at, url)
+    SELECT newsletter_issue_id, subscriber_email, $2, $3, $5
+    FROM issue_deliveries
+    WHERE tracking_token = $1
+        AND NOT EXISTS (
+            SELECT 1 FROM delivery_events
+            WHERE delivery_events.newsletter_issue_id = issue_deliveries.newsletter_issue_id
+                AND delivery_events.subscriber_email = issue_deliveries.subscriber_email
+                AND delivery_events.kind = $2
+                AND delivery_events.occurred_at > $4
+                AND delivery_events.url IS NOT DISTINCT FROM $5
+        )"#,
+        tracking_token,
+        kind,
+        now,
+        now - DEDUPLICATION_WINDOW,
+        url
+    )
+    .execute(connection_pool)
+    .await
+    .context("Failed to store the delivery event.")?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct RedirectPath {
+    token: String,
+}
+
+// The links of issues tracking clicks lead here. Only links signed by the
+// worker redirect, anything else would let anyone borrow our domain.
+#[tracing::instrument(name = "Following a tracked link", skip_all)]
+pub async fn follow_link(
+    path: web::Path<RedirectPath>,
+    request: HttpRequest,
+    connection_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, RedirectError> {
+    let click = ClickToken::verify(&path.token, &hmac_secret).ok_or(RedirectError::InvalidLink)?;
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    // Link scanners of mail gateways follow every link as the email arrives.
+    // Readers get where they were going, recorded or not.
+    if !is_automated(user_agent) {
+        let stored = store_event(
+            &connection_pool,
+            &click.tracking_token,
+            "click",
+            Some(&click.url),
+        )
+        .await;
+        if let Err(e) = stored {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to record a click.");
+        }
+    }
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, click.url))
+        .insert_header(CacheControl(vec![
+            CacheDirective::NoStore,
+            CacheDirective::Private,
+        ]))
+        .finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum RedirectError {
+    #[error("The link is not valid.")]
+    InvalidLink,
+}
+
+impl std::fmt::Debug for RedirectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for RedirectError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RedirectError::InvalidLink => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+// The peer address comes with a port, forwarded addresses usually don't.
+fn parse_ip(address: &str) -> Option<IpAddr> {
+    address
+        .parse::<SocketAddr>()
+        .map(|address| address.ip())
+        .or_else(|_| address.parse::<IpAddr>())
+        .ok()
+}
+
+// Apple Mail Privacy Protection loads every image of every email as soon as
+// it is received, from Apple's own network and with a bare user agent.
+// Scanners and crawlers fetching images are no readers either.
+// Other image proxies, e.g. Gmail's, only fetch the image once it is shown.
+fn is_prefetch(user_agent: Option<&str>, client_ip: Option<IpAddr>) -> bool {
+    let from_apple = matches!(client_ip, Some(IpAddr::V4(ip)) if ip.octets()[0] == 17);
+    from_apple || is_automated(user_agent)
+}
+
+// Bare or missing user agents, and those of scanners and crawlers.
+fn is_automated(user_agent: Option<&str>) -> bool {
+    let Some(user_agent) = user_agent.map(str::trim).filter(|ua| !ua.is_empty()) else {
+        return true;
+    };
+    let user_agent = user_agent.to_lowercase();
+    user_agent == "mozilla/5.0"
+        || AUTOMATED_AGENTS
+            .iter()
+            .any(|agent| user_agent.contains(agent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_automated, is_prefetch, parse_ip};
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) \
+        AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148";
+    const GMAIL: &str = "Mozilla/5.0 (Windows NT 5.1; rv:11.0) Gecko Firefox/11.0 \
+        (via ggpht.com GoogleImageProxy)";
+
+    #[test]
+    fn readers_open_emails() {
+        assert!(!is_prefetch(Some(IPHONE), parse_ip("203.0.113.7")));
+        assert!(!is_prefetch(Some(GMAIL), parse_ip("66.249.84.1:443")));
+    }
+
+    #[test]
+    fn apple_privacy_protection_prefetches() {
+        assert!(is_prefetch(Some(IPHONE), parse_ip("17.58.0.12")));
+        assert!(is_prefetch(Some("Mozilla/5.0"), parse_ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn scanners_and_missing_user_agents_prefetch() {
+        assert!(is_prefetch(None, parse_ip("203.0.113.7")));
+        assert!(is_prefetch(Some("  "), None));
+        assert!(is_prefetch(Some("Barracuda Sentinel (EE)"), None));
+        assert!(is_prefetch(
+            Some("Mozilla/5.0 (compatible; bingbot/2.0)"),
+            None
+        ));
+    }
+
+    #[test]
+    fn only_the_user_agent_tells_scanner_clicks_apart() {
+        assert!(!is_automated(Some(IPHONE)));
+        assert!(is_automated(Some("Barracuda Sentinel (EE)")));
+        assert!(is_automated(None));
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use utoipa::{
+    openapi::{response::Response, RefOr},
+    IntoResponses,
+};
+
+use crate::{
+    routes::{error_chain_fmt, openapi::problem_responses},
+    tenant::Publication,
+    webhooks::{emit_subscriber_event, SubscriberEvent},
+};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Parameters {
+    unsubscribe_token: String,
+}
+
+// Reached from the `{{ unsubscribe_url }}` merge tag.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/unsubscribe",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscriber is unsubscribed."),
+        UnsubscribeError
+    )
+)]
+#[tracing::instrument(
+    name = "Unsubscribing a subscriber",
+    skip_all,
+    fields(publication = %publication.slug)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<Parameters>,
+    publication: Publication,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let subscription = sqlx::query!(
+        r#"
+    SELECT id, status FROM subscriptions
+    WHERE unsubscribe_token = $1 AND publication_id = $2
+    FOR UPDATE"#,
+        parameters.unsubscribe_token,
+        publication.id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscription.")?
+    .ok_or(UnsubscribeError::UnknownToken)?;
+
+    // Following the link again unsubscribes nobody new. Bounced and complained
+    // addresses are out of the audience already, and must stay suppressed.
+    if matches!(
+        subscription.status.as_str(),
+        "pending_confirmation" | "confirmed"
+    ) {
+        sqlx::query!(
+            "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1",
+            subscription.id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update the subscription status.")?;
+        emit_subscriber_event(
+            &mut transaction,
+            subscription.id,
+            SubscriberEvent::Unsubscribed,
+        )
+        .await
+        .context("Failed to emit the unsubscription event.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the unsubscription.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is not valid.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::UnknownToken => StatusCode::NOT_FOUND,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponses for UnsubscribeError {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        problem_responses(&[
+            (404, "The unsubscribe link is not valid."),
+            (500, "Something went wrong on our side."),
+        ])
+    }
+}
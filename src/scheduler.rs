@@ -0,0 +1,218 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    issue_delivery_worker::ExecutionOutcome,
+    newsletter_issues::{
+        get_ab_test_results, get_issue, mark_published, pick_winner, record_winner, start_delivery,
+        AbTestMetric, IssueError, PreparedIssue,
+    },
+    startup::{get_connection_pool, ApplicationBaseUrl},
+    tenant::get_publication_by_id,
+};
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let base_url = ApplicationBaseUrl(configuration.application.base_url);
+    scheduler_loop(connection_pool, base_url).await
+}
+
+async fn scheduler_loop(
+    connection_pool: PgPool,
+    base_url: ApplicationBaseUrl,
+) -> Result<(), anyhow::Error> {
+    loop {
+        let outcome = match try_publish_due_issue(&connection_pool, &base_url).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                try_decide_due_ab_test(&connection_pool, &base_url).await
+            }
+            outcome => outcome,
+        };
+        match outcome {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// Hands one due issue over to the delivery queue. The issue stays locked until
+// its deliveries are queued, other replicas skip it and editors can no longer
+// reschedule or cancel it.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = tracing::field::Empty), err)]
+pub async fn try_publish_due_issue(
+    connection_pool: &PgPool,
+    base_url: &ApplicationBaseUrl,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to start a transaction.")?;
+    let Some(due) = sqlx::query!(
+        r#"
+    SELECT id, publication_id FROM newsletter_issues
+    WHERE status = 'scheduled' AND scheduled_at <= $1
+    ORDER BY scheduled_at
+    FOR UPDATE
+    SKIP LOCKED
+    LIMIT 1"#,
+        Utc::now()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for due issues.")?
+    else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("newsletter_issue_id", display(due.id));
+
+    let issue = get_issue(connection_pool, due.publication_id, due.id)
+        .await?
+        .context("The due issue is gone.")?;
+    let publication = get_publication_by_id(connection_pool, due.publication_id)
+        .await?
+        .context("The publication of the due issue is gone.")?;
+    // The issue was checked when it was scheduled,
+    // segments and custom fields can have changed since.
+    let prepared =
+        PreparedIssue::prepare(connection_pool, &publication, base_url, issue.parts()).await;
+
+    match prepared {
+        Ok(prepared) => {
+            start_delivery(
+                &mut transaction,
+                prepared.audience(due.publication_id),
+                due.id,
+                issue.local_send_time.as_ref(),
+                issue.ab_test.as_ref(),
+            )
+            .await?;
+            mark_published(&mut transaction, due.publication_id, due.id)
+                .await
+                .context("Failed to mark the issue as published.")?;
+        }
+        Err(IssueError::ValidationError(e)) => {
+            tracing::error!(
+                error.message = %e,
+                "A scheduled issue can no longer be sent. It was turned back into a draft."
+            );
+            return_to_drafts(&mut transaction, due.id).await?;
+        }
+        Err(IssueError::UnexpectedError(e)) => return Err(e),
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the publication of a due issue.")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn return_to_drafts(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE newsletter_issues
+    SET status = 'draft', scheduled_at = NULL, local_send_time = NULL,
+        fallback_time_zone = NULL, updated_at = $2
+    WHERE id = $1"#,
+        issue_id,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to turn the issue back into a draft.")?;
+    Ok(())
+}
+
+// Sends the winner of one A/B test to the rest of the audience. Tests are decided
+// once their window is over and the whole test slice got its variant,
+// paused and cancelled issues wait.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = tracing::field::Empty), err)]
+pub async fn try_decide_due_ab_test(
+    connection_pool: &PgPool,
+    base_url: &ApplicationBaseUrl,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to start a transaction.")?;
+    let Some(due) = sqlx::query!(
+        r#"
+    SELECT ab_tests.newsletter_issue_id, ab_tests.metric, newsletter_issues.publication_id
+    FROM ab_tests
+    JOIN newsletter_issues ON newsletter_issues.id = ab_tests.newsletter_issue_id
+    WHERE ab_tests.winning_variant IS NULL AND ab_tests.decides_at <= $1
+        AND newsletter_issues.status = 'published'
+        AND NOT EXISTS (
+            SELECT 1 FROM issue_delivery_queue
+            WHERE issue_delivery_queue.newsletter_issue_id = ab_tests.newsletter_issue_id
+        )
+    ORDER BY ab_tests.decides_at
+    FOR UPDATE OF ab_tests
+    SKIP LOCKED
+    LIMIT 1"#,
+        Utc::now()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for due A/B tests.")?
+    else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    let issue_id = due.newsletter_issue_id;
+    Span::current().record("newsletter_issue_id", display(issue_id));
+
+    let metric = AbTestMetric::try_from(due.metric)?;
+    let results = get_ab_test_results(connection_pool, issue_id, metric)
+        .await
+        .context("Failed to fetch the results of the A/B test.")?;
+    let winner = pick_winner(&results).context("The A/B test has no variants.")?;
+    tracing::info!(
+        winning_variant = winner,
+        ?results,
+        "Picked the winner of an A/B test."
+    );
+
+    let issue = get_issue(connection_pool, due.publication_id, issue_id)
+        .await?
+        .context("The tested issue is gone.")?;
+    let publication = get_publication_by_id(connection_pool, due.publication_id)
+        .await?
+        .context("The publication of the tested issue is gone.")?;
+    let prepared =
+        PreparedIssue::prepare(connection_pool, &publication, base_url, issue.parts()).await;
+    match prepared {
+        Ok(prepared) => {
+            prepared
+                .audience(due.publication_id)
+                .enqueue_delivery(&mut transaction, issue_id, None, Some(winner))
+                .await?;
+        }
+        // The winner is recorded anyway, the test is not run again.
+        Err(IssueError::ValidationError(e)) => {
+            tracing::error!(
+                error.message = %e,
+                "The winner of an A/B test can't be sent to the rest of the audience."
+            );
+        }
+        Err(IssueError::UnexpectedError(e)) => return Err(e),
+    }
+    record_winner(&mut transaction, issue_id, winner)
+        .await
+        .context("Failed to record the winner of the A/B test.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the winner of the A/B test.")?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
@@ -1,16 +1,32 @@
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    click_tracking::HmacSecret,
+    configuration::{ApplicationSettings, DatabaseSettings, Settings},
     email_clients::EmailClient,
-    routes::{subscription::subsribe, subscription_confirm::subscription_confirm},
-    templating::{HealthCheckTemplate, HelloTemplate},
+    link_checker::LinkChecker,
+    outbound::DestinationPolicy,
+    problem::problem_details,
+    routes::{
+        admin, api,
+        email_events::{receive_email_event, EmailWebhookSecret},
+        newsletter::{
+            cancel_scheduled_newsletter, publish_draft, publish_newsletter, schedule_newsletter,
+        },
+        openapi::openapi_json,
+        preferences::update_preferences,
+        subscription::{subscription_form, subsribe},
+        subscription_confirm::subscription_confirm,
+        tracking::{follow_link, record_open},
+        unsubscribe::unsubscribe,
+    },
+    templating::HelloTemplate,
 };
 use askama::Template;
 use std::net::TcpListener;
 
 use actix_files as fs;
 use actix_web::{
-    dev::Server, http::header::ContentType, web, App, HttpRequest, HttpResponse, HttpServer,
-    Responder,
+    dev::Server, http::header::ContentType, middleware::ErrorHandlers, web, App, HttpRequest,
+    HttpResponse, HttpServer, Responder,
 };
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
@@ -19,11 +35,19 @@ pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
+    link_checker: LinkChecker,
+    destination_policy: DestinationPolicy,
+    application: ApplicationSettings,
+    email_webhook_secret: EmailWebhookSecret,
 ) -> Result<Server, std::io::Error> {
     let connection_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let link_checker = web::Data::new(link_checker);
+    let destination_policy = web::Data::new(destination_policy);
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+    let default_publication = web::Data::new(DefaultPublication(application.default_publication));
+    let hmac_secret = web::Data::new(HmacSecret(application.hmac_secret));
+    let email_webhook_secret = web::Data::new(email_webhook_secret);
 
     // actix_web will create one server for each CPU core.
     // Wrapping shared data in web::Data, which is an arc<T> pointer,
@@ -32,6 +56,9 @@ pub fn run(
         // Pattern matching against the path happens in the order
         // in which the routes are registered in the app.
         App::new()
+            // Error responses leave as problem details, with the request id
+            // the trace logger wrapped around it gave the request.
+            .wrap(ErrorHandlers::new().default_handler(problem_details))
             // Tracing on the server level
             // The trace logger is monitoring incoming requests,
             // creating a seperate logging span for each request.
@@ -40,16 +67,24 @@ pub fn run(
             .wrap(TracingLogger::default())
             .route("/hello", web::get().to(greet))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subsribe))
-            .route(
-                "/subscriptions/confirm",
-                web::get().to(subscription_confirm),
-            )
+            .route("/api/openapi.json", web::get().to(openapi_json))
+            .service(fs::Files::new("/api/docs", "./static/api-docs/").index_file("index.html"))
+            // Bounces and complaints concern an address, whatever the publication.
+            .route("/webhooks/email", web::post().to(receive_email_event))
+            // Publication routes are reachable both on the publication's own host
+            // and under a path prefix naming the publication.
+            .configure(publication_routes)
+            .service(web::scope("/p/{publication}").configure(publication_routes))
             .route("/{name}", web::get().to(greet))
             .service(fs::Files::new("/", "./static/root/").index_file("index.html"))
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
+            .app_data(link_checker.clone())
+            .app_data(destination_policy.clone())
             .app_data(base_url.clone())
+            .app_data(default_publication.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(email_webhook_secret.clone())
     })
     .listen(listener)?
     .run();
@@ -57,6 +92,151 @@ pub fn run(
     Ok(server)
 }
 
+fn publication_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/subscriptions", web::get().to(subscription_form))
+        .route("/subscriptions", web::post().to(subsribe))
+        .route(
+            "/subscriptions/confirm",
+            web::get().to(subscription_confirm),
+        )
+        .route("/subscriptions/unsubscribe", web::get().to(unsubscribe))
+        .route(
+            "/subscriptions/preferences",
+            web::post().to(update_preferences),
+        )
+        .route("/newsletters", web::post().to(publish_newsletter))
+        .route("/o/{tracking_token}", web::get().to(record_open))
+        .route("/r/{token}", web::get().to(follow_link))
+        .service(
+            web::scope("/api/v1")
+                .configure(api::configure_extractors)
+                .route("/emails", web::post().to(api::send_email))
+                .route("/emails/{message_id}", web::get().to(api::get_email))
+                .route("/subscribers", web::get().to(api::list_subscribers))
+                .route("/subscribers", web::post().to(api::create_subscriber))
+                .route(
+                    "/subscribers/{subscriber_id}",
+                    web::get().to(api::get_subscriber),
+                )
+                .route(
+                    "/subscribers/{subscriber_id}",
+                    web::patch().to(api::update_subscriber),
+                )
+                .route(
+                    "/subscribers/{subscriber_id}",
+                    web::delete().to(api::delete_subscriber),
+                ),
+        )
+        .service(
+            web::scope("/admin")
+                .route(
+                    "/email_templates",
+                    web::get().to(admin::list_email_templates),
+                )
+                .route(
+                    "/email_templates/{name}",
+                    web::put().to(admin::put_email_template),
+                )
+                .route(
+                    "/email_templates/{name}",
+                    web::delete().to(admin::delete_email_template),
+                )
+                .route("/fields", web::get().to(admin::list_custom_fields))
+                .route("/fields", web::post().to(admin::create_custom_field))
+                .route("/issues", web::get().to(admin::list_issues))
+                .route("/issues", web::post().to(admin::create_issue))
+                .route("/issues/{issue_id}", web::get().to(admin::get_issue))
+                .route("/issues/{issue_id}", web::put().to(admin::update_issue))
+                .route(
+                    "/issues/{issue_id}/preview",
+                    web::get().to(admin::preview_issue),
+                )
+                .route(
+                    "/issues/{issue_id}/test",
+                    web::post().to(admin::send_test_issue),
+                )
+                .route("/issues/{issue_id}/publish", web::post().to(publish_draft))
+                .route(
+                    "/issues/{issue_id}/schedule",
+                    web::put().to(schedule_newsletter),
+                )
+                .route(
+                    "/issues/{issue_id}/schedule",
+                    web::delete().to(cancel_scheduled_newsletter),
+                )
+                .route(
+                    "/issues/{issue_id}/ab_test",
+                    web::get().to(admin::get_ab_test_results_of_issue),
+                )
+                .route(
+                    "/issues/{issue_id}/clicks",
+                    web::get().to(admin::get_issue_clicks),
+                )
+                .route(
+                    "/issues/{issue_id}/opens",
+                    web::get().to(admin::get_issue_opens),
+                )
+                .route(
+                    "/issues/{issue_id}/progress",
+                    web::get().to(admin::stream_issue_progress),
+                )
+                .route(
+                    "/issues/{issue_id}/progress/page",
+                    web::get().to(admin::issue_progress_page),
+                )
+                .route(
+                    "/issues/{issue_id}/delivery",
+                    web::get().to(admin::get_issue_delivery),
+                )
+                .route(
+                    "/issues/{issue_id}/delivery/pause",
+                    web::post().to(admin::pause_issue_delivery),
+                )
+                .route(
+                    "/issues/{issue_id}/delivery/resume",
+                    web::post().to(admin::resume_issue_delivery),
+                )
+                .route(
+                    "/issues/{issue_id}/delivery/cancel",
+                    web::post().to(admin::cancel_issue_delivery),
+                )
+                .route("/tags", web::get().to(admin::list_tags))
+                .route("/tags", web::post().to(admin::create_tag))
+                .route("/segments", web::get().to(admin::list_segments))
+                .route("/segments", web::post().to(admin::create_segment))
+                .route("/segments/preview", web::post().to(admin::preview_segment))
+                .route("/segments/{segment}", web::get().to(admin::get_segment))
+                .service(
+                    web::resource("/subscribers/import")
+                        // Imports are the one place where large bodies are expected.
+                        .app_data(web::PayloadConfig::new(16 * 1024 * 1024))
+                        .route(web::post().to(admin::import_subscribers)),
+                )
+                .route(
+                    "/subscribers/{subscriber_id}/fields",
+                    web::put().to(admin::update_subscriber_fields),
+                )
+                .route(
+                    "/subscribers/{subscriber_id}/tags",
+                    web::put().to(admin::update_subscriber_tags),
+                )
+                .route("/webhooks", web::get().to(admin::list_webhooks))
+                .route("/webhooks", web::post().to(admin::create_webhook))
+                .route(
+                    "/webhooks/{webhook_id}",
+                    web::delete().to(admin::delete_webhook),
+                )
+                .route(
+                    "/webhooks/{webhook_id}/deliveries",
+                    web::get().to(admin::list_webhook_deliveries),
+                )
+                .route(
+                    "/webhooks/{webhook_id}/deliveries/{delivery_id}/replay",
+                    web::post().to(admin::replay_webhook_delivery),
+                ),
+        );
+}
+
 pub fn get_connection_pool(confi: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(confi.with_db())
 }
@@ -70,7 +250,8 @@ async fn greet(_req: HttpRequest) -> impl Responder {
         .body(hello)
 }
 
-async fn health_check(_req: HttpRequest) -> impl Responder {
+#[utoipa::path(get, path = "/health_check", responses((status = 200, description = "The application is up.")))]
+pub async fn health_check(_req: HttpRequest) -> impl Responder {
     HttpResponse::Ok().finish()
 }
 
@@ -81,6 +262,8 @@ pub struct Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct DefaultPublication(pub String);
+
 impl Application {
     pub async fn build(configurations: Settings) -> Result<Self, std::io::Error> {
         let connection = get_connection_pool(&configurations.database);
@@ -92,18 +275,15 @@ impl Application {
 
         let listener = TcpListener::bind(addr_to_bind).expect("Failed to bind random port.");
 
-        let sender_email = configurations
-            .email_client
-            .sender()
-            .expect("Invalide sender email.");
-
-        let timeout = configurations.email_client.timeout();
+        let email_webhook_secret =
+            EmailWebhookSecret(configurations.email_client.webhook_secret.clone());
+        let email_client = configurations.email_client.client();
 
-        let email_client = EmailClient::new(
-            configurations.email_client.base_url,
-            sender_email,
-            configurations.email_client.auth_token,
-            timeout,
+        let link_checker = LinkChecker::new(
+            configurations.link_checker.timeout(),
+            configurations.link_checker.max_concurrency_per_host,
+            configurations.link_checker.max_links,
+            configurations.outbound.policy(),
         );
 
         Ok(Self {
@@ -112,7 +292,10 @@ impl Application {
                 listener,
                 connection,
                 email_client,
-                configurations.application.base_url,
+                link_checker,
+                configurations.outbound.policy(),
+                configurations.application,
+                email_webhook_secret,
             )?,
         })
     }
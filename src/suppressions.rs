@@ -0,0 +1,79 @@
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::webhooks::{emit_subscriber_event, SubscriberEvent};
+
+// Why an address no longer gets any email from us.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SuppressionReason {
+    // The provider reported a hard bounce, the address does not exist.
+    Bounced,
+    // The recipient marked one of our emails as spam.
+    Complained,
+}
+
+impl SuppressionReason {
+    // Also the status their subscriptions end up in.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Bounced => "bounced",
+            SuppressionReason::Complained => "complained",
+        }
+    }
+
+    fn event(&self) -> SubscriberEvent {
+        match self {
+            SuppressionReason::Bounced => SubscriberEvent::Bounced,
+            SuppressionReason::Complained => SubscriberEvent::Complained,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Checking for a suppressed address", skip(connection_pool))]
+pub async fn is_suppressed(connection_pool: &PgPool, email: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT 1 AS found FROM suppressions WHERE email = lower($1)",
+        email
+    )
+    .fetch_optional(connection_pool)
+    .await?;
+    Ok(row.is_some())
+}
+
+// Suppresses an address for every publication. Complaints weigh more than
+// bounces, an address that complained is never turned into a bounced one.
+#[tracing::instrument(name = "Suppressing an address", skip(transaction))]
+pub async fn suppress(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO suppressions (email, reason, suppressed_at)
+    VALUES (lower($1), $2, $3)
+    ON CONFLICT (email) DO UPDATE
+    SET reason = EXCLUDED.reason, suppressed_at = EXCLUDED.suppressed_at
+    WHERE suppressions.reason <> 'complained'"#,
+        email,
+        reason.as_str(),
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    let subscriptions = sqlx::query!(
+        r#"
+    UPDATE subscriptions SET status = $2
+    WHERE lower(email) = lower($1) AND status NOT IN ($2, 'complained')
+    RETURNING id"#,
+        email,
+        reason.as_str()
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    // Each publication the address subscribed to hears about it.
+    for subscription in subscriptions {
+        emit_subscriber_event(transaction, subscription.id, reason.event()).await?;
+    }
+    Ok(())
+}
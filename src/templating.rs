@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use askama::Template;
 
+use crate::domain::{CustomFieldDefinition, CustomFieldKind, ValidationErrors};
+
 #[derive(Template)]
 #[template(path = "hello.html")]
-
 pub struct HelloTemplate<'a> {
     pub name: &'a str,
 }
@@ -12,3 +15,65 @@ pub struct HelloTemplate<'a> {
 pub struct HealthCheckTemplate<'a> {
     pub text: &'a str,
 }
+
+#[derive(Template)]
+#[template(path = "admin/issue_progress.html")]
+pub struct IssueProgressTemplate<'a> {
+    pub issue_id: &'a str,
+    pub progress_url: &'a str,
+}
+
+// The signup form of a publication, shown again with the errors of a rejected submission.
+#[derive(Template)]
+#[template(path = "subscribe.html")]
+pub struct SubscribeTemplate<'a> {
+    pub publication_name: &'a str,
+    pub name: &'a str,
+    pub email: &'a str,
+    // The custom fields of the publication, with what was submitted for them.
+    pub fields: &'a [CustomFieldDefinition],
+    pub values: &'a HashMap<String, String>,
+    pub errors: &'a ValidationErrors,
+    // A problem with the submission as a whole, e.g. a suppressed address.
+    pub message: Option<&'a str>,
+    pub subscribed: bool,
+}
+
+impl SubscribeTemplate<'_> {
+    fn value(&self, field: &CustomFieldDefinition) -> &str {
+        self.values.get(&field.key).map_or("", String::as_str)
+    }
+
+    fn is_checked(&self, field: &CustomFieldDefinition) -> bool {
+        field
+            .parse_value(self.value(field))
+            .is_ok_and(|value| value == serde_json::Value::Bool(true))
+    }
+
+    fn input_type(&self, field: &CustomFieldDefinition) -> &'static str {
+        match field.kind {
+            CustomFieldKind::Number => "number",
+            CustomFieldKind::Date => "date",
+            CustomFieldKind::Boolean => "checkbox",
+            CustomFieldKind::Text | CustomFieldKind::Enum => "text",
+        }
+    }
+}
+
+// The layout newsletter issues written in Markdown are sent in.
+#[derive(Template)]
+#[template(path = "email/newsletter.html")]
+pub struct NewsletterHtmlTemplate<'a> {
+    pub publication_name: &'a str,
+    pub title: &'a str,
+    pub content: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "email/newsletter.txt")]
+pub struct NewsletterTextTemplate<'a> {
+    pub publication_name: &'a str,
+    pub title: &'a str,
+    pub title_underline: &'a str,
+    pub content: &'a str,
+}
@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest, ResponseError};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail, routes::error_chain_fmt, startup::ApplicationBaseUrl,
+    startup::DefaultPublication,
+};
+
+// A publication is the tenant every subscriber, sender identity
+// and piece of email copy belongs to.
+#[derive(Debug, Clone)]
+pub struct Publication {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub base_url: Option<String>,
+    pub sender: Option<SubscriberEmail>,
+    pub confirmation_subject: String,
+    pub confirmation_message: String,
+}
+
+impl Publication {
+    // Links sent out on behalf of a publication have to point back at it.
+    // Publications without their own domain are served under `/p/{slug}`.
+    pub fn base_url(&self, application_base_url: &ApplicationBaseUrl) -> String {
+        match &self.base_url {
+            Some(base_url) => base_url.clone(),
+            None => format!("{}/p/{}", application_base_url.0, self.slug),
+        }
+    }
+}
+
+// The tenant is resolved, in order, from:
+// 1. the `/p/{publication}` path prefix,
+// 2. the Host header, never the forwarded ones clients can set to anything,
+// 3. the default publication from the configuration.
+impl FromRequest for Publication {
+    type Error = TenantError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let connection_pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let default_publication = req.app_data::<web::Data<DefaultPublication>>().cloned();
+        let slug = req.match_info().get("publication").map(String::from);
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| req.uri().host())
+            .unwrap_or_default()
+            .to_string();
+
+        Box::pin(async move {
+            let connection_pool =
+                connection_pool.context("The database pool is not registered on the app.")?;
+
+            if let Some(slug) = slug {
+                return get_publication_by_slug(&connection_pool, &slug)
+                    .await
+                    .context("Failed to look up the publication by its slug.")?
+                    .ok_or(TenantError::UnknownPublication(slug));
+            }
+
+            let hostname = host.split(':').next().unwrap_or_default();
+            if let Some(publication) = get_publication_by_host(&connection_pool, hostname)
+                .await
+                .context("Failed to look up the publication by its host.")?
+            {
+                return Ok(publication);
+            }
+
+            let default_publication = default_publication
+                .context("The default publication is not registered on the app.")?;
+            get_publication_by_slug(&connection_pool, &default_publication.0)
+                .await
+                .context("Failed to look up the default publication.")?
+                .ok_or_else(|| TenantError::UnknownPublication(default_publication.0.clone()))
+        })
+    }
+}
+
+struct PublicationRecord {
+    id: Uuid,
+    slug: String,
+    name: String,
+    base_url: Option<String>,
+    sender_email: Option<String>,
+    confirmation_subject: String,
+    confirmation_message: String,
+}
+
+impl TryFrom<PublicationRecord> for Publication {
+    type Error = anyhow::Error;
+
+    fn try_from(record: PublicationRecord) -> Result<Self, Self::Error> {
+        let sender = record
+            .sender_email
+            .map(SubscriberEmail::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .context("The publication has an invalid sender email.")?;
+
+        Ok(Self {
+            id: record.id,
+            slug: record.slug,
+            name: record.name,
+            base_url: record.base_url,
+            sender,
+            confirmation_subject: record.confirmation_subject,
+            confirmation_message: record.confirmation_message,
+        })
+    }
+}
+
+#[tracing::instrument(name = "Looking up a publication by slug", skip(connection_pool))]
+pub async fn get_publication_by_slug(
+    connection_pool: &PgPool,
+    slug: &str,
+) -> Result<Option<Publication>, anyhow::Error> {
+    let record = sqlx::query_as!(
+        PublicationRecord,
+        r#"
+    SELECT id, slug, name, base_url, sender_email, confirmation_subject, confirmation_message
+    FROM publications WHERE slug = $1"#,
+        slug
+    )
+    .fetch_optional(connection_pool)
+    .await?;
+
+    record.map(Publication::try_from).transpose()
+}
+
+#[tracing::instrument(name = "Looking up a publication by id", skip(connection_pool))]
+pub async fn get_publication_by_id(
+    connection_pool: &PgPool,
+    id: Uuid,
+) -> Result<Option<Publication>, anyhow::Error> {
+    let record = sqlx::query_as!(
+        PublicationRecord,
+        r#"
+    SELECT id, slug, name, base_url, sender_email, confirmation_subject, confirmation_message
+    FROM publications WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(connection_pool)
+    .await?;
+
+    record.map(Publication::try_from).transpose()
+}
+
+#[tracing::instrument(name = "Looking up a publication by host", skip(connection_pool))]
+async fn get_publication_by_host(
+    connection_pool: &PgPool,
+    host: &str,
+) -> Result<Option<Publication>, anyhow::Error> {
+    let record = sqlx::query_as!(
+        PublicationRecord,
+        r#"
+    SELECT id, slug, name, base_url, sender_email, confirmation_subject, confirmation_message
+    FROM publications WHERE host = $1"#,
+        host
+    )
+    .fetch_optional(connection_pool)
+    .await?;
+
+    record.map(Publication::try_from).transpose()
+}
+
+#[derive(thiserror::Error)]
+pub enum TenantError {
+    #[error("There is no publication called {0}.")]
+    UnknownPublication(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TenantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TenantError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TenantError::UnknownPublication(_) => StatusCode::NOT_FOUND,
+            TenantError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
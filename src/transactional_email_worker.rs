@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings, domain::SubscriberEmail, email_clients::EmailClient,
+    issue_delivery_worker::ExecutionOutcome, startup::get_connection_pool,
+    tenant::get_publication_by_id,
+};
+
+// Attempts made before an email is given up on.
+const MAX_ATTEMPTS: i16 = 5;
+const RETRY_BACKOFF: chrono::Duration = chrono::Duration::seconds(30);
+
+pub async fn run_transactional_email_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    worker_loop(connection_pool, email_client).await
+}
+
+async fn worker_loop(
+    connection_pool: PgPool,
+    email_client: EmailClient,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_send_transactional_email(&connection_pool, &email_client).await {
+            // Someone is usually waiting on these, the queue is polled more often.
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+struct EmailTask {
+    id: Uuid,
+    publication_id: Uuid,
+    recipient: String,
+    subject: String,
+    html: String,
+    text: String,
+    n_attempts: i16,
+}
+
+// Sends one email of the queue. Failed emails go back to the queue
+// with an exponential backoff, until they are given up on.
+#[tracing::instrument(
+    skip_all,
+    fields(transactional_email_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_send_transactional_email(
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(connection_pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("transactional_email_id", display(task.id));
+
+    let outcome = send(connection_pool, email_client, &task).await;
+    if let Err(e) = &outcome {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            n_attempts = task.n_attempts + 1,
+            "Failed to send a transactional email."
+        );
+    }
+    record_attempt(&mut transaction, &task, outcome.err()).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the transactional email.")?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn send(
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+    task: &EmailTask,
+) -> Result<(), anyhow::Error> {
+    let publication = get_publication_by_id(connection_pool, task.publication_id)
+        .await?
+        .context("The publication of the email no longer exists.")?;
+    let recipient = SubscriberEmail::parse(task.recipient.clone()).map_err(anyhow::Error::msg)?;
+    email_client
+        .send_email_from(
+            publication.sender.as_ref(),
+            recipient,
+            &task.subject,
+            &task.html,
+            &task.text,
+        )
+        .await
+        .context("Failed to send the email.")
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    connection_pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, EmailTask)>, anyhow::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to start a transaction.")?;
+    let task = sqlx::query_as!(
+        EmailTask,
+        r#"
+    SELECT id, publication_id, recipient, subject, html, text, n_attempts
+    FROM transactional_emails
+    WHERE status = 'queued' AND execute_after <= now()
+    ORDER BY execute_after
+    FOR UPDATE
+    SKIP LOCKED
+    LIMIT 1"#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to dequeue a transactional email.")?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+// Backs off exponentially, 30 seconds after the first failure, then 1, 2 and 4 minutes.
+#[tracing::instrument(skip_all)]
+async fn record_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &EmailTask,
+    error: Option<anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    let n_attempts = task.n_attempts + 1;
+    let status = match (&error, n_attempts < MAX_ATTEMPTS) {
+        (None, _) => "sent",
+        (Some(_), true) => "queued",
+        (Some(_), false) => "failed",
+    };
+    let backoff = RETRY_BACKOFF * 2i32.pow(task.n_attempts as u32);
+    sqlx::query!(
+        r#"
+    UPDATE transactional_emails
+    SET status = $2, n_attempts = $3, last_error = $4, execute_after = $5, sent_at = $6
+    WHERE id = $1"#,
+        task.id,
+        status,
+        n_attempts,
+        error.as_ref().map(|e| format!("{:#}", e)),
+        now + backoff,
+        error.is_none().then_some(now)
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the transactional email attempt.")?;
+    Ok(())
+}
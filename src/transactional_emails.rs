@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::MergeTemplate,
+    email_pipeline::{post_process_html, PostProcessError},
+    newsletter_issues::Email,
+    routes::error_chain_fmt,
+};
+
+// A named email other services send through us, e.g. a password reset.
+// Its merge tags are the variables it declares, filled in by the caller.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct EmailTemplate {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    #[serde(default)]
+    pub variables: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct CompiledTemplate {
+    subject: MergeTemplate,
+    html: MergeTemplate,
+    text: MergeTemplate,
+    variables: Vec<String>,
+}
+
+// Template names end up in URLs and in the code of other services.
+pub fn validate_template_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is not a valid template name, use up to 64 lowercase letters, digits, '-' or '_'.",
+            name
+        ))
+    }
+}
+
+impl EmailTemplate {
+    pub fn compile(&self) -> Result<CompiledTemplate, TemplateError> {
+        for variable in &self.variables {
+            let valid = !variable.is_empty()
+                && variable
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(TemplateError::ValidationError(format!(
+                    "{} is not a valid variable name, use letters, digits or '_'.",
+                    variable
+                )));
+            }
+        }
+        let variables: Vec<&str> = self.variables.iter().map(String::as_str).collect();
+        let parse = |field: &str, template: &str| {
+            MergeTemplate::parse(template, &variables)
+                .map_err(|e| TemplateError::ValidationError(format!("Invalid {}: {}", field, e)))
+        };
+        let html = post_process_html(&self.html, None).map_err(|e| match e {
+            PostProcessError::TooLarge { .. } => TemplateError::ValidationError(e.to_string()),
+            PostProcessError::UnexpectedError(e) => TemplateError::UnexpectedError(e),
+        })?;
+
+        Ok(CompiledTemplate {
+            subject: parse("subject", &self.subject)?,
+            html: parse("HTML content", &html)?,
+            text: parse("text content", &self.text)?,
+            variables: self.variables.clone(),
+        })
+    }
+}
+
+impl CompiledTemplate {
+    // Callers have to fill in every variable the template can't do without,
+    // and only those it declares: a typo would otherwise go unnoticed.
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<Email, TemplateError> {
+        if let Some(unknown) = values.keys().find(|name| !self.variables.contains(name)) {
+            return Err(TemplateError::ValidationError(format!(
+                "The template has no variable {}.",
+                unknown
+            )));
+        }
+        let mut missing: Vec<&str> = [&self.subject, &self.html, &self.text]
+            .into_iter()
+            .flat_map(MergeTemplate::required_tags)
+            .filter(|name| values.get(*name).map_or(true, String::is_empty))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        if !missing.is_empty() {
+            return Err(TemplateError::ValidationError(format!(
+                "Missing template variables: {}.",
+                missing.join(", ")
+            )));
+        }
+
+        let lookup = |name: &str| values.get(name).cloned();
+        Ok(Email {
+            subject: self.subject.render(lookup, false),
+            html: self.html.render(lookup, true),
+            text: self.text.render(lookup, false),
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum TemplateError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(name = "Fetching an email template", skip(connection_pool))]
+pub async fn get_email_template(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    name: &str,
+) -> Result<Option<EmailTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailTemplate,
+        r#"
+    SELECT subject, html, text, variables
+    FROM email_templates
+    WHERE publication_id = $1 AND name = $2"#,
+        publication_id,
+        name
+    )
+    .fetch_optional(connection_pool)
+    .await
+}
+
+// Where a transactional email is at, as reported to the service that sent it.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct TransactionalEmailStatus {
+    pub id: Uuid,
+    pub template: String,
+    pub to: String,
+    // queued, sent, failed or suppressed.
+    pub status: String,
+    pub n_attempts: i16,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Fetching a transactional email", skip(connection_pool))]
+pub async fn get_transactional_email(
+    connection_pool: &PgPool,
+    publication_id: Uuid,
+    id: Uuid,
+) -> Result<Option<TransactionalEmailStatus>, sqlx::Error> {
+    sqlx::query_as!(
+        TransactionalEmailStatus,
+        r#"
+    SELECT id, template_name AS template, recipient AS to, status, n_attempts,
+        last_error, created_at, sent_at
+    FROM transactional_emails
+    WHERE publication_id = $1 AND id = $2"#,
+        publication_id,
+        id
+    )
+    .fetch_optional(connection_pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{validate_template_name, EmailTemplate};
+    use claims::{assert_err, assert_ok};
+
+    fn password_reset() -> EmailTemplate {
+        EmailTemplate {
+            subject: r#"Reset your password, {{ name | default: "friend" }}"#.to_string(),
+            html: r#"<p><a href="{{ reset_url }}">Reset it</a></p>"#.to_string(),
+            text: "Reset it: {{ reset_url }}".to_string(),
+            variables: vec!["name".to_string(), "reset_url".to_string()],
+        }
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn variables_are_filled_in() {
+        let template = password_reset().compile().unwrap();
+        let email = template
+            .render(&values(&[("reset_url", "https://example.com/r?a=1&b=2")]))
+            .unwrap();
+
+        assert_eq!(email.subject, "Reset your password, friend");
+        assert_eq!(email.text, "Reset it: https://example.com/r?a=1&b=2");
+        assert!(email.html.contains("https://example.com/r?a=1&amp;b=2"));
+    }
+
+    #[test]
+    fn required_variables_have_to_be_given() {
+        let template = password_reset().compile().unwrap();
+
+        assert_err!(template.render(&values(&[("name", "Ursula")])));
+        assert_err!(template.render(&values(&[("reset_url", "")])));
+    }
+
+    #[test]
+    fn undeclared_variables_are_rejected() {
+        let template = password_reset().compile().unwrap();
+        assert_err!(template.render(&values(&[("reset_url", "x"), ("nmae", "Ursula")])));
+
+        let mut template = password_reset();
+        template.variables.pop();
+        assert_err!(template.compile());
+    }
+
+    #[test]
+    fn template_names_are_url_friendly() {
+        assert_ok!(validate_template_name("password-reset_2"));
+        for name in [
+            "",
+            "Password-Reset",
+            "password reset",
+            "a/b",
+            &"a".repeat(65),
+        ] {
+            assert_err!(validate_template_name(name));
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use reqwest::Url;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    issue_delivery_worker::ExecutionOutcome,
+    outbound::DestinationPolicy,
+    startup::get_connection_pool,
+    webhooks::{check_endpoint, sign_payload},
+};
+
+// Attempts made before a delivery is given up on, it can still be replayed.
+const MAX_ATTEMPTS: i16 = 6;
+const RETRY_BACKOFF: chrono::Duration = chrono::Duration::seconds(30);
+// Endpoints are expected to acknowledge events and process them later.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn run_webhook_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let policy = configuration.outbound.policy();
+    worker_loop(connection_pool, webhook_client(&policy), policy).await
+}
+
+pub fn webhook_client(policy: &DestinationPolicy) -> reqwest::Client {
+    policy
+        .client_builder()
+        .timeout(REQUEST_TIMEOUT)
+        // A redirect could lead anywhere, endpoints answer where they were registered.
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Failed to build the webhook client.")
+}
+
+async fn worker_loop(
+    connection_pool: PgPool,
+    http_client: reqwest::Client,
+    policy: DestinationPolicy,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_deliver_webhook(&connection_pool, &http_client, &policy).await {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+struct WebhookTask {
+    id: Uuid,
+    event_id: Uuid,
+    url: String,
+    secret: String,
+    payload: serde_json::Value,
+    n_attempts: i16,
+}
+
+// How one attempt went, as shown in the delivery log.
+struct Attempt {
+    response_status: Option<i16>,
+    error: Option<String>,
+}
+
+impl Attempt {
+    fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+// Sends one event of the queue. Failed deliveries go back to the queue with
+// an exponential backoff, endpoints down for a while don't lose events.
+#[tracing::instrument(
+    skip_all,
+    fields(webhook_delivery_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_deliver_webhook(
+    connection_pool: &PgPool,
+    http_client: &reqwest::Client,
+    policy: &DestinationPolicy,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((mut transaction, task)) = dequeue_task(connection_pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current().record("webhook_delivery_id", display(task.id));
+
+    let attempt = send(http_client, policy, &task).await;
+    if !attempt.succeeded() {
+        tracing::warn!(
+            error.message = attempt.error.as_deref().unwrap_or_default(),
+            n_attempts = task.n_attempts + 1,
+            "Failed to deliver a webhook event."
+        );
+    }
+    record_attempt(&mut transaction, &task, &attempt).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the webhook delivery.")?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn send(
+    http_client: &reqwest::Client,
+    policy: &DestinationPolicy,
+    task: &WebhookTask,
+) -> Attempt {
+    let checked = match Url::parse(&task.url) {
+        Ok(url) => check_endpoint(policy, &url).await,
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(error) = checked {
+        return Attempt {
+            response_status: None,
+            error: Some(error),
+        };
+    }
+
+    let body = task.payload.to_string();
+    let timestamp = Utc::now().timestamp();
+    let response = http_client
+        .post(&task.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", task.event_id.to_string())
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "X-Webhook-Signature",
+            sign_payload(&task.secret, timestamp, body.as_bytes()),
+        )
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            Attempt {
+                response_status: Some(status.as_u16() as i16),
+                error: (!status.is_success())
+                    .then(|| format!("The endpoint answered with {}.", status)),
+            }
+        }
+        Err(e) => {
+            // The delivery log is shown to tenants, what went wrong on the
+            // network stays in our logs.
+            tracing::warn!(error.cause_chain = ?e, "Failed to reach a webhook endpoint.");
+            let error = if e.is_timeout() {
+                "The endpoint did not answer in time."
+            } else {
+                "The endpoint could not be reached."
+            };
+            Attempt {
+                response_status: None,
+                error: Some(error.to_string()),
+            }
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    connection_pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, WebhookTask)>, anyhow::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to start a transaction.")?;
+    let task = sqlx::query_as!(
+        WebhookTask,
+        r#"
+    SELECT webhook_deliveries.id, webhook_deliveries.event_id, webhook_deliveries.payload,
+        webhook_deliveries.n_attempts, webhook_endpoints.url, webhook_endpoints.secret
+    FROM webhook_deliveries
+    JOIN webhook_endpoints ON webhook_endpoints.id = webhook_deliveries.endpoint_id
+    WHERE webhook_deliveries.status = 'pending' AND webhook_deliveries.execute_after <= now()
+    ORDER BY webhook_deliveries.execute_after
+    FOR UPDATE OF webhook_deliveries
+    SKIP LOCKED
+    LIMIT 1"#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to dequeue a webhook delivery.")?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+// Backs off exponentially, 30 seconds after the first failure, then 1, 2, 4 and 8 minutes.
+#[tracing::instrument(skip_all)]
+async fn record_attempt(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &WebhookTask,
+    attempt: &Attempt,
+) -> Result<(), anyhow::Error> {
+    let now = Utc::now();
+    let n_attempts = task.n_attempts + 1;
+    let status = match (attempt.succeeded(), n_attempts < MAX_ATTEMPTS) {
+        (true, _) => "delivered",
+        (false, true) => "pending",
+        (false, false) => "failed",
+    };
+    let backoff = RETRY_BACKOFF * 2i32.pow(task.n_attempts as u32);
+    sqlx::query!(
+        r#"
+    UPDATE webhook_deliveries
+    SET status = $2, n_attempts = $3, last_attempt_at = $4, last_response_status = $5,
+        last_error = $6, execute_after = $7, delivered_at = $8
+    WHERE id = $1"#,
+        task.id,
+        status,
+        n_attempts,
+        now,
+        attempt.response_status,
+        attempt.error,
+        now + backoff,
+        attempt.succeeded().then_some(now)
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the webhook delivery attempt.")?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha2::Sha256;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::outbound::DestinationPolicy;
+
+// What happened to a subscriber, as told to the webhook endpoints of their publication.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscriberEvent {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+}
+
+impl SubscriberEvent {
+    pub const ALL: [SubscriberEvent; 5] = [
+        SubscriberEvent::Subscribed,
+        SubscriberEvent::Confirmed,
+        SubscriberEvent::Unsubscribed,
+        SubscriberEvent::Bounced,
+        SubscriberEvent::Complained,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberEvent::Subscribed => "subscriber.subscribed",
+            SubscriberEvent::Confirmed => "subscriber.confirmed",
+            SubscriberEvent::Unsubscribed => "subscriber.unsubscribed",
+            SubscriberEvent::Bounced => "subscriber.bounced",
+            SubscriberEvent::Complained => "subscriber.complained",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|event| event.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known event type.", s))
+    }
+}
+
+// Queues the event for every endpoint of the publication of the subscriber
+// listening to it. Called in the transaction changing the subscriber,
+// events are never emitted for changes that were rolled back.
+#[tracing::instrument(name = "Emitting a subscriber event", skip(transaction))]
+pub async fn emit_subscriber_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    event: SubscriberEvent,
+) -> Result<u64, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+    SELECT subscriptions.publication_id, subscriptions.email, subscriptions.name,
+        subscriptions.status, publications.slug AS publication
+    FROM subscriptions
+    JOIN publications ON publications.id = subscriptions.publication_id
+    WHERE subscriptions.id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    let event_id = Uuid::new_v4();
+    let now = Utc::now();
+    let payload = serde_json::json!({
+        "id": event_id,
+        "type": event.as_str(),
+        "created_at": now,
+        "publication": subscriber.publication,
+        "data": {
+            "subscriber": {
+                "id": subscriber_id,
+                "email": subscriber.email,
+                "name": subscriber.name,
+                "status": subscriber.status,
+            }
+        }
+    });
+    let result = sqlx::query!(
+        r#"
+    INSERT INTO webhook_deliveries
+        (id, endpoint_id, event_id, event_type, payload, created_at, status, execute_after)
+    SELECT gen_random_uuid(), id, $2, $3, $4, $5, 'pending', $5
+    FROM webhook_endpoints
+    WHERE publication_id = $1 AND $3 = ANY(event_types)"#,
+        subscriber.publication_id,
+        event_id,
+        event.as_str(),
+        payload,
+        now
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Where payloads may be sent. Checked when an endpoint is created and again
+// before every delivery, its host may resolve somewhere else by then.
+pub async fn check_endpoint(policy: &DestinationPolicy, url: &Url) -> Result<(), String> {
+    // Servers on localhost have no certificates,
+    // plain http is only accepted where they are.
+    let plain_http_allowed = policy.allows_private() && url.scheme() == "http";
+    if url.scheme() != "https" && !plain_http_allowed {
+        return Err("Webhooks are delivered over https.".to_string());
+    }
+    policy.check(url).await
+}
+
+// Endpoints check `sha256=` and the hex HMAC of `{timestamp}.{body}`, keyed with
+// their secret. Signing the timestamp lets them turn down replayed requests.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size.");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_endpoint, sign_payload, SubscriberEvent};
+    use crate::outbound::DestinationPolicy;
+    use claims::{assert_err, assert_ok, assert_ok_eq};
+    use reqwest::Url;
+
+    #[test]
+    fn event_types_round_trip() {
+        for event in SubscriberEvent::ALL {
+            assert_ok_eq!(SubscriberEvent::parse(event.as_str()), event);
+        }
+        assert_err!(SubscriberEvent::parse("subscriber.deleted"));
+    }
+
+    #[tokio::test]
+    async fn endpoints_must_be_public_and_use_https() {
+        let policy = DestinationPolicy::new(false);
+        for url in [
+            "http://93.184.216.34/hooks",
+            "https://127.0.0.1/hooks",
+            "https://169.254.169.254/latest/meta-data/",
+            "https://10.0.0.12:8080/hooks",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_err!(check_endpoint(&policy, &url).await, "{} was accepted", url);
+        }
+        let url = Url::parse("https://93.184.216.34/hooks").unwrap();
+        assert_ok!(check_endpoint(&policy, &url).await);
+    }
+
+    #[tokio::test]
+    async fn local_endpoints_may_use_http_in_development() {
+        let policy = DestinationPolicy::new(true);
+        let url = Url::parse("http://127.0.0.1:8080/hooks").unwrap();
+        assert_ok!(check_endpoint(&policy, &url).await);
+        let url = Url::parse("ftp://127.0.0.1/hooks").unwrap();
+        assert_err!(check_endpoint(&policy, &url).await);
+    }
+
+    #[test]
+    fn the_timestamp_is_part_of_the_signature() {
+        let signature = sign_payload("secret", 1_715_000_000, b"{}");
+
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert_eq!(signature, sign_payload("secret", 1_715_000_000, b"{}"));
+        assert_ne!(signature, sign_payload("secret", 1_715_000_001, b"{}"));
+        assert_ne!(signature, sign_payload("another", 1_715_000_000, b"{}"));
+    }
+}
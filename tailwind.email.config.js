@@ -0,0 +1,23 @@
+/** @type {import('tailwindcss').Config} */
+// Styles for emails are inlined by the email pipeline (src/email_pipeline),
+// so only rules that make sense as inline styles are generated.
+module.exports = {
+  content: ["./templates/email/**/*.html"],
+  corePlugins: {
+    preflight: false,
+    // Opacity utilities are implemented with CSS variables,
+    // which email clients don't support.
+    textOpacity: false,
+    backgroundOpacity: false,
+    borderOpacity: false,
+  },
+  theme: {
+    extend: {
+      // Web fonts are not loaded by most email clients.
+      fontFamily: {
+        sans: ["Helvetica", "Arial", "sans-serif"],
+      },
+    },
+  },
+  plugins: [],
+};
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_audience(app: &TestApp, size: usize) {
+    let mut csv = "email,name\n".to_string();
+    for i in 0..size {
+        csv.push_str(&format!("reader{}@example.com,Reader {}\n", i, i));
+    }
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+fn issue_with_ab_test() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Subject A",
+        "content": {"markdown": "Hi {{ name }}"},
+        "ab_test": {
+            "variants": [
+                {},
+                {"title": "Subject B for {{ name }}"}
+            ],
+            "test_share": 40,
+            "metric": "opens",
+            "window_minutes": 60
+        },
+        "track_opens": true
+    })
+}
+
+async fn create_draft(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    app.admin_request(Method::POST, "/admin/issues")
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn publish_draft(app: &TestApp, issue_id: &str) {
+    app.admin_request(Method::POST, &format!("/admin/issues/{}/publish", issue_id))
+        .json(&serde_json::json!({"link_check": "skip"}))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap();
+}
+
+async fn start_ab_test(app: &TestApp) -> String {
+    let body: serde_json::Value = create_draft(app, issue_with_ab_test())
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let issue_id = body["issue_id"].as_str().unwrap().to_string();
+    publish_draft(app, &issue_id).await;
+    issue_id
+}
+
+// Subject lines of the emails sent so far, by recipient.
+async fn sent_subjects(app: &TestApp) -> HashMap<String, String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            (
+                body["To"].as_str().unwrap().to_string(),
+                body["Subject"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+async fn end_test_window(app: &TestApp) {
+    sqlx::query!("UPDATE ab_tests SET decides_at = now() - interval '1 minute'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn the_winning_variant_goes_to_the_rest_of_the_audience() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app, 10).await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(10)
+        .mount(&test_app.email_server)
+        .await;
+    let issue_id = start_ab_test(&test_app).await;
+
+    // 40% of the audience, split evenly between the variants.
+    test_app.dispatch_all_pending_emails().await;
+    let test_slice = sent_subjects(&test_app).await;
+    assert_eq!(test_slice.len(), 4);
+    let b_readers: Vec<_> = test_slice
+        .iter()
+        .filter(|(_, subject)| subject.starts_with("Subject B for Reader"))
+        .map(|(email, _)| email.clone())
+        .collect();
+    assert_eq!(b_readers.len(), 2);
+
+    // The test is only decided once its window is over.
+    test_app.decide_due_ab_tests().await;
+    assert_eq!(sent_subjects(&test_app).await.len(), 4);
+
+    sqlx::query!(
+        r#"
+    INSERT INTO delivery_events (newsletter_issue_id, subscriber_email, kind, occurred_at)
+    VALUES ($1, $2, 'open', now())"#,
+        uuid::Uuid::parse_str(&issue_id).unwrap(),
+        b_readers[0]
+    )
+    .execute(&test_app.connection_pool)
+    .await
+    .unwrap();
+    end_test_window(&test_app).await;
+    test_app.decide_due_ab_tests().await;
+    test_app.dispatch_all_pending_emails().await;
+
+    let subjects = sent_subjects(&test_app).await;
+    assert_eq!(subjects.len(), 10);
+    for (email, subject) in &subjects {
+        if !test_slice.contains_key(email) {
+            assert!(subject.starts_with("Subject B for Reader"));
+        }
+    }
+
+    let results: serde_json::Value = test_app
+        .admin_request(Method::GET, &format!("/admin/issues/{}/ab_test", issue_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(results["winning_variant"], 1);
+    assert_eq!(
+        results["variants"],
+        serde_json::json!([
+            {"variant": 0, "delivered": 2, "engaged": 0},
+            {"variant": 1, "delivered": 2, "engaged": 1},
+        ])
+    );
+
+    // Every delivery records the variant it got.
+    let variants = sqlx::query!(
+        "SELECT variant, COUNT(*) AS count FROM issue_deliveries GROUP BY variant ORDER BY variant"
+    )
+    .fetch_all(&test_app.connection_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|row| (row.variant, row.count))
+    .collect::<Vec<_>>();
+    assert_eq!(variants, vec![(Some(0), Some(2)), (Some(1), Some(8))]);
+}
+
+#[tokio::test]
+async fn paused_ab_tests_wait_for_their_winner() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app, 5).await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+    let issue_id = start_ab_test(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Nothing is queued, but the rest of the audience is still to come.
+    let response = test_app
+        .admin_request(
+            Method::POST,
+            &format!("/admin/issues/{}/delivery/pause", issue_id),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["pending"], 0);
+    assert!(body["ab_test"]["winning_variant"].is_null());
+
+    end_test_window(&test_app).await;
+    test_app.decide_due_ab_tests().await;
+    test_app.dispatch_all_pending_emails().await;
+    assert_eq!(sent_subjects(&test_app).await.len(), 2);
+}
+
+#[tokio::test]
+async fn issues_show_their_variants() {
+    let test_app = spawn_app().await;
+    let body: serde_json::Value = create_draft(&test_app, issue_with_ab_test())
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let issue: serde_json::Value = test_app
+        .admin_request(
+            Method::GET,
+            &format!("/admin/issues/{}", body["issue_id"].as_str().unwrap()),
+        )
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let ab_test = &issue["ab_test"];
+    assert_eq!(ab_test["metric"], "opens");
+    assert_eq!(ab_test["test_share"], 40);
+    assert_eq!(ab_test["variants"][0]["title"], "Subject A");
+    assert_eq!(ab_test["variants"][1]["title"], "Subject B for {{ name }}");
+    // What a variant leaves out comes from the issue.
+    assert_eq!(ab_test["variants"][1]["markdown"], "Hi {{ name }}");
+}
+
+#[tokio::test]
+async fn invalid_ab_tests_are_rejected() {
+    let test_app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"variants": [{}], "test_share": 40, "metric": "opens", "window_minutes": 60}),
+            "a single variant",
+        ),
+        (
+            serde_json::json!({"variants": [{}, {}], "test_share": 100, "metric": "opens", "window_minutes": 60}),
+            "a test slice of the whole audience",
+        ),
+        (
+            serde_json::json!({"variants": [{}, {}], "test_share": 40, "metric": "opens", "window_minutes": 0}),
+            "an empty window",
+        ),
+        (
+            serde_json::json!({"variants": [{}, {"title": "{{ nmae }}"}], "test_share": 40, "metric": "opens", "window_minutes": 60}),
+            "an unknown merge tag in a variant",
+        ),
+    ];
+
+    for (ab_test, description) in test_cases {
+        let mut body = issue_with_ab_test();
+        body["ab_test"] = ab_test;
+        let response = create_draft(&test_app, body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+
+    // The metric of a test has to be tracked.
+    for metric in ["opens", "clicks"] {
+        let mut body = issue_with_ab_test();
+        body["track_opens"] = false.into();
+        body["ab_test"]["metric"] = metric.into();
+        let response = create_draft(&test_app, body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request for a test on {} that are not tracked.",
+            metric
+        );
+    }
+}
+
+#[tokio::test]
+async fn ab_tests_are_not_delivered_in_time_zone_waves() {
+    let test_app = spawn_app().await;
+    let body: serde_json::Value = create_draft(&test_app, issue_with_ab_test())
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    let response = test_app
+        .admin_request(
+            Method::PUT,
+            &format!(
+                "/admin/issues/{}/schedule",
+                body["issue_id"].as_str().unwrap()
+            ),
+        )
+        .json(&serde_json::json!({
+            "local_time": (chrono::Utc::now() + chrono::Duration::days(3)).naive_utc(),
+            "link_check": "skip",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+}
@@ -0,0 +1,250 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_subscriber(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    app.admin_request(Method::POST, "/api/v1/subscribers")
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to create the subscriber.")
+}
+
+async fn import_subscriber(app: &TestApp, email: &str) -> serde_json::Value {
+    create_subscriber(
+        app,
+        serde_json::json!({"email": email, "name": "Ursula", "skip_confirmation": true}),
+    )
+    .await
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+async fn list(app: &TestApp, query: &str) -> serde_json::Value {
+    app.admin_request(Method::GET, &format!("/api/v1/subscribers?{}", query))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+fn emails(page: &serde_json::Value) -> Vec<String> {
+    page["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|subscriber| subscriber["email"].as_str().unwrap().to_string())
+        .collect()
+}
+
+async fn assert_problem(response: reqwest::Response, status: u16) -> serde_json::Value {
+    assert_eq!(status, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], status);
+    assert!(problem["title"].is_string());
+    problem
+}
+
+#[tokio::test]
+async fn new_subscribers_get_a_confirmation_email() {
+    let test_app = spawn_app().await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = create_subscriber(
+        &test_app,
+        serde_json::json!({
+            "email": "ursula_le_guin@gmail.com",
+            "name": "le guin",
+            "time_zone": "Europe/Berlin",
+            "tags": ["sci-fi"]
+        }),
+    )
+    .await;
+    assert_eq!(201, response.status().as_u16());
+    let location = response.headers()["Location"].to_str().unwrap().to_string();
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["status"], "pending_confirmation");
+    assert_eq!(subscriber["time_zone"], "Europe/Berlin");
+    assert_eq!(subscriber["tags"], serde_json::json!(["sci-fi"]));
+    assert_eq!(
+        location,
+        format!("/api/v1/subscribers/{}", subscriber["id"].as_str().unwrap())
+    );
+
+    let fetched: serde_json::Value = test_app
+        .admin_request(Method::GET, &location)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(fetched, subscriber);
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    test_app.get_confirmation_link(email_request);
+}
+
+#[tokio::test]
+async fn subscribers_with_consent_skip_double_opt_in() {
+    let test_app = spawn_app().await;
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let subscriber = import_subscriber(&test_app, "ursula@example.com").await;
+    assert_eq!(subscriber["status"], "confirmed");
+
+    let response = create_subscriber(
+        &test_app,
+        serde_json::json!({"email": "ursula@example.com", "name": "Ursula", "skip_confirmation": true}),
+    )
+    .await;
+    assert_problem(response, 409).await;
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_page_by_page() {
+    let test_app = spawn_app().await;
+    let mut created = Vec::new();
+    for i in 0..5 {
+        let email = format!("reader{}@example.com", i);
+        import_subscriber(&test_app, &email).await;
+        created.push(email);
+    }
+
+    let mut listed = Vec::new();
+    let mut page = list(&test_app, "limit=2").await;
+    loop {
+        let page_emails = emails(&page);
+        assert!(page_emails.len() <= 2);
+        listed.extend(page_emails);
+        match page["next_cursor"].as_str() {
+            Some(cursor) => page = list(&test_app, &format!("limit=2&cursor={}", cursor)).await,
+            None => break,
+        }
+    }
+    assert_eq!(listed, created);
+}
+
+#[tokio::test]
+async fn subscribers_are_filtered_by_status_and_date() {
+    let test_app = spawn_app().await;
+    import_subscriber(&test_app, "early@example.com").await;
+    import_subscriber(&test_app, "late@example.com").await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2024-01-01T00:00:00Z' WHERE email = 'early@example.com'"
+    )
+    .execute(&test_app.connection_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE email = 'late@example.com'"
+    )
+    .execute(&test_app.connection_pool)
+    .await
+    .unwrap();
+
+    let page = list(&test_app, "status=confirmed").await;
+    assert_eq!(emails(&page), ["early@example.com"]);
+    let page = list(&test_app, "subscribed_after=2024-02-01T00:00:00Z").await;
+    assert_eq!(emails(&page), ["late@example.com"]);
+    let page = list(&test_app, "subscribed_before=2024-02-01T00:00:00Z").await;
+    assert_eq!(emails(&page), ["early@example.com"]);
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn subscribers_can_be_updated_and_deleted() {
+    let test_app = spawn_app().await;
+    let subscriber = import_subscriber(&test_app, "ursula@example.com").await;
+    let subscriber_path = format!("/api/v1/subscribers/{}", subscriber["id"].as_str().unwrap());
+
+    let updated: serde_json::Value = test_app
+        .admin_request(Method::PATCH, &subscriber_path)
+        .json(&serde_json::json!({"name": "Ursula K. Le Guin", "tags": ["vip", "sci-fi"]}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(updated["name"], "Ursula K. Le Guin");
+    assert_eq!(updated["email"], "ursula@example.com");
+    assert_eq!(updated["tags"], serde_json::json!(["sci-fi", "vip"]));
+
+    for expected_status in [204, 404] {
+        let response = test_app
+            .admin_request(Method::DELETE, &subscriber_path)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(expected_status, response.status().as_u16());
+    }
+    let response = test_app
+        .admin_request(Method::GET, &subscriber_path)
+        .send()
+        .await
+        .unwrap();
+    assert_problem(response, 404).await;
+}
+
+#[tokio::test]
+async fn invalid_requests_are_answered_with_problem_details() {
+    let test_app = spawn_app().await;
+
+    let response = create_subscriber(
+        &test_app,
+        serde_json::json!({"email": "not an email", "name": "Ursula"}),
+    )
+    .await;
+    let problem = assert_problem(response, 400).await;
+    assert!(problem["detail"].as_str().unwrap().contains("not an email"));
+
+    let response = create_subscriber(&test_app, serde_json::json!({"name": "Ursula"})).await;
+    assert_problem(response, 400).await;
+
+    for query in [
+        "status=gone",
+        "cursor=garbage",
+        "limit=1000",
+        "subscribed_after=yesterday",
+    ] {
+        let response = test_app
+            .admin_request(Method::GET, &format!("/api/v1/subscribers?{}", query))
+            .send()
+            .await
+            .unwrap();
+        assert_problem(response, 400).await;
+    }
+
+    let response = test_app
+        .admin_request(Method::GET, "/api/v1/subscribers/not-a-uuid")
+        .send()
+        .await
+        .unwrap();
+    assert_problem(response, 404).await;
+}
@@ -0,0 +1,246 @@
+use reqwest::{redirect::Policy, Method};
+use secrecy::Secret;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::click_tracking::{ClickToken, HmacSecret};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_audience(app: &TestApp) {
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\nkatherine@example.com,Katherine\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+// Publishes a new issue with two links to the whole audience and delivers it.
+async fn deliver_issue(app: &TestApp, tracking: serde_json::Value) -> String {
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let mut issue = serde_json::json!({
+        "title": "Hello",
+        "content": {"markdown": "Read [the post](https://example.com/post?x=1&y=2) \
+            or [the docs](https://example.com/docs).\n\n\
+            [Unsubscribe]({{ unsubscribe_url }})"},
+    });
+    issue
+        .as_object_mut()
+        .unwrap()
+        .extend(tracking.as_object().unwrap().clone());
+    let body: serde_json::Value = app
+        .admin_request(Method::POST, "/admin/issues")
+        .json(&issue)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let issue_id = body["issue_id"].as_str().unwrap().to_string();
+    app.admin_request(Method::POST, &format!("/admin/issues/{}/publish", issue_id))
+        .json(&serde_json::json!({"link_check": "skip"}))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    issue_id
+}
+
+// The HTML parts of the emails sent so far.
+async fn sent_html(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["HtmlBody"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+// The redirect tokens of an HTML part, in the order of the links.
+fn redirect_tokens(html: &str) -> Vec<String> {
+    html.split("/r/")
+        .skip(1)
+        .map(|rest| rest.split('"').next().unwrap().to_string())
+        .collect()
+}
+
+const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0";
+
+async fn follow(app: &TestApp, token: &str) -> reqwest::Response {
+    follow_as(app, token, FIREFOX).await
+}
+
+async fn follow_as(app: &TestApp, token: &str, user_agent: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/r/{}", app.address, token))
+        .header("User-Agent", user_agent)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn clicks(app: &TestApp, issue_id: &str) -> reqwest::Response {
+    app.admin_request(Method::GET, &format!("/admin/issues/{}/clicks", issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn tracked_links_redirect_to_their_tagged_destination() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    deliver_issue(
+        &test_app,
+        serde_json::json!({
+            "track_clicks": true,
+            "utm": {"source": "newsletter", "medium": "email"}
+        }),
+    )
+    .await;
+
+    let html = &sent_html(&test_app).await[0];
+    let tokens = redirect_tokens(html);
+    assert_eq!(tokens.len(), 2);
+    // Unsubscribing is left alone.
+    assert!(html.contains("/subscriptions/unsubscribe?unsubscribe_token="));
+
+    let response = follow(&test_app, &tokens[0]).await;
+    assert_eq!(302, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/post?x=1&y=2&utm_source=newsletter&utm_medium=email"
+    );
+    let response = follow(&test_app, &tokens[1]).await;
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/docs?utm_source=newsletter&utm_medium=email"
+    );
+}
+
+#[tokio::test]
+async fn clicks_are_counted_per_link() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = deliver_issue(&test_app, serde_json::json!({"track_clicks": true})).await;
+    let emails = sent_html(&test_app).await;
+    let first = redirect_tokens(&emails[0]);
+    let second = redirect_tokens(&emails[1]);
+
+    follow(&test_app, &first[0]).await;
+    // Following the link again right away is not counted again.
+    follow(&test_app, &first[0]).await;
+    sqlx::query!("UPDATE delivery_events SET occurred_at = now() - interval '1 hour'")
+        .execute(&test_app.connection_pool)
+        .await
+        .unwrap();
+    follow(&test_app, &first[0]).await;
+    follow(&test_app, &first[1]).await;
+    follow(&test_app, &second[0]).await;
+
+    let response = clicks(&test_app, &issue_id).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["delivered"], 3);
+    assert_eq!(body["unique_clicks"], 2);
+    assert_eq!(body["total_clicks"], 4);
+    assert_eq!(
+        body["links"],
+        serde_json::json!([
+            {"url": "https://example.com/post?x=1&y=2", "clicks": 3, "unique_clicks": 2},
+            {"url": "https://example.com/docs", "clicks": 1, "unique_clicks": 1},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn link_scanners_still_redirect_but_are_not_clicks() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = deliver_issue(&test_app, serde_json::json!({"track_clicks": true})).await;
+    let tokens = redirect_tokens(&sent_html(&test_app).await[0]);
+
+    for user_agent in ["Barracuda Sentinel (EE)", "Mozilla/5.0", ""] {
+        let response = follow_as(&test_app, &tokens[0], user_agent).await;
+        assert_eq!(302, response.status().as_u16());
+    }
+
+    let body: serde_json::Value = clicks(&test_app, &issue_id).await.json().await.unwrap();
+    assert_eq!(body["unique_clicks"], 0);
+    assert_eq!(body["total_clicks"], 0);
+}
+
+#[tokio::test]
+async fn links_that_were_not_signed_by_us_do_not_redirect() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    deliver_issue(&test_app, serde_json::json!({"track_clicks": true})).await;
+    let token = redirect_tokens(&sent_html(&test_app).await[0]).remove(0);
+    let (_, signature) = token.split_once('.').unwrap();
+    let forged = ClickToken {
+        tracking_token: "whatever".to_string(),
+        url: "https://evil.example.com".to_string(),
+    }
+    .sign(&HmacSecret(Secret::new("a guess".to_string())));
+    let (payload, _) = forged.split_once('.').unwrap();
+
+    let test_cases = vec![
+        (forged.clone(), "a link signed with another key"),
+        (
+            format!("{}.{}", payload, signature),
+            "a destination swapped under a valid signature",
+        ),
+        ("not-a-token".to_string(), "garbage"),
+    ];
+    for (token, description) in test_cases {
+        let response = follow(&test_app, &token).await;
+        assert_eq!(
+            404,
+            response.status().as_u16(),
+            "The API did not fail with 404 Not Found for {}.",
+            description
+        );
+    }
+    let stored = sqlx::query!("SELECT COUNT(*) AS count FROM delivery_events")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, Some(0));
+}
+
+#[tokio::test]
+async fn utm_parameters_are_added_without_click_tracking() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = deliver_issue(
+        &test_app,
+        serde_json::json!({"utm": {"campaign": "launch"}}),
+    )
+    .await;
+
+    let html = &sent_html(&test_app).await[0];
+    assert!(redirect_tokens(html).is_empty());
+    assert!(html.contains(r#"href="https://example.com/post?x=1&amp;y=2&amp;utm_campaign=launch""#));
+    assert!(html.contains(r#"href="https://example.com/docs?utm_campaign=launch""#));
+    assert_eq!(404, clicks(&test_app, &issue_id).await.status().as_u16());
+}
@@ -0,0 +1,48 @@
+use wiremock::{
+    matchers::{any, method},
+    Mock, ResponseTemplate,
+};
+use zero2prod_client::{Client, SubscriberEmail, SubscriberName, SubscriberQuery};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn the_client_subscribes_confirms_and_lists_subscribers() {
+    let test_app = spawn_app().await;
+    let client =
+        Client::new(test_app.address.clone()).with_token(test_app.test_admin.token.clone());
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    client
+        .subscribe(
+            &SubscriberName::parse("le guin".into()).unwrap(),
+            &SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap(),
+        )
+        .await
+        .expect("Failed to subscribe.");
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = test_app.get_confirmation_link(email_request).html;
+    let (_, subscription_token) = confirmation_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .unwrap();
+    client
+        .confirm(&subscription_token)
+        .await
+        .expect("Failed to confirm.");
+
+    let page = client
+        .list_subscribers(&SubscriberQuery {
+            status: Some("confirmed".into()),
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to list subscribers.");
+    assert_eq!(page.data.len(), 1);
+    assert_eq!(page.data[0].email, "ursula_le_guin@gmail.com");
+}
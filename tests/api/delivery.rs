@@ -0,0 +1,238 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_audience(app: &TestApp) {
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\nkatherine@example.com,Katherine\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+// Publishes a new issue to the whole audience, nothing is sent yet.
+async fn publish_issue(app: &TestApp) -> String {
+    let body: serde_json::Value = app
+        .admin_request(Method::POST, "/admin/issues")
+        .json(&serde_json::json!({
+            "title": "Oops",
+            "content": {"markdown": "There is a typo in the fist line."}
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let issue_id = body["issue_id"].as_str().unwrap().to_string();
+
+    app.admin_request(Method::POST, &format!("/admin/issues/{}/publish", issue_id))
+        .json(&serde_json::json!({"link_check": "skip"}))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap();
+    issue_id
+}
+
+async fn delivery_action(app: &TestApp, issue_id: &str, action: &str) -> reqwest::Response {
+    app.admin_request(
+        Method::POST,
+        &format!("/admin/issues/{}/delivery/{}", issue_id, action),
+    )
+    .send()
+    .await
+    .expect("Failed to execute request.")
+}
+
+async fn delivery(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    app.admin_request(Method::GET, &format!("/admin/issues/{}/delivery", issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn mount_email_server(app: &TestApp, expected: u64) {
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn paused_deliveries_are_sent_once_resumed() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    mount_email_server(&test_app, 3).await;
+    let issue_id = publish_issue(&test_app).await;
+    assert!(test_app.dispatch_pending_email().await);
+
+    let response = delivery_action(&test_app, &issue_id, "pause").await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "paused");
+    assert_eq!(body["delivered"], 1);
+    assert_eq!(body["pending"], 2);
+
+    // Workers leave the issue alone.
+    assert!(!test_app.dispatch_pending_email().await);
+    assert_eq!(delivery(&test_app, &issue_id).await["delivered"], 1);
+
+    let response = delivery_action(&test_app, &issue_id, "resume").await;
+    assert_eq!(200, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let progress = delivery(&test_app, &issue_id).await;
+    assert_eq!(progress["status"], "published");
+    assert_eq!(progress["delivered"], 3);
+    assert_eq!(progress["pending"], 0);
+}
+
+#[tokio::test]
+async fn cancelled_deliveries_drop_the_rest_of_the_queue() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    mount_email_server(&test_app, 1).await;
+    let issue_id = publish_issue(&test_app).await;
+    assert!(test_app.dispatch_pending_email().await);
+
+    let response = delivery_action(&test_app, &issue_id, "cancel").await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "cancelled");
+    assert_eq!(body["delivered"], 1);
+    assert_eq!(body["pending"], 0);
+
+    test_app.dispatch_all_pending_emails().await;
+    assert_eq!(delivery(&test_app, &issue_id).await["delivered"], 1);
+}
+
+#[tokio::test]
+async fn paused_deliveries_can_be_cancelled() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    mount_email_server(&test_app, 0).await;
+    let issue_id = publish_issue(&test_app).await;
+
+    delivery_action(&test_app, &issue_id, "pause")
+        .await
+        .error_for_status()
+        .unwrap();
+    let response = delivery_action(&test_app, &issue_id, "cancel").await;
+    assert_eq!(200, response.status().as_u16());
+
+    // A cancelled issue stays cancelled.
+    let response = delivery_action(&test_app, &issue_id, "resume").await;
+    assert_eq!(409, response.status().as_u16());
+    let response = test_app
+        .admin_request(Method::POST, &format!("/admin/issues/{}/publish", issue_id))
+        .json(&serde_json::json!({"link_check": "skip"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn only_deliveries_in_progress_can_be_paused_or_cancelled() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    mount_email_server(&test_app, 3).await;
+    let issue_id = publish_issue(&test_app).await;
+
+    let response = delivery_action(&test_app, &issue_id, "resume").await;
+    assert_eq!(409, response.status().as_u16());
+
+    test_app.dispatch_all_pending_emails().await;
+    for action in ["pause", "cancel"] {
+        let response = delivery_action(&test_app, &issue_id, action).await;
+        assert_eq!(
+            409,
+            response.status().as_u16(),
+            "A finished delivery could be {}d.",
+            action
+        );
+    }
+
+    let response = delivery_action(&test_app, &uuid::Uuid::new_v4().to_string(), "pause").await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_before_they_are_given_up_on() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    // Three failures in a row for Ada, everyone else gets it the first time.
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .and(wiremock::matchers::body_string_contains("ada@example.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(3)
+        .expect(3)
+        .mount(&test_app.email_server)
+        .await;
+    mount_email_server(&test_app, 3).await;
+    let issue_id = publish_issue(&test_app).await;
+
+    test_app.dispatch_all_pending_emails().await;
+    let progress = delivery(&test_app, &issue_id).await;
+    assert_eq!(progress["delivered"], 2);
+    assert_eq!(progress["pending"], 1);
+    assert_eq!(progress["retried"], 1);
+
+    // The retry waits for the backoff to pass.
+    for _ in 0..3 {
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&test_app.connection_pool)
+            .await
+            .unwrap();
+        test_app.dispatch_all_pending_emails().await;
+    }
+
+    let progress = delivery(&test_app, &issue_id).await;
+    assert_eq!(progress["delivered"], 3);
+    assert_eq!(progress["failed"], 0);
+    assert_eq!(progress["retried"], 1);
+}
+
+#[tokio::test]
+async fn deliveries_are_given_up_on_after_too_many_failures() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&test_app.email_server)
+        .await;
+    let issue_id = publish_issue(&test_app).await;
+
+    test_app.dispatch_all_pending_emails().await;
+    for _ in 0..3 {
+        sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+            .execute(&test_app.connection_pool)
+            .await
+            .unwrap();
+        test_app.dispatch_all_pending_emails().await;
+    }
+
+    let progress = delivery(&test_app, &issue_id).await;
+    assert_eq!(progress["delivered"], 0);
+    assert_eq!(progress["pending"], 0);
+    assert_eq!(progress["failed"], 3);
+    assert_eq!(progress["retried"], 3);
+}
@@ -1,21 +1,73 @@
+use chrono::Utc;
 use linkify::LinkFinder;
 use once_cell::sync::Lazy;
 use reqwest::Response;
 use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{ConnectOptions, Executor, PgPool};
 use uuid::Uuid;
 use wiremock::{MockServer, Request};
+use zero2prod::authentication::{generate_admin_token, hash_token};
+use zero2prod::click_tracking::HmacSecret;
 use zero2prod::configuration::get_configuration;
 use zero2prod::configuration::DatabaseSettings;
-use zero2prod::startup::Application;
+use zero2prod::email_clients::EmailClient;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome, IssueCache};
+use zero2prod::outbound::DestinationPolicy;
+use zero2prod::scheduler::{try_decide_due_ab_test, try_publish_due_issue};
+use zero2prod::startup::{Application, ApplicationBaseUrl};
 use zero2prod::telemetry::get_subscriber;
 use zero2prod::telemetry::init_subscriber;
+use zero2prod::transactional_email_worker::try_send_transactional_email;
+use zero2prod::webhook_delivery_worker::{try_deliver_webhook, webhook_client};
+use zero2prod::webhooks::sign_payload;
 
 pub struct TestApp {
     pub address: String,
     pub port: u16,
     pub connection_pool: PgPool,
     pub email_server: MockServer,
+    pub test_admin: TestAdmin,
+    pub email_client: EmailClient,
+    pub base_url: ApplicationBaseUrl,
+    pub hmac_secret: HmacSecret,
+    pub email_webhook_secret: Secret<String>,
+}
+
+pub struct TestAdmin {
+    pub token: Secret<String>,
+}
+
+impl TestAdmin {
+    // Every test app starts with an admin of the default publication.
+    async fn store(connection_pool: &PgPool) -> Self {
+        let publication_id = sqlx::query!("SELECT id FROM publications WHERE slug = 'default'")
+            .fetch_one(connection_pool)
+            .await
+            .expect("Failed to fetch the default publication.")
+            .id;
+
+        Self::store_for(connection_pool, publication_id).await
+    }
+
+    pub async fn store_for(connection_pool: &PgPool, publication_id: Uuid) -> Self {
+        let token = generate_admin_token();
+        sqlx::query!(
+            r#"
+        INSERT INTO publication_admins (id, publication_id, username, token_hash, created_at)
+        VALUES ($1, $2, $3, $4, $5)"#,
+            Uuid::new_v4(),
+            publication_id,
+            Uuid::new_v4().to_string(),
+            hash_token(&token),
+            Utc::now()
+        )
+        .execute(connection_pool)
+        .await
+        .expect("Failed to store test admin.");
+
+        Self { token }
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -24,17 +76,159 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    // Works off the delivery queue like the background worker would.
+    pub async fn dispatch_all_pending_emails(&self) {
+        let mut issue_cache = IssueCache::default();
+        while self.dispatch_pending_email_with(&mut issue_cache).await {}
+    }
+
+    // Returns false when there was nothing to send.
+    pub async fn dispatch_pending_email(&self) -> bool {
+        self.dispatch_pending_email_with(&mut IssueCache::default())
+            .await
+    }
+
+    async fn dispatch_pending_email_with(&self, issue_cache: &mut IssueCache) -> bool {
+        let outcome = try_execute_task(
+            &self.connection_pool,
+            &self.email_client,
+            &self.base_url,
+            &self.hmac_secret,
+            issue_cache,
+        )
+        .await
+        .unwrap();
+        matches!(outcome, ExecutionOutcome::TaskCompleted)
+    }
+
+    pub async fn dispatch_pending_webhooks(&self) {
+        let policy = DestinationPolicy::new(true);
+        let http_client = webhook_client(&policy);
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_deliver_webhook(&self.connection_pool, &http_client, &policy)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_transactional_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_send_transactional_email(&self.connection_pool, &self.email_client)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn decide_due_ab_tests(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_decide_due_ab_test(&self.connection_pool, &self.base_url)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn publish_due_issues(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_publish_due_issue(&self.connection_pool, &self.base_url)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscription(&self, body: String) -> Response {
+        self.post_subscription_as("application/x-www-form-urlencoded", body)
+            .await
+    }
+
+    pub async fn post_subscription_as(&self, content_type: &str, body: String) -> Response {
         let client = reqwest::Client::new();
         client
             .post(format!("{}/subscriptions", self.address))
-            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Content-Type", content_type)
             .body(body)
             .send()
             .await
             .expect("Failed to send subscription request.")
     }
 
+    // Signed like the email provider signs its events.
+    pub async fn post_email_event(&self, event: serde_json::Value) -> Response {
+        self.post_email_event_signed_at(event, Utc::now().timestamp())
+            .await
+    }
+
+    pub async fn post_email_event_signed_at(
+        &self,
+        event: serde_json::Value,
+        timestamp: i64,
+    ) -> Response {
+        let body = serde_json::to_vec(&event).unwrap();
+        let signature = sign_payload(self.email_webhook_secret.expose_secret(), timestamp, &body);
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email", self.address))
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Signature", signature)
+            .header("X-Webhook-Timestamp", timestamp.to_string())
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", self.address))
+            .bearer_auth(self.test_admin.token.expose_secret())
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub fn admin_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}{}", self.address, path))
+            .bearer_auth(self.test_admin.token.expose_secret())
+    }
+
+    // Publications without a host of their own
+    // are reachable under the `/p/{slug}` path prefix.
+    pub async fn create_publication(&self, slug: &str, host: Option<&str>) -> Uuid {
+        let publication_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+        INSERT INTO publications (id, slug, name, host, sender_email, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)"#,
+            publication_id,
+            slug,
+            format!("The {} newsletter", slug),
+            host,
+            format!("{}@publications.com", slug),
+            Utc::now()
+        )
+        .execute(&self.connection_pool)
+        .await
+        .expect("Failed to create publication.");
+
+        publication_id
+    }
+
     pub fn get_confirmation_link(&self, email_request: &Request) -> ConfirmationLinks {
         let request_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -81,13 +275,16 @@ pub async fn spawn_app() -> TestApp {
     configurations.database.database_name = Uuid::new_v4().to_string();
     configurations.application.host = String::from("127.0.0.1");
     configurations.application.port = 0;
+    // Every server the tests talk to runs on localhost.
+    configurations.outbound.allow_private_destinations = true;
 
     let email_server = MockServer::start().await;
     configurations.email_client.base_url = email_server.uri();
 
     let connection_pool = configure_database(&configurations.database).await;
+    let test_admin = TestAdmin::store(&connection_pool).await;
 
-    let application = Application::build(configurations)
+    let application = Application::build(configurations.clone())
         .await
         .expect("Failed to build server application");
     let application_port = application.port();
@@ -103,6 +300,11 @@ pub async fn spawn_app() -> TestApp {
         port: application_port,
         connection_pool,
         email_server,
+        test_admin,
+        email_client: configurations.email_client.clone().client(),
+        base_url: ApplicationBaseUrl(configurations.application.base_url),
+        hmac_secret: HmacSecret(configurations.application.hmac_secret),
+        email_webhook_secret: configurations.email_client.webhook_secret.clone(),
     }
 }
 
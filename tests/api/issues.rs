@@ -0,0 +1,427 @@
+use chrono::{DateTime, Duration, Utc};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_audience(app: &TestApp) {
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+async fn create_draft(app: &TestApp, body: serde_json::Value) -> String {
+    let response = app
+        .admin_request(Method::POST, "/admin/issues")
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["issue_id"].as_str().unwrap().to_string()
+}
+
+fn draft() -> serde_json::Value {
+    serde_json::json!({
+        "title": "News for {{ name }}",
+        "content": {"markdown": "Hi {{ name }}, [unsubscribe]({{ unsubscribe_url }})."}
+    })
+}
+
+async fn get_issue(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    app.admin_request(Method::GET, &format!("/admin/issues/{}", issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn drafts_are_stored_without_sending_anything() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    let issue = get_issue(&test_app, &issue_id).await;
+    assert_eq!(issue["status"], "draft");
+    assert_eq!(issue["title"], "News for {{ name }}");
+    assert!(issue["published_at"].is_null());
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited_and_listed() {
+    let test_app = spawn_app().await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    let response = test_app
+        .admin_request(Method::PUT, &format!("/admin/issues/{}", issue_id))
+        .json(&serde_json::json!({
+            "title": "Edited",
+            "preheader": "Now with a preheader",
+            "content": {"html": "<p>Edited</p>", "text": "Edited"}
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let issue = get_issue(&test_app, &issue_id).await;
+    assert_eq!(issue["title"], "Edited");
+    assert_eq!(issue["preheader"], "Now with a preheader");
+    assert!(issue["markdown"].is_null());
+
+    let issues: serde_json::Value = test_app
+        .admin_request(Method::GET, "/admin/issues")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issues.as_array().unwrap().len(), 1);
+    assert_eq!(issues[0]["id"], issue_id.as_str());
+    assert_eq!(issues[0]["status"], "draft");
+}
+
+#[tokio::test]
+async fn drafts_with_unknown_merge_tags_are_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .admin_request(Method::POST, "/admin/issues")
+        .json(&serde_json::json!({
+            "title": "News",
+            "content": {"markdown": "Hi {{ nickname }}"}
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn previews_fill_in_the_merge_tags_of_a_subscriber() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    let preview: serde_json::Value = test_app
+        .admin_request(
+            Method::GET,
+            &format!(
+                "/admin/issues/{}/preview?subscriber=grace@example.com",
+                issue_id
+            ),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(preview["subject"], "News for Grace");
+    assert!(preview["text"].as_str().unwrap().contains("Hi Grace"));
+    assert!(preview["html"].as_str().unwrap().contains("Hi Grace"));
+    // The real unsubscribe token of the subscriber stays out of previews.
+    assert!(preview["html"]
+        .as_str()
+        .unwrap()
+        .contains("unsubscribe_token=preview"));
+}
+
+#[tokio::test]
+async fn previews_use_a_sample_subscriber_by_default() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    let preview: serde_json::Value = test_app
+        .admin_request(Method::GET, &format!("/admin/issues/{}/preview", issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(preview["subject"], "News for Ada");
+}
+
+#[tokio::test]
+async fn previews_for_unknown_subscribers_are_not_found() {
+    let test_app = spawn_app().await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    let response = test_app
+        .admin_request(
+            Method::GET,
+            &format!(
+                "/admin/issues/{}/preview?subscriber=nobody@example.com",
+                issue_id
+            ),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn test_sends_only_go_to_the_given_addresses() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .admin_request(Method::POST, &format!("/admin/issues/{}/test", issue_id))
+        .json(&serde_json::json!({
+            "emails": ["editor@example.com"],
+            "subscriber": "ada@example.com"
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    let request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(email["To"], "editor@example.com");
+    assert_eq!(email["Subject"], "[Test] News for Ada");
+
+    // Test sends leave the draft alone.
+    assert_eq!(get_issue(&test_app, &issue_id).await["status"], "draft");
+}
+
+#[tokio::test]
+async fn test_sends_to_invalid_addresses_are_rejected() {
+    let test_app = spawn_app().await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    for emails in [
+        serde_json::json!([]),
+        serde_json::json!(["editor@example.com", "not-an-email"]),
+    ] {
+        let response = test_app
+            .admin_request(Method::POST, &format!("/admin/issues/{}/test", issue_id))
+            .json(&serde_json::json!({ "emails": emails }))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(400, response.status().as_u16(), "{} was accepted", emails);
+    }
+}
+
+#[tokio::test]
+async fn published_drafts_are_sent_once_and_can_no_longer_be_edited() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    let publish = || {
+        test_app
+            .admin_request(Method::POST, &format!("/admin/issues/{}/publish", issue_id))
+            .json(&serde_json::json!({"link_check": "skip"}))
+            .send()
+    };
+    assert_eq!(200, publish().await.unwrap().status().as_u16());
+    assert_eq!(409, publish().await.unwrap().status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let issue = get_issue(&test_app, &issue_id).await;
+    assert_eq!(issue["status"], "published");
+    assert!(!issue["published_at"].is_null());
+
+    let response = test_app
+        .admin_request(Method::PUT, &format!("/admin/issues/{}", issue_id))
+        .json(&draft())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(409, response.status().as_u16());
+}
+
+async fn schedule(app: &TestApp, issue_id: &str, scheduled_at: DateTime<Utc>) -> reqwest::Response {
+    app.admin_request(Method::PUT, &format!("/admin/issues/{}/schedule", issue_id))
+        .json(&serde_json::json!({"scheduled_at": scheduled_at, "link_check": "skip"}))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+// Moves the issue's send time into the past without waiting for it.
+async fn make_due(app: &TestApp, issue_id: &str) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_at = now() - interval '1 minute' WHERE id = $1",
+        Uuid::parse_str(issue_id).unwrap()
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_sent_once_they_are_due() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = schedule(&test_app, &issue_id, Utc::now() + Duration::days(3)).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Not due yet.
+    test_app.publish_due_issues().await;
+    test_app.dispatch_all_pending_emails().await;
+    assert_eq!(get_issue(&test_app, &issue_id).await["status"], "scheduled");
+
+    make_due(&test_app, &issue_id).await;
+    test_app.publish_due_issues().await;
+    test_app.publish_due_issues().await;
+    test_app.dispatch_all_pending_emails().await;
+
+    let issue = get_issue(&test_app, &issue_id).await;
+    assert_eq!(issue["status"], "published");
+    assert!(issue["scheduled_at"].is_null());
+}
+
+#[tokio::test]
+async fn due_issues_are_published_once_by_concurrent_schedulers() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = create_draft(&test_app, draft()).await;
+    schedule(&test_app, &issue_id, Utc::now() + Duration::hours(1)).await;
+    make_due(&test_app, &issue_id).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    tokio::join!(
+        test_app.publish_due_issues(),
+        test_app.publish_due_issues(),
+        test_app.publish_due_issues()
+    );
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled_and_cancelled() {
+    let test_app = spawn_app().await;
+    let issue_id = create_draft(&test_app, draft()).await;
+    schedule(&test_app, &issue_id, Utc::now() + Duration::days(3)).await;
+
+    let monday = Utc::now() + Duration::days(5);
+    let response = schedule(&test_app, &issue_id, monday).await;
+    assert_eq!(200, response.status().as_u16());
+    let issue = get_issue(&test_app, &issue_id).await;
+    let scheduled_at: DateTime<Utc> =
+        serde_json::from_value(issue["scheduled_at"].clone()).unwrap();
+    assert_eq!(scheduled_at.timestamp(), monday.timestamp());
+
+    // Scheduled issues are locked for editing.
+    let response = test_app
+        .admin_request(Method::PUT, &format!("/admin/issues/{}", issue_id))
+        .json(&draft())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(409, response.status().as_u16());
+
+    let response = test_app
+        .admin_request(
+            Method::DELETE,
+            &format!("/admin/issues/{}/schedule", issue_id),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, response.status().as_u16());
+
+    let issue = get_issue(&test_app, &issue_id).await;
+    assert_eq!(issue["status"], "draft");
+    assert!(issue["scheduled_at"].is_null());
+}
+
+#[tokio::test]
+async fn issues_can_no_longer_be_rescheduled_or_cancelled_once_sent() {
+    let test_app = spawn_app().await;
+    let issue_id = create_draft(&test_app, draft()).await;
+    schedule(&test_app, &issue_id, Utc::now() + Duration::hours(1)).await;
+    make_due(&test_app, &issue_id).await;
+    test_app.publish_due_issues().await;
+
+    let response = schedule(&test_app, &issue_id, Utc::now() + Duration::days(1)).await;
+    assert_eq!(409, response.status().as_u16());
+
+    let response = test_app
+        .admin_request(
+            Method::DELETE,
+            &format!("/admin/issues/{}/schedule", issue_id),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    let test_app = spawn_app().await;
+    let issue_id = create_draft(&test_app, draft()).await;
+
+    let response = schedule(&test_app, &issue_id, Utc::now() - Duration::hours(1)).await;
+
+    assert_eq!(400, response.status().as_u16());
+}
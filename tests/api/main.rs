@@ -1,5 +1,22 @@
+mod ab_tests;
+mod api_subscribers;
+mod clicks;
+mod client;
+mod delivery;
 mod health_check;
 mod helpers;
+mod issues;
 mod newsletter;
+mod openapi;
+mod opens;
+mod personalization;
+mod progress;
+mod publications;
+mod segments;
+mod subscriber_data;
 mod subscription_confirmation;
 mod subscriptions;
+mod suppressions;
+mod time_zones;
+mod transactional_emails;
+mod webhooks;
@@ -1,10 +1,16 @@
-use wiremock::{Mock, ResponseTemplate};
+use reqwest::Method;
+use secrecy::ExposeSecret;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let test_app = spawn_app().await;
+    create_unconfirmed_subscriber(&test_app).await;
 
     Mock::given(any())
         .respond_with(ResponseTemplate::new(200))
@@ -20,28 +26,453 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     }
     });
 
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+    }
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let test_app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = test_app.post_newsletters(invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let test_app = spawn_app().await;
+
     let response = reqwest::Client::new()
-        .post(format!("{}/newsletter", &test_app.address))
-        .json(&newsletter_request_body)
+        .post(format!("{}/newsletters", &test_app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
         .send()
         .await
         .expect("Failed to execute request.");
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Bearer realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) {
+#[tokio::test]
+async fn admins_cannot_publish_to_another_publication() {
+    let test_app = spawn_app().await;
+    test_app.create_publication("rustaceans", None).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/p/rustaceans/newsletters", &test_app.address))
+        .bearer_auth(test_app.test_admin.token.expose_secret())
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn markdown_issues_are_rendered_into_html_and_text() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Weekly digest",
+            "content": {
+                "markdown": "## Hello {{ name }}\n\nRead [the post](https://example.com/post).",
+            },
+            "link_check": "skip",
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = email["HtmlBody"].as_str().unwrap();
+    let text = email["TextBody"].as_str().unwrap();
+
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("Hello le guin</h2>"));
+    // Styles are inlined, email clients drop stylesheets.
+    assert!(html.contains(r#"<h2 style="margin-bottom: 0.75rem;"#));
+    assert!(!html.contains("class="));
+    assert!(html.contains(r#"<a href="https://example.com/post" style="color: #1d4ed8;"#));
+    assert!(html.contains("/subscriptions/unsubscribe?unsubscribe_token="));
+    assert!(text.contains("Hello le guin\n-------------"));
+    assert!(text.contains("Read the post [1]."));
+    assert!(text.contains("[1] https://example.com/post"));
+    assert!(!text.contains('<'));
+}
+
+#[tokio::test]
+async fn published_issues_keep_their_markdown_source() {
+    let test_app = spawn_app().await;
+    let markdown = "# Release notes\n\n- faster\n- smaller";
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Release notes",
+            "content": { "markdown": markdown }
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["issue_id"].as_str().unwrap();
+
+    let issue: serde_json::Value = test_app
+        .admin_request(Method::GET, &format!("/admin/issues/{}", issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(issue["markdown"], markdown);
+    assert!(issue["html"].as_str().unwrap().contains("<li>faster</li>"));
+    assert!(issue["text"]
+        .as_str()
+        .unwrap()
+        .contains("- faster\n- smaller"));
+}
+
+#[tokio::test]
+async fn the_preheader_is_added_to_the_html_part() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "preheader": "Everything new this week, {{ name }}",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = email["HtmlBody"].as_str().unwrap();
+
+    assert!(html.contains(r#"<body><div style="display: none;"#));
+    assert!(html.contains("Everything new this week, le guin"));
+    assert!(!email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Everything new"));
+}
+
+#[tokio::test]
+async fn issues_gmail_would_clip_are_rejected() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": format!("<p>{}</p>", "Too long. ".repeat(11_000)),
+            }
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("Gmail clips"));
+}
+
+#[tokio::test]
+async fn issues_gmail_would_clip_once_tracked_are_rejected() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // About 90KB as written, every link grows once routed through a redirect.
+    let link = r#"<a href="https://example.com/">Link</a>"#;
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": link.repeat(2_400),
+            },
+            "track_clicks": true,
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("Gmail clips"));
+}
+
+#[tokio::test]
+async fn unsafe_html_is_stripped_and_reported() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p onclick=\"steal()\">Hello</p><script>steal()</script>\
+                    <a href=\"javascript:steal()\">Click</a>",
+            }
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["stripped"],
+        serde_json::json!([
+            {"element": "p", "attribute": "onclick", "reason": "not allowed in emails", "count": 1},
+            {"element": "script", "attribute": null, "reason": "not allowed in emails", "count": 1},
+            {"element": "a", "attribute": "href", "reason": "unsafe URL", "count": 1},
+        ])
+    );
+
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(!html.contains("steal"));
+    assert!(html.contains("<p>Hello</p>"));
+}
+
+async fn link_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(path("/ok"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(path("/gone"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn issues_with_dead_links_are_blocked_when_asked_to() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let links = link_server().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": format!("[Fine]({0}/ok) and [broken]({0}/gone)", links.uri()),
+            },
+            "link_check": "block",
+        }))
+        .await;
+
+    assert_eq!(422, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["links"]["checked"], 2);
+    assert_eq!(
+        body["links"]["dead"][0]["url"],
+        format!("{}/gone", links.uri())
+    );
+    assert_eq!(body["links"]["dead"][0]["status"], 404);
+}
+
+#[tokio::test]
+async fn link_problems_are_reported_without_blocking_by_default() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let links = link_server().await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "html": format!(r#"<a href="{0}/ok">Fine</a> <a href="{0}/gone">Broken</a>"#, links.uri()),
+                "text": format!("Fine: {}/ok", links.uri()),
+            }
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["links"]["dead"][0]["url"],
+        format!("{}/gone", links.uri())
+    );
+    assert_eq!(
+        body["links"]["only_in_html"],
+        serde_json::json!([format!("{}/gone", links.uri())])
+    );
+}
+
+async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
-    let _mock_guard = Mock::given(path("/email"))
+    let _mock_guard = Mock::given(path("/"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         .named("Create unconfirmed subscriber")
         .expect(1)
         .mount_as_scoped(&app.email_server)
         .await;
-    app.post_subscriptions(body.into())
+    app.post_subscription(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_link(email_request)
+}
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
         .await
+        .unwrap()
         .error_for_status()
         .unwrap();
 }
@@ -0,0 +1,49 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn the_openapi_document_describes_the_public_and_v1_endpoints() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/api/openapi.json", test_app.address))
+        .send()
+        .await
+        .expect("Failed to fetch the OpenAPI document.");
+
+    assert_eq!(200, response.status().as_u16());
+    let document: serde_json::Value = response.json().await.unwrap();
+    assert!(document["openapi"].as_str().unwrap().starts_with("3.1"));
+    let paths = document["paths"].as_object().unwrap();
+    for path in [
+        "/subscriptions",
+        "/subscriptions/confirm",
+        "/newsletters",
+        "/api/v1/subscribers",
+        "/api/v1/subscribers/{subscriber_id}",
+        "/api/v1/emails",
+    ] {
+        assert!(paths.contains_key(path), "{} is not documented.", path);
+    }
+    let schemas = document["components"]["schemas"].as_object().unwrap();
+    for schema in ["FormDataSubscriber", "BodyData", "Problem", "LinkReport"] {
+        assert!(
+            schemas.contains_key(schema),
+            "{} is not documented.",
+            schema
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_api_documentation_page_is_served() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/api/docs/", test_app.address))
+        .send()
+        .await
+        .expect("Failed to fetch the documentation page.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("swagger-ui"));
+}
@@ -0,0 +1,242 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) \
+    AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148";
+
+async fn import_audience(app: &TestApp) {
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\nkatherine@example.com,Katherine\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+// Publishes a new issue to the whole audience and delivers it.
+async fn deliver_issue(app: &TestApp, track_opens: bool) -> String {
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body: serde_json::Value = app
+        .admin_request(Method::POST, "/admin/issues")
+        .json(&serde_json::json!({
+            "title": "Hello",
+            "content": {"markdown": "Hi {{ name }}"},
+            "track_opens": track_opens
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let issue_id = body["issue_id"].as_str().unwrap().to_string();
+    app.admin_request(Method::POST, &format!("/admin/issues/{}/publish", issue_id))
+        .json(&serde_json::json!({"link_check": "skip"}))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    issue_id
+}
+
+// The tracking tokens of the emails sent so far, by recipient.
+async fn tracking_tokens(app: &TestApp) -> Vec<(String, Option<String>)> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let html = body["HtmlBody"].as_str().unwrap();
+            let token = html.split_once("/o/").map(|(_, rest)| {
+                rest.chars()
+                    .take_while(char::is_ascii_alphanumeric)
+                    .collect()
+            });
+            (body["To"].as_str().unwrap().to_string(), token)
+        })
+        .collect()
+}
+
+async fn open(app: &TestApp, token: &str, user_agent: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new().get(format!("{}/o/{}", app.address, token));
+    if let Some(user_agent) = user_agent {
+        request = request.header("User-Agent", user_agent);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+async fn opens(app: &TestApp, issue_id: &str) -> reqwest::Response {
+    app.admin_request(Method::GET, &format!("/admin/issues/{}/opens", issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn each_delivery_of_a_tracked_issue_gets_its_own_pixel() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    deliver_issue(&test_app, true).await;
+
+    let tokens = tracking_tokens(&test_app).await;
+    assert_eq!(tokens.len(), 3);
+    let mut unique: Vec<_> = tokens
+        .iter()
+        .map(|(_, token)| token.clone().unwrap())
+        .collect();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), 3);
+
+    let stored = sqlx::query!("SELECT COUNT(tracking_token) AS count FROM issue_deliveries")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, Some(3));
+}
+
+#[tokio::test]
+async fn untracked_issues_have_no_pixel() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = deliver_issue(&test_app, false).await;
+
+    for (email, token) in tracking_tokens(&test_app).await {
+        assert!(token.is_none(), "{} got a tracking pixel.", email);
+    }
+    assert_eq!(404, opens(&test_app, &issue_id).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn opens_are_counted_per_issue() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = deliver_issue(&test_app, true).await;
+    let tokens = tracking_tokens(&test_app).await;
+    let token = tokens[0].1.as_deref().unwrap();
+
+    let response = open(&test_app, token, Some(IPHONE)).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    assert!(response.headers()["Cache-Control"]
+        .to_str()
+        .unwrap()
+        .contains("no-store"));
+    // Reloads right away are not counted again.
+    open(&test_app, token, Some(IPHONE)).await;
+    sqlx::query!("UPDATE delivery_events SET occurred_at = now() - interval '1 hour'")
+        .execute(&test_app.connection_pool)
+        .await
+        .unwrap();
+    open(&test_app, token, Some(IPHONE)).await;
+
+    let response = opens(&test_app, &issue_id).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["delivered"], 3);
+    assert_eq!(body["unique_opens"], 1);
+    assert_eq!(body["total_opens"], 2);
+    assert_eq!(body["prefetches"], 0);
+    assert!((body["open_rate"].as_f64().unwrap() - 1.0 / 3.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn prefetches_are_not_opens() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = deliver_issue(&test_app, true).await;
+    let tokens = tracking_tokens(&test_app).await;
+
+    // Apple Mail Privacy Protection, from Apple's network.
+    reqwest::Client::new()
+        .get(format!(
+            "{}/o/{}",
+            test_app.address,
+            tokens[0].1.as_deref().unwrap()
+        ))
+        .header("User-Agent", IPHONE)
+        .header("X-Forwarded-For", "17.58.0.12")
+        .send()
+        .await
+        .unwrap();
+    open(
+        &test_app,
+        tokens[1].1.as_deref().unwrap(),
+        Some("Mozilla/5.0"),
+    )
+    .await;
+    open(&test_app, tokens[2].1.as_deref().unwrap(), None).await;
+
+    let body: serde_json::Value = opens(&test_app, &issue_id).await.json().await.unwrap();
+    assert_eq!(body["unique_opens"], 0);
+    assert_eq!(body["total_opens"], 0);
+    assert_eq!(body["prefetches"], 3);
+}
+
+#[tokio::test]
+async fn unknown_tokens_still_get_a_pixel() {
+    let test_app = spawn_app().await;
+
+    let response = open(&test_app, "unknown", Some(IPHONE)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let stored = sqlx::query!("SELECT COUNT(*) AS count FROM delivery_events")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored.count, Some(0));
+}
+
+#[tokio::test]
+async fn segments_can_target_recent_readers() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    deliver_issue(&test_app, true).await;
+    let tokens = tracking_tokens(&test_app).await;
+    let (reader, token) = &tokens[0];
+    open(&test_app, token.as_deref().unwrap(), Some(IPHONE)).await;
+    // Prefetched, not read.
+    open(
+        &test_app,
+        tokens[1].1.as_deref().unwrap(),
+        Some("Mozilla/5.0"),
+    )
+    .await;
+
+    for (expression, expected) in [
+        ("opened_last(30d)", 1),
+        ("NOT opened_last(30d)", 2),
+        (&format!("opened_last(30d) AND email = \"{}\"", reader), 1),
+    ] {
+        let body: serde_json::Value = test_app
+            .admin_request(Method::POST, "/admin/segments/preview")
+            .json(&serde_json::json!({ "expression": expression }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["subscribers"], expected, "{}", expression);
+    }
+}
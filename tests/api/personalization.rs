@@ -0,0 +1,212 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_audience(app: &TestApp) {
+    app.admin_request(Method::POST, "/admin/fields")
+        .json(&serde_json::json!({"key": "company", "label": "Company", "kind": "text"}))
+        .send()
+        .await
+        .expect("Failed to create custom field.")
+        .error_for_status()
+        .unwrap();
+
+    let csv = "email,name,company\n\
+        ada@example.com,Ada,Analytical & Co\n\
+        grace@example.com,Grace,\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+async fn sent_emails(app: &TestApp) -> Vec<serde_json::Value> {
+    let mut emails: Vec<serde_json::Value> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect();
+    emails.sort_by_key(|email| email["To"].as_str().unwrap().to_string());
+    emails
+}
+
+#[tokio::test]
+async fn merge_tags_are_filled_in_for_every_recipient() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "News for {{ name }}",
+            "content": {
+                "text": "Hi {{ name }} from {{ company | default: \"your company\" }}",
+                "html": "<p>Hi {{ name }} from {{ company | default: \"your company\" }}</p>",
+            }
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+
+    let emails = sent_emails(&test_app).await;
+    assert_eq!(emails[0]["Subject"], "News for Ada");
+    assert_eq!(emails[0]["TextBody"], "Hi Ada from Analytical & Co");
+    // The HTML part is sent as a full document.
+    assert!(emails[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("<body><p>Hi Ada from Analytical &amp; Co</p></body>"));
+    assert_eq!(emails[1]["TextBody"], "Hi Grace from your company");
+}
+
+#[tokio::test]
+async fn issues_with_unknown_merge_tags_are_rejected_before_sending() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let test_cases = vec![
+        ("{{ nmae }}", "a misspelled tag"),
+        ("{{ name", "an unclosed tag"),
+        ("{{ name | default: friend }}", "an unquoted default"),
+    ];
+
+    for (text, description) in test_cases {
+        let response = test_app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": text,
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }))
+            .await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_unsubscribe_url_removes_the_recipient_from_the_audience() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let newsletter = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Unsubscribe: {{ unsubscribe_url }}",
+            "html": "<a href=\"{{ unsubscribe_url }}\">Unsubscribe</a>",
+        }
+    });
+    test_app
+        .post_newsletters(newsletter.clone())
+        .await
+        .error_for_status()
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+
+    let emails = sent_emails(&test_app).await;
+    let text = emails[0]["TextBody"].as_str().unwrap();
+    let mut unsubscribe_url =
+        reqwest::Url::parse(text.strip_prefix("Unsubscribe: ").unwrap()).unwrap();
+    unsubscribe_url.set_port(Some(test_app.port)).unwrap();
+
+    let response = reqwest::get(unsubscribe_url)
+        .await
+        .expect("Failed to follow the unsubscribe link.");
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions WHERE email = 'ada@example.com'")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+
+    test_app
+        .post_newsletters(newsletter)
+        .await
+        .error_for_status()
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+    let received = test_app.email_server.received_requests().await.unwrap();
+    assert_eq!(received.len(), 3);
+}
+
+#[tokio::test]
+async fn unknown_unsubscribe_tokens_are_rejected_with_a_404() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token=does-not-exist",
+        test_app.address
+    ))
+    .await
+    .expect("Failed to send request.");
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirmation_emails_fill_in_merge_tags() {
+    let test_app = spawn_app().await;
+    sqlx::query!(
+        r#"
+    UPDATE publications
+    SET confirmation_subject = 'Welcome {{ name }}',
+        confirmation_message = 'Glad to have you, {{ name | default: "friend" }}!'
+    WHERE slug = 'default'"#
+    )
+    .execute(&test_app.connection_pool)
+    .await
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let emails = sent_emails(&test_app).await;
+    assert_eq!(emails[0]["Subject"], "Welcome le guin");
+    assert!(emails[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Glad to have you, le guin!"));
+}
@@ -0,0 +1,158 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp) -> String {
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+
+    let body: serde_json::Value = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"markdown": "Newsletter body"},
+            "link_check": "skip",
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["issue_id"].as_str().unwrap().to_string()
+}
+
+async fn stream_progress(app: &TestApp, issue_id: &str) -> reqwest::Response {
+    app.admin_request(Method::GET, &format!("/admin/issues/{}/progress", issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+// (event, data) pairs of a Server-Sent Events body.
+fn parse_events(body: &str) -> Vec<(String, serde_json::Value)> {
+    body.split("\n\n")
+        .filter(|event| !event.is_empty())
+        .map(|event| {
+            let mut lines = event.lines();
+            let name = lines.next().unwrap().strip_prefix("event: ").unwrap();
+            let data = lines.next().unwrap().strip_prefix("data: ").unwrap();
+            (name.to_string(), serde_json::from_str(data).unwrap())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn the_progress_stream_ends_with_the_delivery() {
+    let test_app = spawn_app().await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+    let issue_id = publish_issue(&test_app).await;
+
+    let mut response = stream_progress(&test_app, &issue_id).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "text/event-stream"
+    );
+    let first = response.chunk().await.unwrap().unwrap();
+    let first = parse_events(std::str::from_utf8(&first).unwrap());
+    assert_eq!(first[0].0, "progress");
+    assert_eq!(first[0].1["status"], "published");
+    assert_eq!(first[0].1["pending"], 2);
+    assert_eq!(first[0].1["delivered"], 0);
+
+    test_app.dispatch_all_pending_emails().await;
+
+    let rest = parse_events(&response.text().await.unwrap());
+    let (event, report) = rest.last().unwrap();
+    assert_eq!(event, "done");
+    assert_eq!(report["delivered"], 2);
+    assert_eq!(report["pending"], 0);
+    assert_eq!(report["failed"], 0);
+    assert_eq!(report["retried"], 0);
+}
+
+#[tokio::test]
+async fn finished_deliveries_are_reported_in_a_single_event() {
+    let test_app = spawn_app().await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    let issue_id = publish_issue(&test_app).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    let body = stream_progress(&test_app, &issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    let events = parse_events(&body);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "done");
+    assert_eq!(events[0].1["issue_id"], issue_id.as_str());
+    assert_eq!(events[0].1["delivered"], 2);
+}
+
+#[tokio::test]
+async fn the_progress_of_unknown_issues_is_a_404() {
+    let test_app = spawn_app().await;
+
+    let response = stream_progress(&test_app, &uuid::Uuid::new_v4().to_string()).await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_progress_stream_requires_an_admin_token() {
+    let test_app = spawn_app().await;
+    let issue_id = publish_issue(&test_app).await;
+
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/issues/{}/progress",
+            test_app.address, issue_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_progress_page_follows_the_stream_of_the_issue() {
+    let test_app = spawn_app().await;
+    let issue_id = uuid::Uuid::new_v4();
+
+    let response = reqwest::get(format!(
+        "{}/p/default/admin/issues/{}/progress/page",
+        test_app.address, issue_id
+    ))
+    .await
+    .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    assert!(page.contains(&issue_id.to_string()));
+    assert!(page.contains(&format!(
+        "data-progress-url=\"/p/default/admin/issues/{}/progress\"",
+        issue_id
+    )));
+}
@@ -0,0 +1,204 @@
+use wiremock::{
+    matchers::{any, method},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn subscribing_under_a_path_prefix_stores_the_subscriber_in_that_publication() {
+    let test_app = spawn_app().await;
+    let publication_id = test_app.create_publication("rustaceans", None).await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/p/rustaceans/subscriptions", test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to send subscription request.");
+
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT publication_id FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+
+    assert_eq!(saved.publication_id, publication_id);
+}
+
+#[tokio::test]
+async fn the_host_header_selects_the_publication() {
+    let test_app = spawn_app().await;
+    let publication_id = test_app
+        .create_publication("rustaceans", Some("rustaceans.example.com"))
+        .await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", test_app.address))
+        .header("Host", "rustaceans.example.com")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to send subscription request.");
+
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT publication_id FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+
+    assert_eq!(saved.publication_id, publication_id);
+}
+
+#[tokio::test]
+async fn forwarded_hosts_do_not_select_the_publication() {
+    let test_app = spawn_app().await;
+    let publication_id = test_app
+        .create_publication("rustaceans", Some("rustaceans.example.com"))
+        .await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", test_app.address))
+        .header("X-Forwarded-Host", "rustaceans.example.com")
+        .header("Forwarded", "host=rustaceans.example.com")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to send subscription request.");
+
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT publication_id FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+
+    assert_ne!(saved.publication_id, publication_id);
+}
+
+#[tokio::test]
+async fn confirmation_emails_use_the_publication_sender() {
+    let test_app = spawn_app().await;
+    test_app.create_publication("rustaceans", None).await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/p/rustaceans/subscriptions", test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to send subscription request.");
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["From"], "rustaceans@publications.com");
+
+    let confirmation_links = test_app.get_confirmation_link(email_request);
+    assert!(confirmation_links
+        .html
+        .path()
+        .starts_with("/p/rustaceans/subscriptions/confirm"));
+}
+
+#[tokio::test]
+async fn the_same_email_can_subscribe_to_several_publications() {
+    let test_app = spawn_app().await;
+    test_app.create_publication("rustaceans", None).await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let default_response = test_app.post_subscription(body.into()).await;
+    let prefixed_response = reqwest::Client::new()
+        .post(format!("{}/p/rustaceans/subscriptions", test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to send subscription request.");
+
+    assert_eq!(200, default_response.status().as_u16());
+    assert_eq!(200, prefixed_response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirmation_tokens_are_rejected_by_other_publications() {
+    let test_app = spawn_app().await;
+    test_app.create_publication("rustaceans", None).await;
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let mut confirmation_link = test_app.get_confirmation_link(email_request).html;
+    confirmation_link.set_path("/p/rustaceans/subscriptions/confirm");
+
+    let response = reqwest::get(confirmation_link)
+        .await
+        .expect("Failed to follow the confirmation link.");
+    assert!(!response.status().is_success());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn unknown_publications_are_rejected_with_a_404() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/p/does-not-exist/subscriptions",
+            test_app.address
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to send subscription request.");
+
+    assert_eq!(404, response.status().as_u16());
+}
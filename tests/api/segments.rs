@@ -0,0 +1,214 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+// Imported subscribers are confirmed, so they all belong to the audience.
+async fn import_audience(app: &TestApp) {
+    for field in [
+        serde_json::json!({"key": "country", "label": "Country", "kind": "text"}),
+        serde_json::json!({"key": "seats", "label": "Seats", "kind": "number"}),
+    ] {
+        app.admin_request(Method::POST, "/admin/fields")
+            .json(&field)
+            .send()
+            .await
+            .expect("Failed to create custom field.")
+            .error_for_status()
+            .unwrap();
+    }
+
+    let csv = "email,name,country,seats,tags\n\
+        ada@example.com,Ada,DE,10,rust;beta\n\
+        grace@example.com,Grace,FR,3,rust\n\
+        linus@example.com,Linus,FI,1,c\n\
+        barbara@example.com,Barbara,DE,,\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+async fn preview(app: &TestApp, expression: &str) -> reqwest::Response {
+    app.admin_request(Method::POST, "/admin/segments/preview")
+        .json(&serde_json::json!({ "expression": expression }))
+        .send()
+        .await
+        .expect("Failed to preview segment.")
+}
+
+async fn save_segment(app: &TestApp, name: &str, expression: &str) -> reqwest::Response {
+    app.admin_request(Method::POST, "/admin/segments")
+        .json(&serde_json::json!({ "name": name, "expression": expression }))
+        .send()
+        .await
+        .expect("Failed to save segment.")
+}
+
+#[tokio::test]
+async fn segment_previews_count_the_matching_subscribers() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    let test_cases = vec![
+        ("tag:rust", 2),
+        ("tag:rust AND country IN (DE, FR)", 2),
+        ("country = DE AND NOT tag:rust", 1),
+        ("seats >= 3", 2),
+        ("seats < 3 OR tag:beta", 2),
+        ("name != Ada", 3),
+        ("email IN (ada@example.com, linus@example.com)", 2),
+        ("subscribed_at > 2020-01-01", 4),
+        ("subscribed_at < 2020-01-01", 0),
+        (r#"name = "x' OR 1=1 --""#, 0),
+    ];
+
+    for (expression, expected) in test_cases {
+        let response = preview(&test_app, expression).await;
+        assert_eq!(200, response.status().as_u16(), "{}", expression);
+
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            body["subscribers"], expected,
+            "Unexpected audience size for {}",
+            expression
+        );
+    }
+}
+
+#[tokio::test]
+async fn unconfirmed_subscribers_are_not_part_of_any_segment() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'pending_confirmation' WHERE name = 'Ada'")
+        .execute(&test_app.connection_pool)
+        .await
+        .unwrap();
+
+    let body: serde_json::Value = preview(&test_app, "tag:rust").await.json().await.unwrap();
+
+    assert_eq!(body["subscribers"], 1);
+}
+
+#[tokio::test]
+async fn invalid_segment_expressions_are_rejected_with_a_400() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    let test_cases = vec![
+        ("tag:rust AND", "an incomplete expression"),
+        ("plan = pro", "an undefined field"),
+        ("country > DE", "ordering a text field"),
+        ("seats = many", "a number field compared to text"),
+        ("subscribed_at > yesterday", "an invalid date"),
+        ("opened_last(100000d)", "a period going back too far"),
+    ];
+
+    for (expression, description) in test_cases {
+        let response = preview(&test_app, expression).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn saved_segments_report_their_current_size() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    let response = save_segment(&test_app, "rustaceans", "tag:rust").await;
+    assert_eq!(201, response.status().as_u16());
+
+    let duplicate = save_segment(&test_app, "rustaceans", "tag:c").await;
+    assert_eq!(409, duplicate.status().as_u16());
+
+    test_app
+        .admin_request(
+            Method::PUT,
+            &format!(
+                "/admin/subscribers/{}/tags",
+                sqlx::query!("SELECT id FROM subscriptions WHERE name = 'Linus'")
+                    .fetch_one(&test_app.connection_pool)
+                    .await
+                    .unwrap()
+                    .id
+            ),
+        )
+        .json(&serde_json::json!({"tags": ["rust"]}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let segment: serde_json::Value = test_app
+        .admin_request(Method::GET, "/admin/segments/rustaceans")
+        .send()
+        .await
+        .expect("Failed to fetch segment.")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(segment["expression"], "tag:rust");
+    assert_eq!(segment["subscribers"], 3);
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_a_segment_only_reach_its_members() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    save_segment(&test_app, "germany", "country = DE")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "segment": "germany",
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_an_unknown_segment_are_rejected() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "segment": "does-not-exist",
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
@@ -0,0 +1,398 @@
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn create_plan_and_seats_fields(app: &TestApp) {
+    for field in [
+        serde_json::json!({
+            "key": "plan",
+            "label": "Plan tier",
+            "kind": "enum",
+            "options": ["free", "pro"],
+            "required": true,
+        }),
+        serde_json::json!({"key": "seats", "label": "Seats", "kind": "number"}),
+    ] {
+        let response = app
+            .admin_request(Method::POST, "/admin/fields")
+            .json(&field)
+            .send()
+            .await
+            .expect("Failed to create custom field.");
+        assert_eq!(201, response.status().as_u16());
+    }
+}
+
+async fn subscribe(app: &TestApp, body: &str) -> reqwest::Response {
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.to_string()).await
+}
+
+async fn subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.")
+        .id
+}
+
+#[tokio::test]
+async fn subscribe_persists_valid_custom_fields() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+
+    let response = subscribe(
+        &test_app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&plan=pro&seats=5",
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT custom_fields FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!(
+        saved.custom_fields,
+        serde_json::json!({"plan": "pro", "seats": 5.0})
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_invalid_custom_fields() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+
+    let test_cases = vec![
+        ("plan=enterprise", "an unknown enum option"),
+        ("plan=pro&seats=many", "a number field that is not a number"),
+        ("plan=pro&company=acme", "an undefined custom field"),
+        ("seats=5", "a missing required field"),
+    ];
+
+    for (fields, description) in test_cases {
+        let body = format!("name=le%20guin&email=ursula_le_guin%40gmail.com&{}", fields);
+        let response = subscribe(&test_app, &body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn invalid_custom_fields_are_reported_with_the_other_fields() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+
+    let response = subscribe(
+        &test_app,
+        "name=le%20guin&email=definitely-not-an-email&seats=many&company=acme",
+    )
+    .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    let errors = &problem["errors"];
+    assert_eq!(errors["email"]["code"], "invalid_format");
+    assert_eq!(errors["seats"]["code"], "invalid_format");
+    assert_eq!(errors["company"]["code"], "unknown_field");
+    assert_eq!(errors["plan"]["code"], "empty");
+}
+
+#[tokio::test]
+async fn json_subscriptions_may_send_custom_fields_as_numbers_and_booleans() {
+    let test_app = spawn_app().await;
+    for field in [
+        serde_json::json!({"key": "age", "label": "Age", "kind": "number"}),
+        serde_json::json!({"key": "beta", "label": "Beta tester", "kind": "boolean"}),
+    ] {
+        test_app
+            .admin_request(Method::POST, "/admin/fields")
+            .json(&field)
+            .send()
+            .await
+            .expect("Failed to create custom field.")
+            .error_for_status()
+            .unwrap();
+    }
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "age": 42,
+        "beta": true,
+    });
+    let response = test_app
+        .post_subscription_as("application/json", body.to_string())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT custom_fields FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!(
+        saved.custom_fields,
+        serde_json::json!({"age": 42.0, "beta": true})
+    );
+}
+
+#[tokio::test]
+async fn custom_field_keys_are_unique_within_a_publication() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+
+    let response = test_app
+        .admin_request(Method::POST, "/admin/fields")
+        .json(&serde_json::json!({"key": "seats", "label": "Seats", "kind": "text"}))
+        .send()
+        .await
+        .expect("Failed to create custom field.");
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_can_update_the_custom_fields_of_a_subscriber() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+    subscribe(
+        &test_app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&plan=free",
+    )
+    .await;
+    let subscriber_id = subscriber_id(&test_app).await;
+
+    let response = test_app
+        .admin_request(
+            Method::PUT,
+            &format!("/admin/subscribers/{}/fields", subscriber_id),
+        )
+        .json(&serde_json::json!({"fields": {"plan": "pro", "seats": 10}}))
+        .send()
+        .await
+        .expect("Failed to update custom fields.");
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT custom_fields FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!(
+        saved.custom_fields,
+        serde_json::json!({"plan": "pro", "seats": 10.0})
+    );
+}
+
+#[tokio::test]
+async fn admins_cannot_update_subscribers_of_another_publication() {
+    let test_app = spawn_app().await;
+    test_app.create_publication("rustaceans", None).await;
+    subscribe(&test_app, "name=le%20guin&email=ursula_le_guin%40gmail.com").await;
+    let subscriber_id = subscriber_id(&test_app).await;
+
+    // The test admin belongs to the default publication,
+    // the subscriber is looked up in the rustaceans publication instead.
+    let publication_id = sqlx::query!("SELECT id FROM publications WHERE slug = 'rustaceans'")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap()
+        .id;
+    sqlx::query!(
+        "UPDATE subscriptions SET publication_id = $1",
+        publication_id
+    )
+    .execute(&test_app.connection_pool)
+    .await
+    .unwrap();
+
+    let response = test_app
+        .admin_request(
+            Method::PUT,
+            &format!("/admin/subscribers/{}/tags", subscriber_id),
+        )
+        .json(&serde_json::json!({"tags": ["rust"]}))
+        .send()
+        .await
+        .expect("Failed to update tags.");
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn tags_assigned_to_a_subscriber_are_counted() {
+    let test_app = spawn_app().await;
+    subscribe(&test_app, "name=le%20guin&email=ursula_le_guin%40gmail.com").await;
+    let subscriber_id = subscriber_id(&test_app).await;
+
+    test_app
+        .admin_request(Method::POST, "/admin/tags")
+        .json(&serde_json::json!({"name": "Go"}))
+        .send()
+        .await
+        .expect("Failed to create tag.");
+    let response = test_app
+        .admin_request(
+            Method::PUT,
+            &format!("/admin/subscribers/{}/tags", subscriber_id),
+        )
+        .json(&serde_json::json!({"tags": ["Rust", "beta-testers"]}))
+        .send()
+        .await
+        .expect("Failed to update tags.");
+    assert_eq!(200, response.status().as_u16());
+
+    let tags: serde_json::Value = test_app
+        .admin_request(Method::GET, "/admin/tags")
+        .send()
+        .await
+        .expect("Failed to list tags.")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        tags,
+        serde_json::json!([
+            {"name": "beta-testers", "subscribers": 1},
+            {"name": "go", "subscribers": 0},
+            {"name": "rust", "subscribers": 1},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn csv_imports_create_and_update_subscribers() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+    subscribe(
+        &test_app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&plan=free&seats=1",
+    )
+    .await;
+
+    let csv = "email,name,plan,tags\n\
+        ursula_le_guin@gmail.com,Ursula Le Guin,pro,rust;authors\n\
+        octavia@butler.com,Octavia Butler,free,\n";
+    let response = test_app
+        .admin_request(Method::POST, "/admin/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.");
+    assert_eq!(200, response.status().as_u16());
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary, serde_json::json!({"created": 1, "updated": 1}));
+
+    let saved = sqlx::query!(
+        "SELECT name, status, custom_fields FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com'"
+    )
+    .fetch_one(&test_app.connection_pool)
+    .await
+    .expect("Failed to fetch saved subscriber.");
+    assert_eq!(saved.name, "Ursula Le Guin");
+    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(
+        saved.custom_fields,
+        serde_json::json!({"plan": "pro", "seats": 1.0})
+    );
+
+    let imported =
+        sqlx::query!("SELECT status FROM subscriptions WHERE email = 'octavia@butler.com'")
+            .fetch_one(&test_app.connection_pool)
+            .await
+            .expect("Failed to fetch imported subscriber.");
+    assert_eq!(imported.status, "confirmed");
+}
+
+#[tokio::test]
+async fn csv_imports_with_an_invalid_line_import_nothing() {
+    let test_app = spawn_app().await;
+
+    let csv = "email,name\n\
+        octavia@butler.com,Octavia Butler\n\
+        not-an-email,Someone\n";
+    let response = test_app
+        .admin_request(Method::POST, "/admin/subscribers/import")
+        .header("Content-Type", "text/csv")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.");
+    assert_eq!(400, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&test_app.connection_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn the_subscription_form_asks_for_the_custom_fields() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+
+    let page = reqwest::get(format!("{}/subscriptions", test_app.address))
+        .await
+        .expect("Failed to fetch the subscription form.")
+        .text()
+        .await
+        .unwrap();
+
+    assert!(page.contains(r#"<label for="plan">Plan tier</label>"#));
+    assert!(page.contains(r#"<select id="plan" name="plan" required>"#));
+    assert!(page.contains("<option>pro</option>"));
+    assert!(page.contains(r#"<input id="seats" name="seats" type="number" value="" step="any" />"#));
+}
+
+#[tokio::test]
+async fn the_form_can_be_completed_with_its_required_fields() {
+    let test_app = spawn_app().await;
+    create_plan_and_seats_fields(&test_app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    let submit = |body: &'static str| {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", test_app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", "text/html")
+            .body(body)
+            .send()
+    };
+
+    let response = submit("name=le%20guin&email=ursula_le_guin%40gmail.com&plan=&seats=3")
+        .await
+        .unwrap();
+    assert_eq!(400, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"id="plan-error" data-code="empty""#));
+    // What was typed in is kept.
+    assert!(page.contains(r#"name="seats" type="number" value="3""#));
+
+    let response = submit("name=le%20guin&email=ursula_le_guin%40gmail.com&plan=pro&seats=3")
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
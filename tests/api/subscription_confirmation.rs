@@ -16,6 +16,27 @@ async fn confirmations_without_token_are_rejected_with_a_400() {
     assert_eq!(400, response.status().as_u16());
 }
 
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_problem() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        test_app.address
+    ))
+    .await
+    .expect("Failed to send request to the test app.");
+
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 404);
+    assert!(problem["detail"].is_string());
+}
+
 #[tokio::test]
 async fn the_link_returned_by_subscribe_returns_a_200_when_called() {
     let test_app = spawn_app().await;
@@ -72,3 +93,123 @@ async fn click_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+async fn subscribe_and_get_confirmation_link(test_app: &TestApp) -> String {
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    test_app
+        .get_confirmation_link(email_request)
+        .html
+        .to_string()
+}
+
+async fn subscription_status(test_app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .status
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_resubscribe_an_unsubscribed_subscriber() {
+    let test_app = spawn_app().await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&test_app).await;
+    reqwest::get(&confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let unsubscribe_token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        test_app.address, unsubscribe_token
+    ))
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+
+    let response = reqwest::get(&confirmation_link)
+        .await
+        .expect("Failed to follow the confirmation link.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscription_status(&test_app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_confirmation_link_does_not_resubscribe_a_suppressed_subscriber() {
+    let events = [
+        (
+            serde_json::json!({
+                "RecordType": "Bounce",
+                "Type": "HardBounce",
+                "Email": "ursula_le_guin@gmail.com"
+            }),
+            "bounced",
+        ),
+        (
+            serde_json::json!({"RecordType": "SpamComplaint", "Email": "ursula_le_guin@gmail.com"}),
+            "complained",
+        ),
+    ];
+    for (event, status) in events {
+        let test_app = spawn_app().await;
+        let confirmation_link = subscribe_and_get_confirmation_link(&test_app).await;
+        test_app
+            .post_email_event(event)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let response = reqwest::get(&confirmation_link)
+            .await
+            .expect("Failed to follow the confirmation link.");
+
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!(subscription_status(&test_app).await, status);
+    }
+}
+
+#[tokio::test]
+async fn unsubscribing_keeps_a_subscriber_suppressed() {
+    let test_app = spawn_app().await;
+    subscribe_and_get_confirmation_link(&test_app).await;
+    test_app
+        .post_email_event(serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Email": "ursula_le_guin@gmail.com"
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let unsubscribe_token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        test_app.address, unsubscribe_token
+    ))
+    .await
+    .expect("Failed to follow the unsubscribe link.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscription_status(&test_app).await, "complained");
+}
@@ -91,6 +91,86 @@ async fn subsctibe_returns_a_400_when_fields_are_present_but_invalid() {
     }
 }
 
+#[tokio::test]
+async fn invalid_fields_are_reported_in_a_problem_document() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_subscription("name=Ursula&email=definitely-not-an-email".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "about:blank");
+    assert_eq!(problem["title"], "Bad Request");
+    assert_eq!(problem["errors"]["email"]["code"], "invalid_format");
+    assert!(problem["errors"]["email"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("definitely-not-an-email"));
+    assert!(problem["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn every_invalid_field_is_reported_at_once() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_subscription("name=Ursula%20%3C3&email=definitely-not-an-email".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"]["name"]["code"], "forbidden_character");
+    assert_eq!(problem["errors"]["email"]["code"], "invalid_format");
+}
+
+#[tokio::test]
+async fn the_form_is_shown_again_with_its_errors_to_browsers() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "text/html,application/xhtml+xml")
+        .body("name=Ursula%20%3C3&email=definitely-not-an-email")
+        .send()
+        .await
+        .expect("Failed to send subscription request.");
+
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"data-code="forbidden_character""#));
+    assert!(page.contains(r#"data-code="invalid_format""#));
+    // What was typed in is kept, escaped.
+    assert!(page.contains(r#"value="definitely-not-an-email""#));
+    assert!(page.contains(r#"value="Ursula &lt;3""#));
+}
+
+#[tokio::test]
+async fn the_subscription_form_is_served() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscriptions", test_app.address))
+        .send()
+        .await
+        .expect("Failed to fetch the subscription form.");
+
+    assert_eq!(200, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"<form method="post">"#));
+    assert!(page.contains(r#"name="email""#));
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let test_app = spawn_app().await;
@@ -140,4 +220,116 @@ async fn subscription_fails_if_there_is_a_fatal_database_error() {
     let response = test_app.post_subscription(body.into()).await;
 
     assert_eq!(response.status().as_u16(), 500);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    // What went wrong inside stays in the logs.
+    assert!(problem.get("detail").is_none());
+    assert!(problem["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_multipart_bodies() {
+    let test_app = spawn_app().await;
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    let json = serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+    let response = test_app
+        .post_subscription_as("application/json", json.to_string())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let boundary = "zero2prod-boundary";
+    let multipart = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\nhg liu\r\n\
+        --{b}\r\nContent-Disposition: form-data; name=\"email\"\r\n\r\nhg_liu@gmail.com\r\n\
+        --{b}--\r\n",
+        b = boundary
+    );
+    let response = test_app
+        .post_subscription_as(
+            &format!("multipart/form-data; boundary={}", boundary),
+            multipart,
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&test_app.connection_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    let emails: Vec<_> = saved.into_iter().map(|row| row.email).collect();
+    assert_eq!(emails, ["hg_liu@gmail.com", "ursula_le_guin@gmail.com"]);
+}
+
+#[tokio::test]
+async fn json_bodies_are_validated_like_forms() {
+    let test_app = spawn_app().await;
+
+    let json = serde_json::json!({"name": "Ursula <3", "email": "definitely-not-an-email"});
+    let response = test_app
+        .post_subscription_as("application/json", json.to_string())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"]["name"]["code"], "forbidden_character");
+    assert_eq!(problem["errors"]["email"]["code"], "invalid_format");
+}
+
+#[tokio::test]
+async fn malformed_bodies_are_rejected_with_a_problem() {
+    let test_app = spawn_app().await;
+
+    let test_cases = [
+        ("application/json", "{\"name\": \"le guin\"", "broken JSON"),
+        (
+            "application/json",
+            "{\"name\": \"le guin\"}",
+            "JSON missing the email",
+        ),
+        (
+            "multipart/form-data; boundary=missing",
+            "not multipart at all",
+            "broken multipart",
+        ),
+    ];
+    for (content_type, body, description) in test_cases {
+        let response = test_app
+            .post_subscription_as(content_type, body.into())
+            .await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "application/problem+json"
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert!(problem["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("The request body is malformed"));
+    }
+}
+
+#[tokio::test]
+async fn unsupported_content_types_are_rejected_with_a_415() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_subscription_as("text/plain", "le guin <ursula_le_guin@gmail.com>".into())
+        .await;
+
+    assert_eq!(415, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 415);
+    assert!(problem["detail"].as_str().unwrap().contains("text/plain"));
 }
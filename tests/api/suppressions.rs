@@ -0,0 +1,228 @@
+use chrono::Utc;
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_audience(app: &TestApp) {
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+}
+
+fn hard_bounce(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "Email": email,
+        "BouncedAt": "2024-05-06T08:41:12Z"
+    })
+}
+
+fn spam_complaint(email: &str) -> serde_json::Value {
+    serde_json::json!({"RecordType": "SpamComplaint", "Email": email})
+}
+
+async fn subscription_status(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.connection_pool)
+        .await
+        .expect("Failed to fetch the subscription.")
+        .status
+}
+
+async fn suppression_reason(app: &TestApp, email: &str) -> Option<String> {
+    sqlx::query!("SELECT reason FROM suppressions WHERE email = $1", email)
+        .fetch_optional(&app.connection_pool)
+        .await
+        .unwrap()
+        .map(|row| row.reason)
+}
+
+#[tokio::test]
+async fn hard_bounces_are_suppressed_and_left_out_of_issues() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    let response = test_app
+        .post_email_event(hard_bounce("Ada@Example.com"))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        subscription_status(&test_app, "ada@example.com").await,
+        "bounced"
+    );
+    assert_eq!(
+        suppression_reason(&test_app, "ada@example.com")
+            .await
+            .as_deref(),
+        Some("bounced")
+    );
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    let body: serde_json::Value = test_app
+        .admin_request(Method::POST, "/admin/issues")
+        .json(&serde_json::json!({"title": "Hello", "content": {"markdown": "Hi"}}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    test_app
+        .admin_request(
+            Method::POST,
+            &format!(
+                "/admin/issues/{}/publish",
+                body["issue_id"].as_str().unwrap()
+            ),
+        )
+        .json(&serde_json::json!({"link_check": "skip"}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn complaints_outweigh_bounces() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    test_app
+        .post_email_event(spam_complaint("ada@example.com"))
+        .await;
+    test_app
+        .post_email_event(hard_bounce("ada@example.com"))
+        .await;
+
+    assert_eq!(
+        subscription_status(&test_app, "ada@example.com").await,
+        "complained"
+    );
+    assert_eq!(
+        suppression_reason(&test_app, "ada@example.com")
+            .await
+            .as_deref(),
+        Some("complained")
+    );
+    assert_eq!(
+        subscription_status(&test_app, "grace@example.com").await,
+        "confirmed"
+    );
+}
+
+#[tokio::test]
+async fn soft_bounces_and_other_events_are_acknowledged_and_ignored() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+
+    for event in [
+        serde_json::json!({"RecordType": "Bounce", "Type": "SoftBounce", "Email": "ada@example.com"}),
+        serde_json::json!({"RecordType": "Delivery", "Recipient": "ada@example.com"}),
+    ] {
+        let response = test_app.post_email_event(event).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    assert_eq!(
+        subscription_status(&test_app, "ada@example.com").await,
+        "confirmed"
+    );
+    assert_eq!(suppression_reason(&test_app, "ada@example.com").await, None);
+}
+
+#[tokio::test]
+async fn events_without_a_valid_signature_are_rejected() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let body = serde_json::to_vec(&hard_bounce("ada@example.com")).unwrap();
+
+    let test_cases = vec![
+        (None, "a missing signature"),
+        (Some("sha256=00ff"), "a wrong signature"),
+        (Some("not hex at all"), "a malformed signature"),
+    ];
+    for (signature, description) in test_cases {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/webhooks/email", test_app.address))
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+        if let Some(signature) = signature {
+            request = request.header("X-Webhook-Signature", signature);
+        }
+        let response = request.send().await.unwrap();
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not fail with 401 Unauthorized for {}.",
+            description
+        );
+    }
+    assert_eq!(suppression_reason(&test_app, "ada@example.com").await, None);
+}
+
+#[tokio::test]
+async fn stale_events_are_rejected() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let an_hour_ago = Utc::now().timestamp() - 60 * 60;
+
+    let response = test_app
+        .post_email_event_signed_at(hard_bounce("ada@example.com"), an_hour_ago)
+        .await;
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(suppression_reason(&test_app, "ada@example.com").await, None);
+}
+
+#[tokio::test]
+async fn malformed_events_are_rejected_with_a_400() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_email_event(serde_json::json!({"RecordType": "Bounce"}))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn suppressed_addresses_can_not_subscribe() {
+    let test_app = spawn_app().await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_email_event(spam_complaint("ursula_le_guin@gmail.com"))
+        .await;
+
+    let response = test_app
+        .post_subscription("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let subscriptions = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.count, Some(0));
+}
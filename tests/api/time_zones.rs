@@ -0,0 +1,271 @@
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::{America::New_York, Asia::Tokyo};
+use reqwest::Method;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_audience(app: &TestApp) {
+    let csv = "email,name\nada@example.com,Ada\ngrace@example.com,Grace\nkatherine@example.com,Katherine\n";
+    app.admin_request(Method::POST, "/admin/subscribers/import")
+        .body(csv)
+        .send()
+        .await
+        .expect("Failed to import subscribers.")
+        .error_for_status()
+        .unwrap();
+
+    for (email, time_zone) in [
+        ("ada@example.com", "Asia/Tokyo"),
+        ("grace@example.com", "America/New_York"),
+    ] {
+        sqlx::query!(
+            "UPDATE subscriptions SET time_zone = $2 WHERE email = $1",
+            email,
+            time_zone
+        )
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    }
+}
+
+async fn stored_time_zone(app: &TestApp, email: &str) -> Option<String> {
+    sqlx::query!(
+        "SELECT time_zone FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap()
+    .time_zone
+}
+
+#[tokio::test]
+async fn the_time_zone_is_stored_when_subscribing() {
+    let test_app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscription(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&time_zone=America%2FLos_Angeles"
+                .into(),
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        stored_time_zone(&test_app, "ursula_le_guin@gmail.com").await,
+        Some("America/Los_Angeles".into())
+    );
+}
+
+#[tokio::test]
+async fn the_time_zone_is_optional_when_subscribing() {
+    let test_app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = test_app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com&time_zone=".into())
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        stored_time_zone(&test_app, "ursula_le_guin@gmail.com").await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn unknown_time_zones_are_rejected_when_subscribing() {
+    let test_app = spawn_app().await;
+
+    let response = test_app
+        .post_subscription(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&time_zone=Mars%2FOlympus".into(),
+        )
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribers_can_set_their_time_zone_from_the_preference_center() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let token = sqlx::query!(
+        "SELECT unsubscribe_token FROM subscriptions WHERE email = 'katherine@example.com'"
+    )
+    .fetch_one(&test_app.connection_pool)
+    .await
+    .unwrap()
+    .unsubscribe_token;
+
+    let preferences = |token: String, time_zone: &'static str| {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/preferences?unsubscribe_token={}",
+                test_app.address, token
+            ))
+            .form(&[("time_zone", time_zone)])
+            .send()
+    };
+
+    let response = preferences(token.clone(), "Europe/Berlin").await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        stored_time_zone(&test_app, "katherine@example.com").await,
+        Some("Europe/Berlin".into())
+    );
+
+    let response = preferences(token, "Europe/Atlantis").await.unwrap();
+    assert_eq!(400, response.status().as_u16());
+
+    let response = preferences("does-not-exist".into(), "Europe/Berlin")
+        .await
+        .unwrap();
+    assert_eq!(404, response.status().as_u16());
+}
+
+async fn create_draft(app: &TestApp) -> String {
+    let body: serde_json::Value = app
+        .admin_request(Method::POST, "/admin/issues")
+        .json(&serde_json::json!({
+            "title": "Good morning {{ name }}",
+            "content": {"markdown": "Coffee first."}
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["issue_id"].as_str().unwrap().to_string()
+}
+
+async fn release_times(app: &TestApp) -> Vec<(String, DateTime<Utc>)> {
+    sqlx::query!(
+        "SELECT subscriber_email, execute_after FROM issue_delivery_queue ORDER BY subscriber_email"
+    )
+    .fetch_all(&app.connection_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|task| (task.subscriber_email, task.execute_after))
+    .collect()
+}
+
+#[tokio::test]
+async fn issues_are_delivered_in_time_zone_waves() {
+    let test_app = spawn_app().await;
+    import_audience(&test_app).await;
+    let issue_id = create_draft(&test_app).await;
+
+    // 09:00 a few days from now, wherever the subscriber is.
+    let local_time: NaiveDateTime = (Utc::now() + Duration::days(3))
+        .date_naive()
+        .and_hms_opt(9, 0, 0)
+        .unwrap();
+    let response = test_app
+        .admin_request(Method::PUT, &format!("/admin/issues/{}/schedule", issue_id))
+        .json(&serde_json::json!({
+            "local_time": local_time,
+            "fallback_time_zone": "Europe/Berlin",
+            "link_check": "skip",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    // The first wave starts when the earliest time zone reaches 09:00.
+    let issue: serde_json::Value = test_app
+        .admin_request(Method::GET, &format!("/admin/issues/{}", issue_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let scheduled_at: DateTime<Utc> =
+        serde_json::from_value(issue["scheduled_at"].clone()).unwrap();
+    assert_eq!(
+        scheduled_at,
+        Utc.from_utc_datetime(&local_time) - Duration::hours(14)
+    );
+    assert_eq!(issue["fallback_time_zone"], "Europe/Berlin");
+
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_at = now() - interval '1 minute'")
+        .execute(&test_app.connection_pool)
+        .await
+        .unwrap();
+    test_app.publish_due_issues().await;
+
+    let local = |time_zone: chrono_tz::Tz| {
+        time_zone
+            .from_local_datetime(&local_time)
+            .unwrap()
+            .with_timezone(&Utc)
+    };
+    assert_eq!(
+        release_times(&test_app).await,
+        vec![
+            ("ada@example.com".to_string(), local(Tokyo)),
+            ("grace@example.com".to_string(), local(New_York)),
+            (
+                "katherine@example.com".to_string(),
+                local(chrono_tz::Europe::Berlin)
+            ),
+        ]
+    );
+
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Nobody's clock has reached 09:00 yet.
+    test_app.dispatch_all_pending_emails().await;
+    assert_eq!(release_times(&test_app).await.len(), 3);
+
+    // Tokyo gets there first.
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET execute_after = now() WHERE subscriber_email = 'ada@example.com'"
+    )
+    .execute(&test_app.connection_pool)
+    .await
+    .unwrap();
+    test_app.dispatch_all_pending_emails().await;
+    assert_eq!(release_times(&test_app).await.len(), 2);
+}
+
+#[tokio::test]
+async fn local_times_the_first_time_zone_has_passed_are_rejected() {
+    let test_app = spawn_app().await;
+    let issue_id = create_draft(&test_app).await;
+
+    let response = test_app
+        .admin_request(Method::PUT, &format!("/admin/issues/{}/schedule", issue_id))
+        .json(&serde_json::json!({
+            "local_time": (Utc::now() + Duration::hours(2)).naive_utc(),
+            "link_check": "skip",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+}
@@ -0,0 +1,302 @@
+use reqwest::Method;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn put_password_reset_template(app: &TestApp) {
+    app.admin_request(Method::PUT, "/admin/email_templates/password-reset")
+        .json(&serde_json::json!({
+            "subject": "Reset your password, {{ name | default: \"friend\" }}",
+            "html": "<p><a href=\"{{ reset_url }}\">Reset it</a> within {{ hours }} hours.</p>",
+            "text": "Reset it within {{ hours }} hours: {{ reset_url }}",
+            "variables": ["name", "reset_url", "hours"]
+        }))
+        .send()
+        .await
+        .expect("Failed to save the template.")
+        .error_for_status()
+        .unwrap();
+}
+
+async fn send_email(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    app.admin_request(Method::POST, "/api/v1/emails")
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to send the email request.")
+}
+
+fn password_reset_for(email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "template": "password-reset",
+        "to": email,
+        "variables": {"reset_url": "https://example.com/reset?t=1&u=2", "hours": 24}
+    })
+}
+
+async fn email_status(app: &TestApp, id: &str) -> serde_json::Value {
+    app.admin_request(Method::GET, &format!("/api/v1/emails/{}", id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn transactional_emails_are_rendered_and_sent() {
+    let test_app = spawn_app().await;
+    put_password_reset_template(&test_app).await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let response = send_email(&test_app, password_reset_for("ursula@example.com")).await;
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "queued");
+    let id = body["id"].as_str().unwrap();
+
+    test_app.dispatch_transactional_emails().await;
+
+    let status = email_status(&test_app, id).await;
+    assert_eq!(status["status"], "sent");
+    assert_eq!(status["template"], "password-reset");
+    assert_eq!(status["to"], "ursula@example.com");
+    assert_eq!(status["n_attempts"], 1);
+    let received = test_app.email_server.received_requests().await.unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&received[0].body).unwrap();
+    assert_eq!(email["To"], "ursula@example.com");
+    assert_eq!(email["Subject"], "Reset your password, friend");
+    assert_eq!(
+        email["TextBody"],
+        "Reset it within 24 hours: https://example.com/reset?t=1&u=2"
+    );
+    assert!(email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("https://example.com/reset?t=1&amp;u=2"));
+}
+
+#[tokio::test]
+async fn failed_emails_are_retried_later() {
+    let test_app = spawn_app().await;
+    put_password_reset_template(&test_app).await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&test_app.email_server)
+        .await;
+    Mock::given(path("/"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let body: serde_json::Value = send_email(&test_app, password_reset_for("ursula@example.com"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let id = body["id"].as_str().unwrap();
+
+    test_app.dispatch_transactional_emails().await;
+    let status = email_status(&test_app, id).await;
+    assert_eq!(status["status"], "queued");
+    assert_eq!(status["n_attempts"], 1);
+    assert!(status["last_error"].is_string());
+
+    sqlx::query!("UPDATE transactional_emails SET execute_after = now() - interval '1 second'")
+        .execute(&test_app.connection_pool)
+        .await
+        .unwrap();
+    test_app.dispatch_transactional_emails().await;
+    let status = email_status(&test_app, id).await;
+    assert_eq!(status["status"], "sent");
+    assert_eq!(status["n_attempts"], 2);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_never_sent_to() {
+    let test_app = spawn_app().await;
+    put_password_reset_template(&test_app).await;
+    test_app
+        .post_email_event(serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Email": "ursula@example.com"
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    Mock::given(path("/"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body: serde_json::Value = send_email(&test_app, password_reset_for("ursula@example.com"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["status"], "suppressed");
+    test_app.dispatch_transactional_emails().await;
+    let status = email_status(&test_app, body["id"].as_str().unwrap()).await;
+    assert_eq!(status["status"], "suppressed");
+}
+
+#[tokio::test]
+async fn invalid_email_requests_are_rejected_with_a_400() {
+    let test_app = spawn_app().await;
+    put_password_reset_template(&test_app).await;
+    let test_cases = [
+        (
+            serde_json::json!({"template": "welcome", "to": "ursula@example.com"}),
+            "an unknown template",
+        ),
+        (
+            serde_json::json!({"template": "password-reset", "to": "not an email"}),
+            "an invalid recipient",
+        ),
+        (
+            serde_json::json!({
+                "template": "password-reset",
+                "to": "ursula@example.com",
+                "variables": {"hours": 24}
+            }),
+            "a missing variable",
+        ),
+        (
+            serde_json::json!({
+                "template": "password-reset",
+                "to": "ursula@example.com",
+                "variables": {"reset_url": "x", "hours": 24, "nmae": "Ursula"}
+            }),
+            "an undeclared variable",
+        ),
+        (
+            serde_json::json!({
+                "template": "password-reset",
+                "to": "ursula@example.com",
+                "variables": {"reset_url": ["x"], "hours": 24}
+            }),
+            "a variable that is not a string",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = send_email(&test_app, body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a request with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_email_api_requires_a_token() {
+    let test_app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/emails", test_app.address))
+        .json(&password_reset_for("ursula@example.com"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, response.status().as_u16());
+
+    let response = test_app
+        .admin_request(
+            Method::GET,
+            "/api/v1/emails/7d8a1f3e-3b6c-4a1e-9c37-7f5b1d0c2a9e",
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_templates_are_rejected_with_a_400() {
+    let test_app = spawn_app().await;
+    let test_cases = [
+        (
+            "/admin/email_templates/Password%20Reset",
+            serde_json::json!({"subject": "Hi", "html": "<p>Hi</p>", "text": "Hi"}),
+            "an invalid name",
+        ),
+        (
+            "/admin/email_templates/password-reset",
+            serde_json::json!({"subject": "Hi {{ name }}", "html": "<p>Hi</p>", "text": "Hi"}),
+            "an undeclared variable",
+        ),
+        (
+            "/admin/email_templates/password-reset",
+            serde_json::json!({
+                "subject": "Hi",
+                "html": "<p>Hi</p>",
+                "text": "Hi",
+                "variables": ["reset-url"]
+            }),
+            "an invalid variable name",
+        ),
+    ];
+
+    for (path, body, description) in test_cases {
+        let response = test_app
+            .admin_request(Method::PUT, path)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a template with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn templates_can_be_replaced_and_deleted() {
+    let test_app = spawn_app().await;
+    put_password_reset_template(&test_app).await;
+    put_password_reset_template(&test_app).await;
+
+    let templates: Vec<serde_json::Value> = test_app
+        .admin_request(Method::GET, "/admin/email_templates")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(templates.len(), 1);
+    assert_eq!(templates[0]["name"], "password-reset");
+    assert_eq!(
+        templates[0]["variables"],
+        serde_json::json!(["name", "reset_url", "hours"])
+    );
+
+    for expected_status in [204, 404] {
+        let response = test_app
+            .admin_request(Method::DELETE, "/admin/email_templates/password-reset")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(expected_status, response.status().as_u16());
+    }
+}
@@ -0,0 +1,320 @@
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, MockServer, Request, ResponseTemplate,
+};
+use zero2prod::webhooks::sign_payload;
+
+use crate::helpers::{spawn_app, TestApp};
+
+struct Webhook {
+    id: Uuid,
+    secret: String,
+}
+
+async fn create_webhook(app: &TestApp, body: serde_json::Value) -> Webhook {
+    let response: serde_json::Value = app
+        .admin_request(Method::POST, "/admin/webhooks")
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to create the webhook.")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    Webhook {
+        id: response["id"].as_str().unwrap().parse().unwrap(),
+        secret: response["secret"].as_str().unwrap().to_string(),
+    }
+}
+
+async fn subscribe(app: &TestApp) {
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn deliveries(app: &TestApp, webhook: &Webhook) -> Vec<serde_json::Value> {
+    app.admin_request(
+        Method::GET,
+        &format!("/admin/webhooks/{}/deliveries", webhook.id),
+    )
+    .send()
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap()
+    .json()
+    .await
+    .unwrap()
+}
+
+// Makes the deliveries waiting for a retry due right away.
+async fn skip_backoff(app: &TestApp) {
+    sqlx::query!("UPDATE webhook_deliveries SET execute_after = now() - interval '1 second'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+}
+
+fn event_types(requests: &[Request]) -> Vec<String> {
+    requests
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["type"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn subscriber_lifecycle_events_are_delivered_signed() {
+    let test_app = spawn_app().await;
+    let endpoint = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&endpoint)
+        .await;
+    let webhook = create_webhook(
+        &test_app,
+        serde_json::json!({"url": format!("{}/hooks", endpoint.uri())}),
+    )
+    .await;
+    assert_eq!(webhook.secret.len(), 40);
+
+    subscribe(&test_app).await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_link(email_request);
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Confirming again changes nothing, no event is emitted.
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let token = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&test_app.connection_pool)
+        .await
+        .unwrap()
+        .unsubscribe_token;
+    reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        test_app.address, token
+    ))
+    .await
+    .unwrap()
+    .error_for_status()
+    .unwrap();
+    test_app.dispatch_pending_webhooks().await;
+
+    let requests = endpoint.received_requests().await.unwrap();
+    let mut types = event_types(&requests);
+    types.sort();
+    assert_eq!(
+        types,
+        [
+            "subscriber.confirmed",
+            "subscriber.subscribed",
+            "subscriber.unsubscribed"
+        ]
+    );
+    for request in &requests {
+        let header = |name: &str| request.headers.get(name).unwrap().to_str().unwrap();
+        let timestamp: i64 = header("X-Webhook-Timestamp").parse().unwrap();
+        assert_eq!(
+            header("X-Webhook-Signature"),
+            sign_payload(&webhook.secret, timestamp, &request.body)
+        );
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(header("X-Webhook-Id"), body["id"].as_str().unwrap());
+        assert_eq!(
+            body["data"]["subscriber"]["email"],
+            "ursula_le_guin@gmail.com"
+        );
+    }
+    assert!(deliveries(&test_app, &webhook)
+        .await
+        .iter()
+        .all(|delivery| delivery["status"] == "delivered"));
+}
+
+#[tokio::test]
+async fn endpoints_only_receive_the_event_types_they_listen_to() {
+    let test_app = spawn_app().await;
+    let endpoint = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&endpoint)
+        .await;
+    create_webhook(
+        &test_app,
+        serde_json::json!({"url": endpoint.uri(), "events": ["subscriber.bounced"]}),
+    )
+    .await;
+
+    subscribe(&test_app).await;
+    test_app
+        .post_email_event(serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": "HardBounce",
+            "Email": "ursula_le_guin@gmail.com",
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    test_app.dispatch_pending_webhooks().await;
+
+    let requests = endpoint.received_requests().await.unwrap();
+    assert_eq!(event_types(&requests), ["subscriber.bounced"]);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_then_given_up_on_and_can_be_replayed() {
+    let test_app = spawn_app().await;
+    let endpoint = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(6)
+        .mount(&endpoint)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&endpoint)
+        .await;
+    let webhook = create_webhook(&test_app, serde_json::json!({"url": endpoint.uri()})).await;
+    subscribe(&test_app).await;
+
+    test_app.dispatch_pending_webhooks().await;
+    let delivery = &deliveries(&test_app, &webhook).await[0];
+    assert_eq!(delivery["status"], "pending");
+    assert_eq!(delivery["n_attempts"], 1);
+    assert_eq!(delivery["last_response_status"], 500);
+    // The retry waits for the backoff.
+    assert_eq!(endpoint.received_requests().await.unwrap().len(), 1);
+
+    for _ in 0..5 {
+        skip_backoff(&test_app).await;
+        test_app.dispatch_pending_webhooks().await;
+    }
+    let delivery = deliveries(&test_app, &webhook).await.remove(0);
+    assert_eq!(delivery["status"], "failed");
+    assert_eq!(delivery["n_attempts"], 6);
+    skip_backoff(&test_app).await;
+    test_app.dispatch_pending_webhooks().await;
+    assert_eq!(endpoint.received_requests().await.unwrap().len(), 6);
+
+    let replay_path = format!(
+        "/admin/webhooks/{}/deliveries/{}/replay",
+        webhook.id,
+        delivery["id"].as_str().unwrap()
+    );
+    let response = test_app
+        .admin_request(Method::POST, &replay_path)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(202, response.status().as_u16());
+    // It is back in the queue, replaying it again is a conflict.
+    let response = test_app
+        .admin_request(Method::POST, &replay_path)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(409, response.status().as_u16());
+
+    test_app.dispatch_pending_webhooks().await;
+    let requests = endpoint.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 7);
+    // Replays carry the id of the original event.
+    assert_eq!(requests[0].body, requests[6].body);
+    let delivery = &deliveries(&test_app, &webhook).await[0];
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["last_response_status"], 200);
+}
+
+#[tokio::test]
+async fn invalid_webhooks_are_rejected_with_a_400() {
+    let test_app = spawn_app().await;
+    let test_cases = [
+        (serde_json::json!({"url": "not a url"}), "an invalid URL"),
+        (
+            serde_json::json!({"url": "ftp://example.com/hooks"}),
+            "a URL that is not http",
+        ),
+        (
+            serde_json::json!({"url": "https://example.com", "events": []}),
+            "no event types",
+        ),
+        (
+            serde_json::json!({"url": "https://example.com", "events": ["subscriber.deleted"]}),
+            "an unknown event type",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = test_app
+            .admin_request(Method::POST, "/admin/webhooks")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not reject a webhook with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn deleted_webhooks_stop_receiving_events() {
+    let test_app = spawn_app().await;
+    let endpoint = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&endpoint)
+        .await;
+    let webhook = create_webhook(&test_app, serde_json::json!({"url": endpoint.uri()})).await;
+    subscribe(&test_app).await;
+
+    let webhook_path = format!("/admin/webhooks/{}", webhook.id);
+    let response = test_app
+        .admin_request(Method::DELETE, &webhook_path)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+    test_app.dispatch_pending_webhooks().await;
+
+    let response = test_app
+        .admin_request(Method::DELETE, &webhook_path)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(404, response.status().as_u16());
+    let webhooks: Vec<serde_json::Value> = test_app
+        .admin_request(Method::GET, "/admin/webhooks")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(webhooks.is_empty());
+}
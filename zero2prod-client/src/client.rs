@@ -0,0 +1,307 @@
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+use crate::{
+    EmailStatus, Issue, NewSubscriber, PublishedIssue, QueuedEmail, SendEmail, Subscriber,
+    SubscriberEmail, SubscriberName, SubscriberPage, SubscriberQuery,
+};
+
+// Calls go to the publication behind `base_url`, either its own host
+// or the `/p/{publication}` path prefix of the service.
+pub struct Client {
+    http_client: reqwest::Client,
+    base_url: String,
+    // The API token of the publication, for everything but the public endpoints.
+    token: Option<Secret<String>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    // `message` is the detail of the problem the service answered with.
+    #[error("The service answered {status}: {message}")]
+    Response { status: StatusCode, message: String },
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+#[derive(serde::Deserialize)]
+struct Problem {
+    title: String,
+    detail: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct CreatedIssue {
+    issue_id: Uuid,
+}
+
+impl Client {
+    pub fn new(base_url: String) -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: Secret<String>) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .http_client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token.expose_secret()),
+            None => request,
+        }
+    }
+
+    // A confirmation email is sent to the new subscriber.
+    pub async fn subscribe(
+        &self,
+        name: &SubscriberName,
+        email: &SubscriberEmail,
+    ) -> Result<(), Error> {
+        let response = self
+            .request(Method::POST, "/subscriptions")
+            .form(&[("name", name.as_ref()), ("email", email.as_ref())])
+            .send()
+            .await?;
+        check(response).await?;
+        Ok(())
+    }
+
+    pub async fn confirm(&self, subscription_token: &str) -> Result<(), Error> {
+        let response = self
+            .request(Method::GET, "/subscriptions/confirm")
+            .query(&[("subscription_token", subscription_token)])
+            .send()
+            .await?;
+        check(response).await?;
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&self, unsubscribe_token: &str) -> Result<(), Error> {
+        let response = self
+            .request(Method::GET, "/subscriptions/unsubscribe")
+            .query(&[("unsubscribe_token", unsubscribe_token)])
+            .send()
+            .await?;
+        check(response).await?;
+        Ok(())
+    }
+
+    // Sends the issue to its audience right away.
+    pub async fn publish_newsletter(&self, issue: &Issue) -> Result<PublishedIssue, Error> {
+        let response = self
+            .request(Method::POST, "/newsletters")
+            .json(issue)
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    // Stores the issue as a draft, returning its id.
+    pub async fn create_issue(&self, issue: &Issue) -> Result<Uuid, Error> {
+        let response = self
+            .request(Method::POST, "/admin/issues")
+            .json(issue)
+            .send()
+            .await?;
+        let created: CreatedIssue = json(response).await?;
+        Ok(created.issue_id)
+    }
+
+    pub async fn publish_issue(&self, issue_id: Uuid) -> Result<PublishedIssue, Error> {
+        let response = self
+            .request(Method::POST, &format!("/admin/issues/{}/publish", issue_id))
+            .json(&serde_json::json!({}))
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    pub async fn list_subscribers(&self, query: &SubscriberQuery) -> Result<SubscriberPage, Error> {
+        let response = self
+            .request(Method::GET, "/api/v1/subscribers")
+            .query(query)
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    pub async fn get_subscriber(&self, subscriber_id: Uuid) -> Result<Subscriber, Error> {
+        let response = self
+            .request(
+                Method::GET,
+                &format!("/api/v1/subscribers/{}", subscriber_id),
+            )
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    pub async fn create_subscriber(&self, subscriber: &NewSubscriber) -> Result<Subscriber, Error> {
+        let response = self
+            .request(Method::POST, "/api/v1/subscribers")
+            .json(subscriber)
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    pub async fn delete_subscriber(&self, subscriber_id: Uuid) -> Result<(), Error> {
+        let response = self
+            .request(
+                Method::DELETE,
+                &format!("/api/v1/subscribers/{}", subscriber_id),
+            )
+            .send()
+            .await?;
+        check(response).await?;
+        Ok(())
+    }
+
+    pub async fn send_email(&self, email: &SendEmail) -> Result<QueuedEmail, Error> {
+        let response = self
+            .request(Method::POST, "/api/v1/emails")
+            .json(email)
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    pub async fn get_email(&self, message_id: Uuid) -> Result<EmailStatus, Error> {
+        let response = self
+            .request(Method::GET, &format!("/api/v1/emails/{}", message_id))
+            .send()
+            .await?;
+        json(response).await
+    }
+}
+
+// Errors are answered with problem details, the detail is what
+// went wrong unless it was on the side of the service.
+async fn check(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await?;
+    let message = match serde_json::from_str::<Problem>(&body) {
+        Ok(problem) => problem.detail.unwrap_or(problem.title),
+        Err(_) => body,
+    };
+    Err(Error::Response { status, message })
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    Ok(check(response).await?.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::name::en::Name;
+    use fake::Fake;
+    use wiremock::matchers::{body_string_contains, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn client(base_url: String) -> Client {
+        Client::new(base_url).with_token(Secret::new("mock_token".to_string()))
+    }
+
+    fn name() -> SubscriberName {
+        SubscriberName::parse(Name().fake()).unwrap()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn subscribe_posts_the_form() {
+        let mock_server = MockServer::start().await;
+        let email = email();
+
+        Mock::given(method("POST"))
+            .and(path("/subscriptions"))
+            .and(header("Content-Type", "application/x-www-form-urlencoded"))
+            .and(body_string_contains(email.as_ref().replace('@', "%40")))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(client(mock_server.uri()).subscribe(&name(), &email).await);
+    }
+
+    #[tokio::test]
+    async fn admin_calls_carry_the_publication_token() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/api/v1/subscribers"))
+            .and(header("Authorization", "Bearer mock_token"))
+            .and(query_param("status", "confirmed"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"data": [], "next_cursor": null})),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let page = client(mock_server.uri())
+            .list_subscribers(&SubscriberQuery {
+                status: Some("confirmed".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert!(page.data.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn the_detail_of_a_problem_is_reported() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(404).set_body_raw(
+                    serde_json::json!({
+                        "type": "about:blank",
+                        "title": "Not Found",
+                        "status": 404,
+                        "detail": "There is no subscriber with id 42."
+                    })
+                    .to_string(),
+                    "application/problem+json",
+                ),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let outcome = client(mock_server.uri())
+            .get_subscriber(Uuid::new_v4())
+            .await;
+
+        assert_err!(&outcome);
+        match outcome.unwrap_err() {
+            Error::Response { status, message } => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(message, "There is no subscriber with id 42.");
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod subscriber_email;
+mod subscriber_name;
+mod validation_error;
+
+pub use subscriber_email::SubscriberEmail;
+pub use subscriber_name::SubscriberName;
+pub use validation_error::{ValidationCode, ValidationError};
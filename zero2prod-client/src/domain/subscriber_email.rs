@@ -1,14 +1,24 @@
 use validator::validate_email;
 
-#[derive(Debug)]
+use crate::{ValidationCode, ValidationError};
+
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
+    pub fn parse(s: String) -> Result<SubscriberEmail, ValidationError> {
+        if s.trim().is_empty() {
+            Err(ValidationError::new(
+                ValidationCode::Empty,
+                "The email address can't be empty.".into(),
+            ))
+        } else if validate_email(&s) {
             Ok(Self(s))
         } else {
-            Err(format!("{} is not a valid email address.", s))
+            Err(ValidationError::new(
+                ValidationCode::InvalidFormat,
+                format!("{} is not a valid email address.", s),
+            ))
         }
     }
 }
@@ -64,4 +74,11 @@ mod tests {
         let email = "@domain.com".to_string();
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn malformed_emails_are_rejected_as_invalid_format() {
+        let email = "ursuladomain.com".to_string();
+        let error = SubscriberEmail::parse(email).unwrap_err();
+        assert_eq!(error.code, ValidationCode::InvalidFormat);
+    }
 }
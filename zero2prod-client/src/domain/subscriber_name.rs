@@ -1,23 +1,37 @@
 use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{ValidationCode, ValidationError};
 // wrapping the String type inside a tuple struct
 // makes sure that the new struct does not inherit any methods available on String
 // Also, trying to assign a String to a vairable of type SubscrtiberName will trigger a compiler error.
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 pub struct SubscriberName(String);
 
 impl SubscriberName {
-    pub fn parse(s: String) -> Result<SubscriberName, String> {
+    pub fn parse(s: String) -> Result<SubscriberName, ValidationError> {
         let is_empty_or_whitespace = s.trim().is_empty();
 
         let is_too_long = s.graphemes(true).count() > 256;
 
         let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters =
-            s.chars().any(|char| forbidden_characters.contains(&char));
+        let forbidden_character = s.chars().find(|char| forbidden_characters.contains(char));
 
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
+        if is_empty_or_whitespace {
+            Err(ValidationError::new(
+                ValidationCode::Empty,
+                "The name can't be empty.".into(),
+            ))
+        } else if is_too_long {
+            Err(ValidationError::new(
+                ValidationCode::TooLong,
+                "The name can't be longer than 256 characters.".into(),
+            ))
+        } else if let Some(character) = forbidden_character {
+            Err(ValidationError::new(
+                ValidationCode::ForbiddenCharacter,
+                format!("The name can't contain {}.", character),
+            ))
         } else {
             Ok(Self(s))
         }
@@ -66,4 +80,16 @@ mod tests {
             assert_err!(SubscriberName::parse(name));
         }
     }
+
+    #[test]
+    fn rejected_names_carry_the_code_of_what_is_wrong() {
+        let cases = [
+            ("  ".to_string(), ValidationCode::Empty),
+            ("a".repeat(257), ValidationCode::TooLong),
+            ("Ursula <3".to_string(), ValidationCode::ForbiddenCharacter),
+        ];
+        for (name, code) in cases {
+            assert_eq!(SubscriberName::parse(name).unwrap_err().code, code);
+        }
+    }
 }
@@ -0,0 +1,42 @@
+// Why a value was rejected, for callers to act on without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationCode {
+    Empty,
+    TooLong,
+    ForbiddenCharacter,
+    InvalidFormat,
+    // A field the publication doesn't ask for.
+    UnknownField,
+}
+
+impl ValidationCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationCode::Empty => "empty",
+            ValidationCode::TooLong => "too_long",
+            ValidationCode::ForbiddenCharacter => "forbidden_character",
+            ValidationCode::InvalidFormat => "invalid_format",
+            ValidationCode::UnknownField => "unknown_field",
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+#[error("{message}")]
+pub struct ValidationError {
+    pub code: ValidationCode,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(code: ValidationCode, message: String) -> Self {
+        Self { code, message }
+    }
+}
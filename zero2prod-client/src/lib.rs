@@ -0,0 +1,12 @@
+// A typed client for the zero2prod service, for the Rust services calling it.
+// The domain types are the ones the service validates with.
+mod client;
+mod domain;
+mod types;
+
+pub use client::{Client, Error};
+pub use domain::{SubscriberEmail, SubscriberName, ValidationCode, ValidationError};
+pub use types::{
+    DeadLink, EmailStatus, Issue, IssueContent, LinkReport, NewSubscriber, PublishedIssue,
+    QueuedEmail, RedirectedLink, SendEmail, Subscriber, SubscriberPage, SubscriberQuery,
+};
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{SubscriberEmail, SubscriberName};
+
+// Issues are either written in Markdown, which the service renders into
+// both parts of the email, or come with hand-written HTML and plain text.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(untagged)]
+pub enum IssueContent {
+    Markdown { markdown: String },
+    Html { html: String, text: String },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Issue {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preheader: Option<String>,
+    pub content: IssueContent,
+    // Name of a saved segment, the issue goes to every
+    // confirmed subscriber when it is left out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment: Option<String>,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+}
+
+impl Issue {
+    pub fn new(title: impl Into<String>, content: IssueContent) -> Self {
+        Self {
+            title: title.into(),
+            preheader: None,
+            content,
+            segment: None,
+            track_opens: false,
+            track_clicks: false,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PublishedIssue {
+    pub issue_id: Uuid,
+    // None when the links were not checked.
+    pub links: Option<LinkReport>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LinkReport {
+    pub checked: usize,
+    pub dead: Vec<DeadLink>,
+    pub unchecked: Vec<String>,
+    pub redirects: Vec<RedirectedLink>,
+    pub only_in_html: Vec<String>,
+    pub only_in_text: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DeadLink {
+    pub url: String,
+    pub status: Option<u16>,
+    pub error: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RedirectedLink {
+    pub url: String,
+    pub status: u16,
+    pub location: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct NewSubscriber {
+    pub email: SubscriberEmail,
+    pub name: SubscriberName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
+    pub custom_fields: HashMap<String, serde_json::Value>,
+    pub tags: Vec<String>,
+    // For audiences that already gave their consent elsewhere.
+    pub skip_confirmation: bool,
+}
+
+impl NewSubscriber {
+    pub fn new(email: SubscriberEmail, name: SubscriberName) -> Self {
+        Self {
+            email,
+            name,
+            time_zone: None,
+            custom_fields: HashMap::new(),
+            tags: Vec::new(),
+            skip_confirmation: false,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub time_zone: Option<String>,
+    pub custom_fields: serde_json::Value,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SubscriberPage {
+    pub data: Vec<Subscriber>,
+    // None on the last page.
+    pub next_cursor: Option<String>,
+}
+
+// Filters of the subscriber list, every one of them is optional.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SubscriberQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscribed_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscribed_before: Option<DateTime<Utc>>,
+    // The `next_cursor` of the previous page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SendEmail {
+    pub template: String,
+    pub to: SubscriberEmail,
+    pub variables: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct QueuedEmail {
+    pub id: Uuid,
+    // queued or suppressed.
+    pub status: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct EmailStatus {
+    pub id: Uuid,
+    pub template: String,
+    pub to: String,
+    // queued, sent, failed or suppressed.
+    pub status: String,
+    pub n_attempts: i16,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}